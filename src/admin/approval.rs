@@ -0,0 +1,309 @@
+use std::collections::{HashMap, HashSet};
+
+use nostr_sdk::{EventId, PublicKey, Timestamp};
+use serde::Deserialize;
+
+use super::{
+    commands::{AdminCommand, AdminRequest, PendingApproval},
+    roles::Role,
+};
+
+/// `[admins.approval]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApprovalConfig {
+    /// Admins that must agree on a sensitive command, the one sending it included. 1 runs
+    /// commands right away.
+    #[serde(default = "default_threshold")]
+    pub threshold: usize,
+
+    /// Seconds the other admins have to approve a staged command
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        Self {
+            threshold: default_threshold(),
+            window_secs: default_window_secs(),
+        }
+    }
+}
+
+fn default_threshold() -> usize {
+    1
+}
+
+fn default_window_secs() -> u64 {
+    600
+}
+
+/// Enum representing why an approval was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApprovalError {
+    /// The id is not a valid event id.
+    InvalidId(String),
+
+    /// No command with this id is waiting for approval, or its window has passed.
+    UnknownCommand(String),
+
+    /// The admin already approved the command, or sent it.
+    AlreadyApproved(String),
+
+    /// The admin's role does not allow the command.
+    NotPermitted(Role),
+}
+
+impl std::fmt::Display for ApprovalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidId(s) => write!(f, "ApprovalError::InvalidId: {}", s),
+            Self::UnknownCommand(s) => write!(f, "ApprovalError::UnknownCommand: {}", s),
+            Self::AlreadyApproved(s) => write!(f, "ApprovalError::AlreadyApproved: {}", s),
+            Self::NotPermitted(role) => write!(
+                f,
+                "ApprovalError::NotPermitted: command not permitted for the {} role",
+                role
+            ),
+        }
+    }
+}
+
+/// Outcome of an accepted approval.
+#[derive(Debug)]
+pub enum Approval {
+    /// More approvals are needed
+    Pending(PendingApproval),
+    /// The threshold is reached, the request can be run
+    Approved(AdminRequest, PendingApproval),
+}
+
+struct StagedCommand {
+    request: AdminRequest,
+    approvals: HashSet<PublicKey>,
+    expires_at: Timestamp,
+}
+
+/// Sensitive commands waiting for enough admins to approve them.
+///
+/// Staged commands live in memory: after a restart they have to be sent again.
+pub struct PendingApprovals {
+    config: ApprovalConfig,
+    staged: HashMap<EventId, StagedCommand>,
+}
+
+impl Default for PendingApprovals {
+    fn default() -> Self {
+        Self::new(ApprovalConfig::default())
+    }
+}
+
+impl PendingApprovals {
+    pub fn new(config: ApprovalConfig) -> Self {
+        Self {
+            config,
+            staged: HashMap::new(),
+        }
+    }
+
+    /// Whether `request` has to be staged rather than run.
+    pub fn needs_approval(&self, request: &AdminRequest) -> bool {
+        self.config.threshold > 1 && request.command.requires_approval()
+    }
+
+    /// Stages `request`, sent in the event `command_id`, with the approval of its sender.
+    pub fn stage(
+        &mut self,
+        command_id: EventId,
+        request: AdminRequest,
+        now: Timestamp,
+    ) -> PendingApproval {
+        self.expire(now);
+        let staged = StagedCommand {
+            approvals: HashSet::from([request.pubkey]),
+            request,
+            expires_at: now + self.config.window_secs,
+        };
+        let pending = self.report(&command_id, &staged);
+        self.staged.insert(command_id, staged);
+        pending
+    }
+
+    /// Records the approval of `approver`, whose role is `role`, for the command `command_id`.
+    pub fn approve(
+        &mut self,
+        command_id: &str,
+        approver: PublicKey,
+        role: Role,
+        now: Timestamp,
+    ) -> Result<Approval, ApprovalError> {
+        self.expire(now);
+        let id = EventId::from_hex(command_id)
+            .map_err(|_| ApprovalError::InvalidId(command_id.to_string()))?;
+        let staged = self
+            .staged
+            .get_mut(&id)
+            .ok_or_else(|| ApprovalError::UnknownCommand(command_id.to_string()))?;
+        if !role.allows(&staged.request.command) {
+            return Err(ApprovalError::NotPermitted(role));
+        }
+        if !staged.approvals.insert(approver) {
+            return Err(ApprovalError::AlreadyApproved(command_id.to_string()));
+        }
+
+        let staged = &self.staged[&id];
+        let pending = self.report(&id, staged);
+        if staged.approvals.len() < self.config.threshold {
+            return Ok(Approval::Pending(pending));
+        }
+        let staged = self.staged.remove(&id).expect("approved command is staged");
+        Ok(Approval::Approved(staged.request, pending))
+    }
+
+    fn report(&self, command_id: &EventId, staged: &StagedCommand) -> PendingApproval {
+        PendingApproval {
+            command_id: command_id.to_hex(),
+            command: staged.request.command.clone(),
+            approvals: staged.approvals.len(),
+            required: self.config.threshold,
+            expires_at: staged.expires_at.as_u64(),
+        }
+    }
+
+    /// Applies a change of `admin`'s role, `None` once they were removed: the commands they
+    /// staged and the approvals they gave that their role no longer allows are dropped.
+    pub fn change_role(&mut self, admin: &PublicKey, role: Option<Role>) {
+        let allowed = |command: &AdminCommand| role.is_some_and(|role| role.allows(command));
+        self.staged.retain(|_, staged| {
+            staged.request.pubkey != *admin || allowed(&staged.request.command)
+        });
+        for staged in self.staged.values_mut() {
+            if !allowed(&staged.request.command) {
+                staged.approvals.remove(admin);
+            }
+        }
+    }
+
+    /// Drops the commands whose window has passed.
+    fn expire(&mut self, now: Timestamp) {
+        self.staged.retain(|_, staged| staged.expires_at >= now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::Keys;
+
+    use super::*;
+    use crate::customer::nostr::DmProtocol;
+
+    fn request(pubkey: PublicKey, command: AdminCommand) -> AdminRequest {
+        AdminRequest {
+            pubkey,
+            request_id: "42".to_string(),
+            protocol: DmProtocol::PrivateMessage,
+            command,
+        }
+    }
+
+    #[test]
+    fn test_command_runs_once_threshold_is_reached() {
+        let mut approvals = PendingApprovals::new(ApprovalConfig {
+            threshold: 3,
+            window_secs: 60,
+        });
+        let [owner, second, third] = [(); 3].map(|_| Keys::generate().public_key());
+        let now = Timestamp::from(1_700_000_000);
+        let id = EventId::all_zeros();
+
+        let shutdown = request(owner, AdminCommand::Shutdown);
+        assert!(approvals.needs_approval(&shutdown));
+        assert!(!approvals.needs_approval(&request(owner, AdminCommand::Status)));
+        let pending = approvals.stage(id, shutdown, now);
+        assert_eq!((pending.approvals, pending.required), (1, 3));
+
+        assert_eq!(
+            approvals
+                .approve(&id.to_hex(), owner, Role::Owner, now)
+                .unwrap_err(),
+            ApprovalError::AlreadyApproved(id.to_hex())
+        );
+        assert_eq!(
+            approvals
+                .approve(&id.to_hex(), second, Role::Operator, now)
+                .unwrap_err(),
+            ApprovalError::NotPermitted(Role::Operator)
+        );
+        assert!(matches!(
+            approvals.approve(&id.to_hex(), second, Role::Owner, now),
+            Ok(Approval::Pending(PendingApproval { approvals: 2, .. }))
+        ));
+        let Ok(Approval::Approved(approved, _)) =
+            approvals.approve(&id.to_hex(), third, Role::Owner, now)
+        else {
+            panic!("the third approval should reach the threshold");
+        };
+        assert_eq!(approved.pubkey, owner);
+        assert!(matches!(approved.command, AdminCommand::Shutdown));
+
+        // The command is no longer staged once approved
+        assert!(matches!(
+            approvals.approve(&id.to_hex(), third, Role::Owner, now),
+            Err(ApprovalError::UnknownCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_staged_command_expires() {
+        let mut approvals = PendingApprovals::new(ApprovalConfig {
+            threshold: 2,
+            window_secs: 60,
+        });
+        let [owner, second] = [(); 2].map(|_| Keys::generate().public_key());
+        let now = Timestamp::from(1_700_000_000);
+        let id = EventId::all_zeros();
+
+        approvals.stage(id, request(owner, AdminCommand::RemoveItem(7)), now);
+        assert!(matches!(
+            approvals.approve(&id.to_hex(), second, Role::Owner, now + 61),
+            Err(ApprovalError::UnknownCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_removed_admins_no_longer_count() {
+        let mut approvals = PendingApprovals::new(ApprovalConfig {
+            threshold: 3,
+            window_secs: 60,
+        });
+        let [owner, second, third] = [(); 3].map(|_| Keys::generate().public_key());
+        let now = Timestamp::from(1_700_000_000);
+        let [sent, approved] = [EventId::all_zeros(), EventId::from_byte_array([1; 32])];
+
+        approvals.stage(sent, request(second, AdminCommand::Shutdown), now);
+        approvals.stage(approved, request(owner, AdminCommand::Shutdown), now);
+        approvals
+            .approve(&approved.to_hex(), second, Role::Owner, now)
+            .unwrap();
+
+        // Demoted to a role that may not shut down: the command they sent is dropped and their
+        // approval no longer counts
+        approvals.change_role(&second, Some(Role::Operator));
+        assert!(matches!(
+            approvals.approve(&sent.to_hex(), third, Role::Owner, now),
+            Err(ApprovalError::UnknownCommand(_))
+        ));
+        assert!(matches!(
+            approvals.approve(&approved.to_hex(), third, Role::Owner, now),
+            Ok(Approval::Pending(PendingApproval { approvals: 2, .. }))
+        ));
+
+        // Removed: their approval is dropped too
+        approvals.change_role(&third, None);
+        let fourth = Keys::generate().public_key();
+        assert!(matches!(
+            approvals.approve(&approved.to_hex(), fourth, Role::Owner, now),
+            Ok(Approval::Pending(PendingApproval { approvals: 2, .. }))
+        ));
+    }
+}
@@ -1,21 +1,48 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
 use tokio::sync::mpsc;
 
-use super::{commands::AdminCommand, helper, AdminError, AdminHandler};
+use super::{
+    approval::{ApprovalConfig, PendingApprovals},
+    commands::AdminRequest,
+    helper,
+    limits::{LimitsConfig, RateLimiter},
+    replay::ReplayGuard,
+    roles::Role,
+    store::AdminStore,
+    AdminError, AdminHandler,
+};
 
 pub struct AdminHandlerBuilder {
     /// The Nostr client to be used with the handler
     client: Option<nostr_sdk::Client>,
 
-    /// Set of admin public keys that will be authorized to issue commands
-    admin_pubkeys: HashSet<nostr_sdk::PublicKey>,
+    /// Admin public keys that will be authorized to issue commands, with their role
+    admin_pubkeys: HashMap<nostr_sdk::PublicKey, Role>,
 
     /// nostr private key
     key: Option<nostr_sdk::SecretKey>,
 
     /// admin commands sender
-    admin_commands_sender: Option<mpsc::Sender<AdminCommand>>,
+    admin_commands_sender: Option<mpsc::Sender<AdminRequest>>,
+
+    /// Replay protection, in memory with the default window unless set
+    replay: Option<ReplayGuard>,
+
+    /// Accept kind 4 DMs too
+    legacy_dm: bool,
+
+    /// Approval of sensitive commands, none unless set
+    approval: Option<ApprovalConfig>,
+
+    /// Where admin list changes are saved, in memory unless set
+    admin_store: AdminStore,
+
+    /// Rate limits and lockout, the defaults unless set
+    limits: Option<LimitsConfig>,
 }
 
 impl Default for AdminHandlerBuilder {
@@ -35,9 +62,14 @@ impl AdminHandlerBuilder {
     pub fn new() -> Self {
         Self {
             client: None,
-            admin_pubkeys: HashSet::new(),
+            admin_pubkeys: HashMap::new(),
             key: None,
             admin_commands_sender: None,
+            replay: None,
+            legacy_dm: false,
+            approval: None,
+            admin_store: AdminStore::default(),
+            limits: None,
         }
     }
 
@@ -56,7 +88,7 @@ impl AdminHandlerBuilder {
         self
     }
 
-    /// Adds an admin public key (either hex or Bech32) to the builder.
+    /// Adds an owner public key (either hex or Bech32) to the builder.
     ///
     /// This function attempts to parse the provided string as either a valid 64-character hex string
     /// or a Bech32-encoded Nostr public key. If the public key is valid, it is added to the set of
@@ -70,11 +102,19 @@ impl AdminHandlerBuilder {
     /// A result containing either:
     /// - `Ok(self)` if the public key was successfully added.
     /// - `Err(AdminError)` if the public key format is invalid.
-    pub fn add_admin_pubkey<S: Into<String>>(mut self, input: S) -> Result<Self, AdminError> {
+    pub fn add_admin_pubkey<S: Into<String>>(self, input: S) -> Result<Self, AdminError> {
+        self.add_admin(input, Role::Owner)
+    }
+
+    /// Adds an admin public key (either hex or Bech32) only allowed the commands of `role`.
+    ///
+    /// # Returns
+    /// - `Err(AdminError)` if the public key format is invalid.
+    pub fn add_admin<S: Into<String>>(mut self, input: S, role: Role) -> Result<Self, AdminError> {
         let raw = input.into();
 
         if let Some(pk) = helper::parse_pubkey(&raw) {
-            self.admin_pubkeys.insert(pk);
+            self.admin_pubkeys.insert(pk, role);
         } else {
             return Err(AdminError::InvalidNostrPubKey(format!(
                 "⚠️ Invalid pubkey format: {}",
@@ -90,11 +130,41 @@ impl AdminHandlerBuilder {
         self
     }
 
-    pub fn sender_admin_commands(mut self, sender: mpsc::Sender<AdminCommand>) -> Self {
+    pub fn sender_admin_commands(mut self, sender: mpsc::Sender<AdminRequest>) -> Self {
         self.admin_commands_sender = Some(sender);
         self
     }
 
+    /// Sets the guard rejecting replayed, stale and expired commands.
+    pub fn replay_guard(mut self, replay: ReplayGuard) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    /// Also accepts commands in NIP-44 encrypted kind 4 DMs, next to NIP-17 private messages.
+    pub fn legacy_dm(mut self, legacy_dm: bool) -> Self {
+        self.legacy_dm = legacy_dm;
+        self
+    }
+
+    /// Makes sensitive commands wait for the approval of `approval.threshold` admins.
+    pub fn approval(mut self, approval: ApprovalConfig) -> Self {
+        self.approval = Some(approval);
+        self
+    }
+
+    /// Saves the admin list to `admin_store` when admins are added or removed with commands.
+    pub fn admin_store(mut self, admin_store: AdminStore) -> Self {
+        self.admin_store = admin_store;
+        self
+    }
+
+    /// Sets the rate limits and lockout applied to each admin.
+    pub fn limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
     /// Builds the `AdminHandler` struct, ensuring all required fields are provided and valid.
     ///
     /// # Returns
@@ -133,9 +203,15 @@ impl AdminHandlerBuilder {
         // If validation passed, return the AdminHandler
         Ok(AdminHandler {
             client,
-            admin_pubkeys: self.admin_pubkeys,
+            admin_pubkeys: Arc::new(Mutex::new(self.admin_pubkeys)),
+            admin_store: self.admin_store,
+            limiter: Mutex::new(self.limits.map(RateLimiter::new).unwrap_or_default()),
             key,
             send_admin_commands,
+            replay: Mutex::new(self.replay.unwrap_or_default()),
+            legacy_dm: self.legacy_dm,
+            approvals: Mutex::new(self.approval.map(PendingApprovals::new).unwrap_or_default()),
+            protocols: Mutex::new(HashMap::new()),
         })
     }
 }
@@ -207,6 +283,6 @@ mod tests {
         let result = builder.build();
         assert!(result.is_ok());
         let handler = result.unwrap();
-        assert_eq!(handler.admin_pubkeys.len(), 1);
+        assert_eq!(handler.admin_pubkeys.lock().unwrap().len(), 1);
     }
 }
@@ -1,5 +1,10 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
+use super::roles::Role;
+use crate::{customer::nostr::DmProtocol, vm::vending_machine::Item};
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AddItemRequest {
     pub id: u64,
@@ -14,6 +19,27 @@ pub struct ChangePriceRequest {
     pub price: u64,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LoadFloatRequest {
+    pub denomination: u64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AddAdminRequest {
+    /// Hex or `npub1...`
+    pub pubkey: String,
+    /// Required, so that leaving it out never grants more than intended
+    pub role: Role,
+}
+
+/// An admin and their role, as listed by `ListAdmins` and saved in `admins_path`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AdminEntry {
+    pub pubkey: String,
+    pub role: Role,
+}
+
 /// AdminCommand represents a command that can be sent by the admin via Nostr.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type", content = "data")]
@@ -30,8 +56,246 @@ pub enum AdminCommand {
     RemoveItem(u64),
     /// Change price
     ChangePrice(ChangePriceRequest),
+    /// Add coins to the change float
+    LoadFloat(LoadFloatRequest),
+    /// Take every coin out of the cash box
+    EmptyFloat,
+    /// Put the slot of an item back in service after a dispense fault
+    ClearFault(u64),
     /// Shutdown
     Shutdown,
     /// End
     End,
+    /// Approve the staged command with this event id (hex)
+    Approve(String),
+    /// Authorize a new admin, or change the role of one
+    AddAdmin(AddAdminRequest),
+    /// Revoke an admin (hex or `npub1...`)
+    RemoveAdmin(String),
+    /// List the admins and their roles
+    ListAdmins,
+    /// Inventory and float commands run as one: either they all apply or none does, and the
+    /// state is published once
+    Batch(Vec<AdminCommand>),
+}
+
+impl AdminCommand {
+    /// Whether the command waits for the approval of other admins before it is run.
+    pub fn requires_approval(&self) -> bool {
+        match self {
+            Self::Batch(commands) => commands.iter().any(Self::requires_approval),
+            _ => matches!(
+                self,
+                Self::ChangePrice(_)
+                    | Self::RemoveItem(_)
+                    | Self::Shutdown
+                    | Self::AddAdmin(_)
+                    | Self::RemoveAdmin(_)
+            ),
+        }
+    }
+
+    /// Whether the command may be part of a `Batch`.
+    pub fn batchable(&self) -> bool {
+        matches!(
+            self,
+            Self::AddItem(_)
+                | Self::RemoveItem(_)
+                | Self::ChangePrice(_)
+                | Self::LoadFloat(_)
+                | Self::EmptyFloat
+                | Self::ClearFault(_)
+        )
+    }
+}
+
+/// What an admin sends in a DM: a command and the id the reply will carry.
+///
+/// `{"id":"42","type":"RemoveItem","data":7}`. Without an `id` the reply carries the id of the DM.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminMessage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub command: AdminCommand,
+}
+
+/// An `AdminCommand` together with the admin that sent it.
+#[derive(Debug, Clone)]
+pub struct AdminRequest {
+    /// Public key of the admin that issued the command
+    pub pubkey: nostr_sdk::PublicKey,
+    /// Id echoed in the reply to the admin
+    pub request_id: String,
+    /// How the command was sent, the reply goes back the same way
+    pub protocol: DmProtocol,
+    pub command: AdminCommand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminResponseStatus {
+    Ok,
+    Error,
+    /// Staged until enough admins approve it
+    Pending,
+}
+
+/// Connection state of one of the machine's relays.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RelayReport {
+    pub url: String,
+    pub connected: bool,
+}
+
+/// Answer to `AdminCommand::Status`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StatusReport {
+    /// Name of the current state, e.g. `ListeningState`
+    pub state: String,
+    pub under_admin: bool,
+    pub items: Vec<Item>,
+    /// Seconds since the machine started
+    pub uptime_secs: u64,
+    /// Seconds since the last customer or admin activity, if any
+    pub last_activity_secs: Option<u64>,
+    pub relays: Vec<RelayReport>,
+    /// Version of the machine software
+    pub version: String,
+}
+
+/// A command waiting for the approval of other admins.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PendingApproval {
+    /// Event id (hex) other admins approve the command with
+    pub command_id: String,
+    pub command: AdminCommand,
+    /// Approvals so far, the admin that sent the command included
+    pub approvals: usize,
+    pub required: usize,
+    /// Unix time after which the command is dropped
+    pub expires_at: u64,
+}
+
+/// What a successful command produced.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", content = "data")]
+pub enum AdminCommandResult {
+    /// The command was carried out, there is nothing to report
+    Done,
+    /// Answer to `Status`
+    Status(StatusReport),
+    /// Coins taken out by `EmptyFloat`, by denomination
+    FloatEmptied(BTreeMap<u64, u64>),
+    /// Where a staged command stands, for `pending` replies and approvals
+    Pending(PendingApproval),
+    /// Answer to `ListAdmins`, sorted by pubkey
+    Admins(Vec<AdminEntry>),
+    /// What each command of a `Batch` produced, in order
+    Batch(Vec<AdminCommandResult>),
+}
+
+/// Reply DM sent to the admin for every request.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminResponse {
+    /// Id of the request answered
+    pub id: String,
+    pub status: AdminResponseStatus,
+    /// What the command produced, for `ok` replies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<AdminCommandResult>,
+    /// What went wrong, for `error` replies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AdminResponse {
+    pub fn ok(id: &str, result: AdminCommandResult) -> Self {
+        Self {
+            id: id.to_string(),
+            status: AdminResponseStatus::Ok,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn pending(id: &str, pending: PendingApproval) -> Self {
+        Self {
+            id: id.to_string(),
+            status: AdminResponseStatus::Pending,
+            result: Some(AdminCommandResult::Pending(pending)),
+            error: None,
+        }
+    }
+
+    pub fn error(id: &str, error: impl std::fmt::Display) -> Self {
+        Self {
+            id: id.to_string(),
+            status: AdminResponseStatus::Error,
+            result: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_message_format() {
+        let message: AdminMessage =
+            serde_json::from_str(r#"{"id":"42","type":"RemoveItem","data":7}"#).unwrap();
+        assert_eq!(message.id.as_deref(), Some("42"));
+        assert!(matches!(message.command, AdminCommand::RemoveItem(7)));
+
+        // Commands sent without an id are still understood
+        let message: AdminMessage = serde_json::from_str(r#"{"type":"Status"}"#).unwrap();
+        assert!(message.id.is_none());
+        assert!(matches!(message.command, AdminCommand::Status));
+
+        assert_eq!(
+            serde_json::to_string(&AdminResponse::error("42", "no such item")).unwrap(),
+            r#"{"id":"42","status":"error","error":"no such item"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&AdminResponse::ok("42", AdminCommandResult::Done)).unwrap(),
+            r#"{"id":"42","status":"ok","result":{"type":"Done"}}"#
+        );
+    }
+
+    #[test]
+    fn test_batch_format() {
+        let message: AdminMessage = serde_json::from_str(
+            r#"{"type":"Batch","data":[{"type":"ClearFault","data":3},{"type":"RemoveItem","data":7}]}"#,
+        )
+        .unwrap();
+        let AdminCommand::Batch(commands) = &message.command else {
+            panic!("expected a batch");
+        };
+        assert_eq!(commands.len(), 2);
+        assert!(commands.iter().all(AdminCommand::batchable));
+        // RemoveItem needs approval, so does the batch
+        assert!(message.command.requires_approval());
+        assert!(!message.command.batchable());
+        assert!(!AdminCommand::Batch(vec![AdminCommand::ClearFault(3)]).requires_approval());
+    }
+
+    #[test]
+    fn test_add_admin_needs_a_role() {
+        let message: AdminMessage = serde_json::from_str(
+            r#"{"type":"AddAdmin","data":{"pubkey":"npub1admin","role":"auditor"}}"#,
+        )
+        .unwrap();
+        let AdminCommand::AddAdmin(request) = message.command else {
+            panic!("expected AddAdmin");
+        };
+        assert_eq!(request.role, Role::Auditor);
+
+        // Leaving the role out must not default to the owner role
+        assert!(serde_json::from_str::<AdminMessage>(
+            r#"{"type":"AddAdmin","data":{"pubkey":"npub1admin"}}"#
+        )
+        .is_err());
+    }
 }
@@ -16,10 +16,8 @@ pub fn parse_pubkey(input: &str) -> Option<nostr_sdk::PublicKey> {
         Some(pk)
     }
     // Then try 64-char hex
-    else if let Ok(pk) = PublicKey::from_hex(input) {
-        Some(pk)
-    } else {
-        None
+    else {
+        PublicKey::from_hex(input).ok()
     }
 }
 
@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use nostr_sdk::PublicKey;
+use serde::Deserialize;
+
+/// `[admins.limits]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LimitsConfig {
+    /// Commands an admin may send in a row
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+
+    /// Commands per minute an admin may send once the burst is used up
+    #[serde(default = "default_per_minute")]
+    pub per_minute: u32,
+
+    /// Commands waiting for the machine. Commands arriving when the queue is full are dropped
+    /// and the admin is told.
+    #[serde(default = "default_backlog")]
+    pub backlog: usize,
+
+    /// Undecryptable or malformed commands in a row after which an admin is locked out
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+
+    /// Seconds a locked out admin is ignored
+    #[serde(default = "default_lockout_secs")]
+    pub lockout_secs: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            burst: default_burst(),
+            per_minute: default_per_minute(),
+            backlog: default_backlog(),
+            max_failures: default_max_failures(),
+            lockout_secs: default_lockout_secs(),
+        }
+    }
+}
+
+fn default_burst() -> u32 {
+    5
+}
+
+fn default_per_minute() -> u32 {
+    30
+}
+
+fn default_backlog() -> usize {
+    10
+}
+
+fn default_max_failures() -> u32 {
+    5
+}
+
+fn default_lockout_secs() -> u64 {
+    600
+}
+
+/// Why a command was not let through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limited {
+    /// The admin sent too many commands. `notify` is set for the first one dropped since the
+    /// last command let through, the admin is told once.
+    RateLimited { notify: bool },
+
+    /// The admin is locked out after too many bad commands
+    LockedOut,
+}
+
+/// Token bucket and failure count of one admin.
+struct AdminLimits {
+    tokens: f64,
+    refilled_at: Instant,
+    /// A rate limited command was already reported
+    notified: bool,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Per-admin token-bucket rate limits and lockout after repeated bad commands.
+pub struct RateLimiter {
+    config: LimitsConfig,
+    admins: HashMap<PublicKey, AdminLimits>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(LimitsConfig::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: LimitsConfig) -> Self {
+        Self {
+            config,
+            admins: HashMap::new(),
+        }
+    }
+
+    fn limits(&mut self, admin: PublicKey, now: Instant) -> &mut AdminLimits {
+        let burst = self.config.burst as f64;
+        self.admins.entry(admin).or_insert(AdminLimits {
+            tokens: burst,
+            refilled_at: now,
+            notified: false,
+            failures: 0,
+            locked_until: None,
+        })
+    }
+
+    /// Takes a token for a command from `admin` received at `now`.
+    pub fn check(&mut self, admin: PublicKey, now: Instant) -> Result<(), Limited> {
+        let burst = self.config.burst as f64;
+        let per_sec = self.config.per_minute as f64 / 60.0;
+        let limits = self.limits(admin, now);
+
+        if let Some(locked_until) = limits.locked_until {
+            if now < locked_until {
+                return Err(Limited::LockedOut);
+            }
+            limits.locked_until = None;
+            limits.failures = 0;
+        }
+
+        let elapsed = now.saturating_duration_since(limits.refilled_at);
+        limits.tokens = (limits.tokens + elapsed.as_secs_f64() * per_sec).min(burst);
+        limits.refilled_at = now;
+        if limits.tokens < 1.0 {
+            let notify = !limits.notified;
+            limits.notified = true;
+            return Err(Limited::RateLimited { notify });
+        }
+        limits.tokens -= 1.0;
+        limits.notified = false;
+        Ok(())
+    }
+
+    /// Counts an undecryptable or malformed command from `admin`. Returns whether the admin is
+    /// now locked out.
+    pub fn record_failure(&mut self, admin: PublicKey, now: Instant) -> bool {
+        let max_failures = self.config.max_failures;
+        let lockout = Duration::from_secs(self.config.lockout_secs);
+        let limits = self.limits(admin, now);
+        limits.failures += 1;
+        if limits.failures < max_failures {
+            return false;
+        }
+        limits.locked_until = Some(now + lockout);
+        true
+    }
+
+    /// A well-formed command from `admin` resets their failure count.
+    pub fn record_success(&mut self, admin: PublicKey) {
+        if let Some(limits) = self.admins.get_mut(&admin) {
+            limits.failures = 0;
+        }
+    }
+
+    /// Seconds a lockout lasts.
+    pub fn lockout_secs(&self) -> u64 {
+        self.config.lockout_secs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::Keys;
+
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(LimitsConfig {
+            burst: 2,
+            per_minute: 6,
+            backlog: 10,
+            max_failures: 3,
+            lockout_secs: 60,
+        })
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut limiter = limiter();
+        let admin = Keys::generate().public_key();
+        let other = Keys::generate().public_key();
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(admin, now), Ok(()));
+        assert_eq!(limiter.check(admin, now), Ok(()));
+        assert_eq!(
+            limiter.check(admin, now),
+            Err(Limited::RateLimited { notify: true })
+        );
+        assert_eq!(
+            limiter.check(admin, now),
+            Err(Limited::RateLimited { notify: false })
+        );
+        // Each admin has their own bucket
+        assert_eq!(limiter.check(other, now), Ok(()));
+
+        // 6 per minute: one token every 10 seconds
+        assert_eq!(limiter.check(admin, now + Duration::from_secs(10)), Ok(()));
+        assert_eq!(
+            limiter.check(admin, now + Duration::from_secs(11)),
+            Err(Limited::RateLimited { notify: true })
+        );
+    }
+
+    #[test]
+    fn test_lockout_after_repeated_failures() {
+        let mut limiter = limiter();
+        let admin = Keys::generate().public_key();
+        let now = Instant::now();
+
+        assert!(!limiter.record_failure(admin, now));
+        limiter.record_success(admin);
+        assert!(!limiter.record_failure(admin, now));
+        assert!(!limiter.record_failure(admin, now));
+        assert!(limiter.record_failure(admin, now));
+        assert_eq!(
+            limiter.check(admin, now + Duration::from_secs(59)),
+            Err(Limited::LockedOut)
+        );
+        assert_eq!(limiter.check(admin, now + Duration::from_secs(60)), Ok(()));
+    }
+}
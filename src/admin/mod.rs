@@ -1,12 +1,34 @@
+pub mod approval;
 pub mod builder;
 pub mod commands;
 mod helper;
+pub mod limits;
+pub mod replay;
+pub mod roles;
+pub mod store;
 
+use approval::{Approval, ApprovalConfig, PendingApprovals};
 use builder::AdminHandlerBuilder;
-use commands::AdminCommand;
-use nostr_sdk::Client;
-use std::collections::HashSet;
-use tokio::sync::mpsc;
+use commands::{
+    AdminCommand, AdminCommandResult, AdminEntry, AdminMessage, AdminRequest, AdminResponse,
+    PendingApproval,
+};
+use limits::{Limited, LimitsConfig, RateLimiter};
+use nostr_sdk::{Client, SubscriptionId, Timestamp};
+use replay::{ReplayConfig, ReplayGuard};
+use roles::Role;
+use serde::Deserialize;
+use store::AdminStore;
+
+use crate::{
+    customer::nostr::{send_message, DmProtocol},
+    storage::StorageError,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
 
 /// Enum representing errors related to admin handling.
 #[derive(Debug)]
@@ -34,6 +56,102 @@ pub enum AdminError {
 
     /// Relay error
     Relay(String),
+
+    /// The pubkey is not an admin
+    UnknownAdmin(String),
+
+    /// The change would leave the machine without an owner
+    LastOwner(String),
+
+    /// Reading or saving the admin list failed
+    Storage(StorageError),
+}
+
+impl std::fmt::Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownCommand(s) => write!(f, "AdminError::UnknownCommand: {}", s),
+            Self::InvalidNostrPubKey(s) => write!(f, "AdminError::InvalidNostrPubKey: {}", s),
+            Self::MissingClient(s) => write!(f, "AdminError::MissingClient: {}", s),
+            Self::MissingAdminPubKeys(s) => write!(f, "AdminError::MissingAdminPubKeys: {}", s),
+            Self::MissingPrivateKey(s) => write!(f, "AdminError::MissingPrivateKey: {}", s),
+            Self::ShutdownError(s) => write!(f, "AdminError::ShutdownError: {}", s),
+            Self::HandleNotifications(s) => write!(f, "AdminError::HandleNotifications: {}", s),
+            Self::Relay(s) => write!(f, "AdminError::Relay: {}", s),
+            Self::UnknownAdmin(s) => write!(f, "AdminError::UnknownAdmin: {}", s),
+            Self::LastOwner(s) => write!(f, "AdminError::LastOwner: {}", s),
+            Self::Storage(e) => write!(f, "AdminError::Storage: {}", e),
+        }
+    }
+}
+
+/// Id of the subscription to kind 4 DMs, replaced when the admin list changes.
+const DIRECT_MESSAGES_SUBSCRIPTION: &str = "admin_direct_messages";
+
+/// `[admins]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminConfig {
+    /// Admins allowed to send commands, hex or `npub1...`
+    pub public_keys: Vec<String>,
+
+    /// Role of each admin pubkey, those only in `public_keys` are owners
+    #[serde(default)]
+    pub roles: HashMap<String, Role>,
+
+    /// Also accept commands in NIP-44 encrypted kind 4 DMs, as sent by the web admin
+    #[serde(default)]
+    pub legacy_dm: bool,
+
+    #[serde(flatten)]
+    pub replay: ReplayConfig,
+
+    /// M-of-N approval of sensitive commands
+    #[serde(default)]
+    pub approval: ApprovalConfig,
+
+    /// Rate limits, command backlog and lockout
+    #[serde(default)]
+    pub limits: LimitsConfig,
+
+    /// Where admins added or removed with commands are saved. `None` keeps them in memory.
+    #[serde(default = "default_admins_path")]
+    pub admins_path: Option<String>,
+}
+
+fn default_admins_path() -> Option<String> {
+    Some("admins.json".to_string())
+}
+
+impl AdminConfig {
+    /// Every admin with their role: the list saved in `admins_path` if the admins were ever
+    /// changed remotely, else `public_keys` (owners) and `roles`.
+    pub fn admins(&self) -> Result<Vec<AdminEntry>, StorageError> {
+        if let Some(admins) = self.store().load()? {
+            return Ok(admins);
+        }
+        let mut admins: Vec<AdminEntry> = self
+            .public_keys
+            .iter()
+            .filter(|pubkey| !self.roles.contains_key(*pubkey))
+            .map(|pubkey| AdminEntry {
+                pubkey: pubkey.clone(),
+                role: Role::Owner,
+            })
+            .collect();
+        admins.extend(self.roles.iter().map(|(pubkey, role)| AdminEntry {
+            pubkey: pubkey.clone(),
+            role: *role,
+        }));
+        Ok(admins)
+    }
+
+    /// Store of the admin list at `admins_path`.
+    pub fn store(&self) -> AdminStore {
+        self.admins_path
+            .as_ref()
+            .map(AdminStore::new)
+            .unwrap_or_default()
+    }
 }
 
 /// Represents the handler for processing and managing admin-related Nostr events and commands.
@@ -46,33 +164,87 @@ pub struct AdminHandler {
     /// The Nostr client used for network interactions
     client: Client,
 
-    /// Set of authorized Nostr public keys for admins
-    admin_pubkeys: HashSet<nostr_sdk::PublicKey>,
+    /// Authorized Nostr public keys for admins, with the role of each
+    admin_pubkeys: AdminList,
+
+    /// Saves the admin list when it changes
+    admin_store: AdminStore,
 
     /// nostr private key
     key: nostr_sdk::SecretKey,
 
     /// command producer
-    send_admin_commands: mpsc::Sender<AdminCommand>,
+    send_admin_commands: mpsc::Sender<AdminRequest>,
+
+    /// Rejects replayed, stale and expired commands
+    replay: Mutex<ReplayGuard>,
+
+    /// Whether kind 4 DMs are accepted next to NIP-17 private messages
+    legacy_dm: bool,
+
+    /// Sensitive commands waiting for the approval of other admins
+    approvals: Mutex<PendingApprovals>,
+
+    /// Per-admin rate limits and lockout
+    limiter: Mutex<RateLimiter>,
+
+    /// How each admin last wrote to the handler
+    protocols: Mutex<HashMap<nostr_sdk::PublicKey, DmProtocol>>,
 }
 
+/// The admins and their roles, shared with the handlers that must tell admins from customers.
+pub type AdminList = Arc<Mutex<HashMap<nostr_sdk::PublicKey, Role>>>;
+
 /// Builder for the `AdminHandler` struct, allowing flexible and validated construction.
 ///
 /// The builder pattern allows incremental construction of an `AdminHandler` by first setting
 /// the `Client` and then adding admin public keys. Once the required fields are set, the handler can
 /// be constructed using the `.build()` method.
 impl AdminHandler {
+    /// The live admin list, changed by `AddAdmin` and `RemoveAdmin`.
+    pub fn admin_list(&self) -> AdminList {
+        self.admin_pubkeys.clone()
+    }
+
     /// Subscribes the handler to listen for commands from the admin.
     pub async fn subscribe(&self) {
-        let filter = nostr_sdk::Filter::new()
-            .kinds(vec![nostr_sdk::Kind::EncryptedDirectMessage])
-            .authors(self.admin_pubkeys.clone());
+        // Gift wraps carry a randomized `created_at`, only new ones are requested. Freshness is
+        // checked against the sealed message once unwrapped.
+        let gift_wraps = nostr_sdk::Filter::new()
+            .kind(nostr_sdk::Kind::GiftWrap)
+            .pubkey(nostr_sdk::Keys::new(self.key.clone()).public_key())
+            .limit(0);
+        let _ = self.client.subscribe(gift_wraps, None).await;
+
+        if self.legacy_dm {
+            self.subscribe_direct_messages().await;
+        }
+    }
 
-        let _ = self.client.subscribe(filter, None).await;
+    /// Subscribes to kind 4 DMs from the current admins, replacing the previous subscription.
+    async fn subscribe_direct_messages(&self) {
+        let since = self
+            .replay
+            .lock()
+            .unwrap()
+            .oldest_accepted(nostr_sdk::Timestamp::now());
+        let authors: Vec<nostr_sdk::PublicKey> =
+            self.admin_pubkeys.lock().unwrap().keys().copied().collect();
+        let direct_messages = nostr_sdk::Filter::new()
+            .kinds(vec![nostr_sdk::Kind::EncryptedDirectMessage])
+            .authors(authors)
+            .since(since);
+        let _ = self
+            .client
+            .subscribe_with_id(
+                SubscriptionId::new(DIRECT_MESSAGES_SUBSCRIPTION),
+                direct_messages,
+                None,
+            )
+            .await;
     }
 
     pub async fn handle_events(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let admin_pubkeys = &self.admin_pubkeys;
         let client = &self.client;
 
         client
@@ -83,29 +255,14 @@ impl AdminHandler {
                     ..
                 } = notification
                 {
-                    if admin_pubkeys.contains(&event.pubkey)
-                        && event.kind == nostr_sdk::Kind::EncryptedDirectMessage
-                    {
-                        // Attempt to decrypt using NIP-44
-                        if let Ok(decrypted_command) = nostr_sdk::nips::nip44::decrypt(
-                            &self.key,
-                            &event.pubkey,
-                            &event.content,
-                        ) {
-                            println!("🔐 Decrypted NIP-44 message: {}", decrypted_command);
-                            if let Ok(command) =
-                                serde_json::from_str::<AdminCommand>(&decrypted_command)
-                            {
-                                let _ = self.send_admin_commands.send(command.clone()).await;
-                                if let AdminCommand::Shutdown = command {
-                                    return Ok(true);
-                                }
-                            } else {
-                                eprintln!("incorrect format for command");
-                            }
-                        } else {
-                            eprintln!("error while decrypting");
+                    match event.kind {
+                        nostr_sdk::Kind::GiftWrap => {
+                            return Ok(self.handle_gift_wrap(&event).await)
+                        }
+                        nostr_sdk::Kind::EncryptedDirectMessage if self.legacy_dm => {
+                            return Ok(self.handle_direct_message(&event).await)
                         }
+                        _ => {}
                     }
                 }
                 Ok(false) // Keep listening
@@ -117,13 +274,444 @@ impl AdminHandler {
 
         Ok(())
     }
+
+    /// Handles a NIP-17 private message. Returns whether the handler should stop.
+    async fn handle_gift_wrap(&self, event: &nostr_sdk::Event) -> bool {
+        let unwrapped = match self.client.unwrap_gift_wrap(event).await {
+            Ok(unwrapped) => unwrapped,
+            Err(e) => {
+                eprintln!("Failed to unwrap gift wrap {}: {}", event.id, e);
+                return false;
+            }
+        };
+        let mut rumor = unwrapped.rumor;
+        if !self.is_admin(&unwrapped.sender) || rumor.kind != nostr_sdk::Kind::PrivateDirectMessage
+        {
+            return false;
+        }
+
+        rumor.ensure_id();
+        let Some(rumor_id) = rumor.id else {
+            return false;
+        };
+        // Both the gift wrap and the message inside may carry a NIP-40 expiration
+        let expiration = [event.tags.expiration(), rumor.tags.expiration()]
+            .into_iter()
+            .flatten()
+            .min()
+            .copied();
+        let checked = self.replay.lock().unwrap().check_command(
+            rumor_id,
+            rumor.created_at,
+            expiration,
+            nostr_sdk::Timestamp::now(),
+        );
+        if let Err(rejection) = checked {
+            eprintln!("Ignoring admin command {}: {}", rumor_id, rejection);
+            return false;
+        }
+        if !self
+            .admit(unwrapped.sender, DmProtocol::PrivateMessage, rumor_id)
+            .await
+        {
+            return false;
+        }
+
+        self.handle_message(
+            unwrapped.sender,
+            rumor_id,
+            &rumor.content,
+            DmProtocol::PrivateMessage,
+        )
+        .await
+    }
+
+    /// Handles a legacy NIP-44 encrypted kind 4 DM. Returns whether the handler should stop.
+    async fn handle_direct_message(&self, event: &nostr_sdk::Event) -> bool {
+        if !self.is_admin(&event.pubkey) {
+            return false;
+        }
+        let checked = self
+            .replay
+            .lock()
+            .unwrap()
+            .check(event, nostr_sdk::Timestamp::now());
+        if let Err(rejection) = checked {
+            eprintln!("Ignoring admin command {}: {}", event.id, rejection);
+            return false;
+        }
+        if !self
+            .admit(event.pubkey, DmProtocol::EncryptedDm, event.id)
+            .await
+        {
+            return false;
+        }
+
+        // Attempt to decrypt using NIP-44
+        match nostr_sdk::nips::nip44::decrypt(&self.key, &event.pubkey, &event.content) {
+            Ok(decrypted_command) => {
+                self.handle_message(
+                    event.pubkey,
+                    event.id,
+                    &decrypted_command,
+                    DmProtocol::EncryptedDm,
+                )
+                .await
+            }
+            Err(e) => {
+                eprintln!("error while decrypting");
+                let response = AdminResponse::error(
+                    &event.id.to_hex(),
+                    format!("error while decrypting: {}", e),
+                );
+                self.reply(event.pubkey, DmProtocol::EncryptedDm, &response)
+                    .await;
+                self.record_failure(event.pubkey, DmProtocol::EncryptedDm, event.id)
+                    .await;
+                false
+            }
+        }
+    }
+
+    /// Takes a token for a command `event_id` from `admin`. Tells the admin about the first
+    /// command dropped for going over the rate limit, commands of locked out admins are dropped
+    /// silently.
+    async fn admit(
+        &self,
+        admin: nostr_sdk::PublicKey,
+        protocol: DmProtocol,
+        event_id: nostr_sdk::EventId,
+    ) -> bool {
+        let limited = self
+            .limiter
+            .lock()
+            .unwrap()
+            .check(admin, std::time::Instant::now());
+        match limited {
+            Ok(()) => true,
+            Err(Limited::RateLimited { notify }) => {
+                eprintln!("Admin {} is rate limited, dropping {}", admin, event_id);
+                if notify {
+                    let response = AdminResponse::error(
+                        &event_id.to_hex(),
+                        "too many commands, dropped until the rate limit allows more",
+                    );
+                    self.reply(admin, protocol, &response).await;
+                }
+                false
+            }
+            Err(Limited::LockedOut) => {
+                eprintln!("Admin {} is locked out, dropping {}", admin, event_id);
+                false
+            }
+        }
+    }
+
+    /// Counts an undecryptable or malformed command `event_id` from `admin`, and tells them if
+    /// they are now locked out.
+    async fn record_failure(
+        &self,
+        admin: nostr_sdk::PublicKey,
+        protocol: DmProtocol,
+        event_id: nostr_sdk::EventId,
+    ) {
+        let (locked_out, lockout_secs) = {
+            let mut limiter = self.limiter.lock().unwrap();
+            (
+                limiter.record_failure(admin, std::time::Instant::now()),
+                limiter.lockout_secs(),
+            )
+        };
+        if locked_out {
+            eprintln!("Admin {} locked out after repeated bad commands", admin);
+            let response = AdminResponse::error(
+                &event_id.to_hex(),
+                format!(
+                    "too many bad commands, ignored for {} seconds",
+                    lockout_secs
+                ),
+            );
+            self.reply(admin, protocol, &response).await;
+        }
+    }
+
+    /// Forwards the command in `decrypted_command` to the machine, or tells the admin why it
+    /// cannot be parsed. Returns whether the handler should stop.
+    async fn handle_message(
+        &self,
+        admin: nostr_sdk::PublicKey,
+        event_id: nostr_sdk::EventId,
+        decrypted_command: &str,
+        protocol: DmProtocol,
+    ) -> bool {
+        println!("🔐 Decrypted admin message: {}", decrypted_command);
+        self.protocols.lock().unwrap().insert(admin, protocol);
+        match serde_json::from_str::<AdminMessage>(decrypted_command) {
+            Ok(message) => {
+                self.limiter.lock().unwrap().record_success(admin);
+                let request_id = message.id.unwrap_or_else(|| event_id.to_hex());
+                // The admin may have been removed since the command was received
+                let Some(role) = self.admin_pubkeys.lock().unwrap().get(&admin).copied() else {
+                    return false;
+                };
+                if !role.allows(&message.command) {
+                    eprintln!(
+                        "Admin {} ({}) may not run {:?}",
+                        admin, role, message.command
+                    );
+                    let response = AdminResponse::error(
+                        &request_id,
+                        format!("command not permitted for the {} role", role),
+                    );
+                    self.reply(admin, protocol, &response).await;
+                    return false;
+                }
+                // Refuse a batch the machine would refuse before other admins approve it
+                if let AdminCommand::Batch(commands) = &message.command {
+                    if let Some(command) = commands.iter().find(|command| !command.batchable()) {
+                        let response = AdminResponse::error(
+                            &request_id,
+                            format!("{:?} cannot be part of a batch", command),
+                        );
+                        self.reply(admin, protocol, &response).await;
+                        return false;
+                    }
+                }
+                let request = AdminRequest {
+                    pubkey: admin,
+                    request_id,
+                    protocol,
+                    command: message.command,
+                };
+                if let AdminCommand::Approve(command_id) = &request.command {
+                    return self.approve(&request, command_id, role).await;
+                }
+
+                let staged = {
+                    let mut approvals = self.approvals.lock().unwrap();
+                    approvals
+                        .needs_approval(&request)
+                        .then(|| approvals.stage(event_id, request.clone(), Timestamp::now()))
+                };
+                match staged {
+                    Some(pending) => {
+                        self.request_approvals(&request, pending).await;
+                        false
+                    }
+                    None => self.forward(request).await,
+                }
+            }
+            Err(e) => {
+                eprintln!("incorrect format for command");
+                // Answer with the id the admin chose if it can be read at all
+                let id = serde_json::from_str::<serde_json::Value>(decrypted_command)
+                    .ok()
+                    .and_then(|value| value["id"].as_str().map(str::to_string))
+                    .unwrap_or_else(|| event_id.to_hex());
+                let response =
+                    AdminResponse::error(&id, format!("incorrect format for command: {}", e));
+                self.reply(admin, protocol, &response).await;
+                self.record_failure(admin, protocol, event_id).await;
+                false
+            }
+        }
+    }
+
+    fn is_admin(&self, pubkey: &nostr_sdk::PublicKey) -> bool {
+        self.admin_pubkeys.lock().unwrap().contains_key(pubkey)
+    }
+
+    /// Runs `request`: the handler manages the admin list, everything else is passed on to the
+    /// machine. Returns whether the handler should stop.
+    async fn forward(&self, request: AdminRequest) -> bool {
+        match &request.command {
+            AdminCommand::ListAdmins => {
+                let response =
+                    AdminResponse::ok(&request.request_id, AdminCommandResult::Admins(self.list()));
+                self.reply(request.pubkey, request.protocol, &response)
+                    .await;
+                return false;
+            }
+            AdminCommand::AddAdmin(_) | AdminCommand::RemoveAdmin(_) => {
+                if let Err(e) = self.change_admins(&request.command) {
+                    eprintln!("Failed to change the admins: {}", e);
+                    let response = AdminResponse::error(&request.request_id, e);
+                    self.reply(request.pubkey, request.protocol, &response)
+                        .await;
+                    return false;
+                }
+                if self.legacy_dm {
+                    self.subscribe_direct_messages().await;
+                }
+                // The machine updates who it notifies and answers the admin
+            }
+            _ => {}
+        }
+        let shutdown = matches!(request.command, AdminCommand::Shutdown);
+        // Never wait on a busy machine: a flood of commands is dropped rather than queued
+        match self.send_admin_commands.try_send(request) {
+            Ok(()) => shutdown,
+            Err(TrySendError::Full(request)) => {
+                eprintln!(
+                    "Admin command backlog full, dropping {}",
+                    request.request_id
+                );
+                let response = AdminResponse::error(
+                    &request.request_id,
+                    "the machine is busy with earlier commands, command dropped",
+                );
+                self.reply(request.pubkey, request.protocol, &response)
+                    .await;
+                false
+            }
+            Err(TrySendError::Closed(_)) => shutdown,
+        }
+    }
+
+    /// The admins and their roles, sorted by pubkey.
+    fn list(&self) -> Vec<AdminEntry> {
+        let mut admins: Vec<AdminEntry> = self
+            .admin_pubkeys
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(pubkey, role)| AdminEntry {
+                pubkey: pubkey.to_hex(),
+                role: *role,
+            })
+            .collect();
+        admins.sort_by(|a, b| a.pubkey.cmp(&b.pubkey));
+        admins
+    }
+
+    /// Applies an `AddAdmin` or `RemoveAdmin` and saves the new list.
+    fn change_admins(&self, command: &AdminCommand) -> Result<(), AdminError> {
+        let mut admins = self.admin_pubkeys.lock().unwrap().clone();
+        let (pubkey, role) = match command {
+            AdminCommand::AddAdmin(request) => {
+                let pubkey = helper::parse_pubkey(&request.pubkey)
+                    .ok_or_else(|| AdminError::InvalidNostrPubKey(request.pubkey.clone()))?;
+                admins.insert(pubkey, request.role);
+                (pubkey, Some(request.role))
+            }
+            AdminCommand::RemoveAdmin(input) => {
+                let pubkey = helper::parse_pubkey(input)
+                    .ok_or_else(|| AdminError::InvalidNostrPubKey(input.clone()))?;
+                admins
+                    .remove(&pubkey)
+                    .ok_or_else(|| AdminError::UnknownAdmin(input.clone()))?;
+                (pubkey, None)
+            }
+            _ => return Ok(()),
+        };
+        if !admins.values().any(|role| *role == Role::Owner) {
+            return Err(AdminError::LastOwner(
+                "the machine must keep at least one owner".to_string(),
+            ));
+        }
+
+        let mut entries: Vec<AdminEntry> = admins
+            .iter()
+            .map(|(pubkey, role)| AdminEntry {
+                pubkey: pubkey.to_hex(),
+                role: *role,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.pubkey.cmp(&b.pubkey));
+        self.admin_store
+            .save(&entries)
+            .map_err(AdminError::Storage)?;
+        *self.admin_pubkeys.lock().unwrap() = admins;
+        self.approvals.lock().unwrap().change_role(&pubkey, role);
+        Ok(())
+    }
+
+    /// Tells the sender of the staged `request` it waits for approval, and asks the other admins
+    /// allowed to run it for theirs.
+    async fn request_approvals(&self, request: &AdminRequest, pending: PendingApproval) {
+        let response = AdminResponse::pending(&request.request_id, pending.clone());
+        self.reply(request.pubkey, request.protocol, &response)
+            .await;
+
+        let approvers: Vec<nostr_sdk::PublicKey> = self
+            .admin_pubkeys
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(admin, role)| **admin != request.pubkey && role.allows(&request.command))
+            .map(|(admin, _)| *admin)
+            .collect();
+        // Approvers answer with `Approve` and the id of the command, the request carries it too
+        let response = AdminResponse::pending(&pending.command_id.clone(), pending);
+        for approver in approvers {
+            // Admins that never wrote to the machine get a NIP-17 private message
+            let protocol = self
+                .protocols
+                .lock()
+                .unwrap()
+                .get(&approver)
+                .copied()
+                .unwrap_or(DmProtocol::PrivateMessage);
+            self.reply(approver, protocol, &response).await;
+        }
+    }
+
+    /// Records the approval in `request` of the staged command `command_id`, and runs that
+    /// command once approved by enough admins. Returns whether the handler should stop.
+    async fn approve(&self, request: &AdminRequest, command_id: &str, role: Role) -> bool {
+        let approval = self.approvals.lock().unwrap().approve(
+            command_id,
+            request.pubkey,
+            role,
+            Timestamp::now(),
+        );
+        match approval {
+            Ok(Approval::Pending(pending)) => {
+                let response =
+                    AdminResponse::ok(&request.request_id, AdminCommandResult::Pending(pending));
+                self.reply(request.pubkey, request.protocol, &response)
+                    .await;
+                false
+            }
+            Ok(Approval::Approved(approved, pending)) => {
+                let response =
+                    AdminResponse::ok(&request.request_id, AdminCommandResult::Pending(pending));
+                self.reply(request.pubkey, request.protocol, &response)
+                    .await;
+                // The machine answers the admin that sent the command
+                self.forward(approved).await
+            }
+            Err(e) => {
+                let response = AdminResponse::error(&request.request_id, e);
+                self.reply(request.pubkey, request.protocol, &response)
+                    .await;
+                false
+            }
+        }
+    }
+
+    /// Sends `response` to `admin` over `protocol`.
+    async fn reply(
+        &self,
+        admin: nostr_sdk::PublicKey,
+        protocol: DmProtocol,
+        response: &AdminResponse,
+    ) {
+        let Ok(content) = serde_json::to_string(response) else {
+            return;
+        };
+        if let Err(e) = send_message(&self.client, admin, protocol, &content).await {
+            eprintln!("Failed to reply to admin {}: {}", admin, e);
+        }
+    }
 }
 
 pub async fn setup_admin_handler(
     keys: nostr_sdk::Keys,
-    pubkeys: &[String],
+    config: &AdminConfig,
     admin_relays: &[&str],
-    sender: tokio::sync::mpsc::Sender<AdminCommand>,
+    sender: tokio::sync::mpsc::Sender<AdminRequest>,
+    replay: ReplayGuard,
 ) -> Result<AdminHandler, AdminError> {
     // Create client
     let nostr_client = nostr_sdk::ClientBuilder::new().signer(keys.clone()).build();
@@ -141,10 +729,15 @@ pub async fn setup_admin_handler(
     let mut admin_handler_builder = AdminHandlerBuilder::new()
         .client(nostr_client)
         .private_key(keys.secret_key().clone())
-        .sender_admin_commands(sender);
+        .sender_admin_commands(sender)
+        .replay_guard(replay)
+        .legacy_dm(config.legacy_dm)
+        .approval(config.approval.clone())
+        .admin_store(config.store())
+        .limits(config.limits.clone());
 
-    for pubkey in pubkeys {
-        admin_handler_builder = admin_handler_builder.add_admin_pubkey(pubkey)?;
+    for admin in config.admins().map_err(AdminError::Storage)? {
+        admin_handler_builder = admin_handler_builder.add_admin(admin.pubkey, admin.role)?;
     }
 
     let admin_handler = admin_handler_builder.build()?;
@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use nostr_sdk::{Event, EventId, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::StorageError;
+
+/// Default for `max_command_age_secs`.
+pub const DEFAULT_MAX_COMMAND_AGE_SECS: u64 = 300;
+
+/// `[admins]` settings protecting the machine against replayed commands.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplayConfig {
+    /// Commands created more than this many seconds before (or after) the machine clock are
+    /// rejected
+    #[serde(default = "default_max_command_age_secs")]
+    pub max_command_age_secs: u64,
+
+    /// File remembering the commands already processed, so they are not run again after a
+    /// restart
+    #[serde(default = "default_seen_events_path")]
+    pub seen_events_path: String,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            max_command_age_secs: default_max_command_age_secs(),
+            seen_events_path: default_seen_events_path(),
+        }
+    }
+}
+
+impl ReplayConfig {
+    /// Opens the seen-set at `seen_events_path`.
+    pub fn open(&self) -> Result<ReplayGuard, StorageError> {
+        ReplayGuard::open(&self.seen_events_path, self.max_command_age_secs)
+    }
+}
+
+fn default_max_command_age_secs() -> u64 {
+    DEFAULT_MAX_COMMAND_AGE_SECS
+}
+
+fn default_seen_events_path() -> String {
+    "admin_seen_events.json".to_string()
+}
+
+/// Why an admin event was not processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The event was already processed
+    Replayed,
+
+    /// `created_at` is outside the accepted window
+    Stale,
+
+    /// The NIP-40 `expiration` tag is in the past
+    Expired,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Replayed => write!(f, "already processed"),
+            Self::Stale => write!(f, "created outside the accepted time window"),
+            Self::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SeenEvent {
+    id: EventId,
+    created_at: Timestamp,
+}
+
+/// Decides whether an admin event is fresh, and remembers the ones that were.
+///
+/// Only events inside the freshness window need remembering: older ones are rejected as stale
+/// anyway, so the seen-set is pruned of them on every save.
+pub struct ReplayGuard {
+    max_age_secs: u64,
+    path: Option<PathBuf>,
+    seen: HashMap<EventId, Timestamp>,
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        Self::in_memory(DEFAULT_MAX_COMMAND_AGE_SECS)
+    }
+}
+
+impl ReplayGuard {
+    /// Guard whose seen-set is lost on restart.
+    pub fn in_memory(max_age_secs: u64) -> Self {
+        Self {
+            max_age_secs,
+            path: None,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Guard persisting its seen-set as JSON in `path`, loading it if the file exists.
+    pub fn open<P: AsRef<Path>>(path: P, max_age_secs: u64) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let seen = if path.exists() {
+            let raw = fs::read_to_string(&path).map_err(|e| StorageError::Io(e.to_string()))?;
+            serde_json::from_str::<Vec<SeenEvent>>(&raw)
+                .map_err(|e| StorageError::Serialization(e.to_string()))?
+                .into_iter()
+                .map(|event| (event.id, event.created_at))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            max_age_secs,
+            path: Some(path),
+            seen,
+        })
+    }
+
+    /// Oldest `created_at` still accepted at `now`.
+    pub fn oldest_accepted(&self, now: Timestamp) -> Timestamp {
+        now - self.max_age_secs
+    }
+
+    /// Checks `event` at time `now` and records it as processed if it is accepted.
+    pub fn check(&mut self, event: &Event, now: Timestamp) -> Result<(), Rejection> {
+        self.check_command(
+            event.id,
+            event.created_at,
+            event.tags.expiration().copied(),
+            now,
+        )
+    }
+
+    /// Checks the command `id` created at `created_at` and expiring at `expiration`, and records
+    /// it as processed if it is accepted. Used for gift-wrapped commands, whose id and creation
+    /// time are those of the sealed message rather than of the wrapping event.
+    pub fn check_command(
+        &mut self,
+        id: EventId,
+        created_at: Timestamp,
+        expiration: Option<Timestamp>,
+        now: Timestamp,
+    ) -> Result<(), Rejection> {
+        if self.seen.contains_key(&id) {
+            return Err(Rejection::Replayed);
+        }
+        if created_at < self.oldest_accepted(now) || created_at > now + self.max_age_secs {
+            return Err(Rejection::Stale);
+        }
+        if expiration.is_some_and(|expiration| expiration < now) {
+            return Err(Rejection::Expired);
+        }
+
+        self.seen.insert(id, created_at);
+        let oldest = self.oldest_accepted(now);
+        self.seen.retain(|_, created_at| *created_at >= oldest);
+        if let Err(e) = self.save() {
+            eprintln!("Failed to save the processed admin commands: {}", e);
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), StorageError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let seen: Vec<SeenEvent> = self
+            .seen
+            .iter()
+            .map(|(id, created_at)| SeenEvent {
+                id: *id,
+                created_at: *created_at,
+            })
+            .collect();
+        let raw =
+            serde_json::to_string(&seen).map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, raw).map_err(|e| StorageError::Io(e.to_string()))?;
+        fs::rename(&tmp, path).map_err(|e| StorageError::Io(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{EventBuilder, Keys, Kind, Tag};
+
+    use super::*;
+
+    fn command(created_at: Timestamp, tags: Vec<Tag>) -> Event {
+        EventBuilder::new(Kind::EncryptedDirectMessage, "command")
+            .custom_created_at(created_at)
+            .tags(tags)
+            .sign_with_keys(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_rejects_replayed_stale_and_expired_commands() {
+        let now = Timestamp::from(1_700_000_000);
+        let mut guard = ReplayGuard::in_memory(60);
+
+        let event = command(now - 10, Vec::new());
+        assert_eq!(guard.check(&event, now), Ok(()));
+        assert_eq!(guard.check(&event, now), Err(Rejection::Replayed));
+
+        assert_eq!(
+            guard.check(&command(now - 61, Vec::new()), now),
+            Err(Rejection::Stale)
+        );
+        assert_eq!(
+            guard.check(&command(now + 61, Vec::new()), now),
+            Err(Rejection::Stale)
+        );
+        assert_eq!(
+            guard.check(&command(now, vec![Tag::expiration(now - 1)]), now),
+            Err(Rejection::Expired)
+        );
+        assert_eq!(
+            guard.check(&command(now, vec![Tag::expiration(now + 30)]), now),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_seen_events_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "vm-admin-seen-{}.json",
+            Keys::generate().public_key().to_hex()
+        ));
+        let now = Timestamp::now();
+        let event = command(now, Vec::new());
+
+        let mut guard = ReplayGuard::open(&path, 60).unwrap();
+        assert_eq!(guard.check(&event, now), Ok(()));
+
+        let mut reopened = ReplayGuard::open(&path, 60).unwrap();
+        assert_eq!(reopened.check(&event, now), Err(Rejection::Replayed));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+
+use super::commands::AdminCommand;
+
+/// What an admin pubkey is trusted with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Every command
+    #[default]
+    Owner,
+    /// Restocking staff: stock, coins and faults, but not prices, the menu or the machine itself
+    Operator,
+    /// Read-only access to the machine status
+    Auditor,
+}
+
+impl Role {
+    /// Whether an admin with this role may run `command`.
+    pub fn allows(&self, command: &AdminCommand) -> bool {
+        if let AdminCommand::Batch(commands) = command {
+            // A batch needs every command in it allowed
+            return !commands.is_empty() && commands.iter().all(|command| self.allows(command));
+        }
+        match self {
+            Self::Owner => true,
+            Self::Operator => matches!(
+                command,
+                AdminCommand::RequestAdminState
+                    | AdminCommand::End
+                    | AdminCommand::Status
+                    | AdminCommand::AddItem(_)
+                    | AdminCommand::LoadFloat(_)
+                    | AdminCommand::ClearFault(_)
+            ),
+            Self::Auditor => matches!(command, AdminCommand::Status),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Owner => write!(f, "owner"),
+            Self::Operator => write!(f, "operator"),
+            Self::Auditor => write!(f, "auditor"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::commands::{AddItemRequest, ChangePriceRequest};
+
+    #[test]
+    fn test_permission_matrix() {
+        let add_item = AdminCommand::AddItem(AddItemRequest {
+            id: 1,
+            name: "Water".to_string(),
+            price: 10,
+            count: 5,
+        });
+        let change_price = AdminCommand::ChangePrice(ChangePriceRequest { id: 1, price: 20 });
+
+        for command in [&add_item, &change_price, &AdminCommand::Shutdown] {
+            assert!(Role::Owner.allows(command));
+        }
+
+        assert!(Role::Operator.allows(&add_item));
+        assert!(Role::Operator.allows(&AdminCommand::Status));
+        assert!(!Role::Operator.allows(&change_price));
+        assert!(!Role::Operator.allows(&AdminCommand::Shutdown));
+        assert!(!Role::Operator.allows(&AdminCommand::EmptyFloat));
+        assert!(!Role::Operator.allows(&AdminCommand::ListAdmins));
+        assert!(Role::Operator.allows(&AdminCommand::Batch(vec![
+            add_item.clone(),
+            AdminCommand::ClearFault(1)
+        ])));
+        assert!(!Role::Operator.allows(&AdminCommand::Batch(vec![
+            add_item.clone(),
+            change_price.clone()
+        ])));
+
+        assert!(Role::Auditor.allows(&AdminCommand::Status));
+        assert!(!Role::Auditor.allows(&add_item));
+        assert!(!Role::Auditor.allows(&AdminCommand::RequestAdminState));
+    }
+}
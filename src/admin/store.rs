@@ -0,0 +1,73 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::commands::AdminEntry;
+use crate::storage::StorageError;
+
+/// Keeps the admin list changed with `AddAdmin` and `RemoveAdmin` across restarts.
+///
+/// Once saved, the file takes precedence over the keys and roles of `config.toml`.
+#[derive(Default)]
+pub struct AdminStore {
+    /// `None` keeps changes in memory only
+    path: Option<PathBuf>,
+}
+
+impl AdminStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: Some(path.as_ref().to_path_buf()),
+        }
+    }
+
+    /// The saved admin list, `None` if it was never changed remotely.
+    pub fn load(&self) -> Result<Option<Vec<AdminEntry>>, StorageError> {
+        let Some(path) = self.path.as_ref().filter(|path| path.exists()) else {
+            return Ok(None);
+        };
+        let raw = fs::read_to_string(path).map_err(|e| StorageError::Io(e.to_string()))?;
+        serde_json::from_str(&raw)
+            .map(Some)
+            .map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+
+    pub fn save(&self, admins: &[AdminEntry]) -> Result<(), StorageError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let raw = serde_json::to_string_pretty(admins)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, raw).map_err(|e| StorageError::Io(e.to_string()))?;
+        fs::rename(&tmp, path).map_err(|e| StorageError::Io(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::roles::Role;
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let path = std::env::temp_dir().join(format!(
+            "vm-admins-{}.json",
+            nostr_sdk::Keys::generate().public_key().to_hex()
+        ));
+        let store = AdminStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+
+        let admins = vec![AdminEntry {
+            pubkey: "8c2fa6ac7b9f09d8d5ad52be317bf1f8eab428f3ffb3c15e0420be9e97f0d387".to_string(),
+            role: Role::Operator,
+        }];
+        store.save(&admins).unwrap();
+        assert_eq!(AdminStore::new(&path).load().unwrap(), Some(admins));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::journal::{JournalEntry, JournalEvent};
+
+/// Coins and bills held by the machine, keyed by denomination.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CashBox {
+    coins: BTreeMap<u64, u64>,
+}
+
+impl CashBox {
+    /// Number of coins held for every denomination.
+    pub fn coins(&self) -> &BTreeMap<u64, u64> {
+        &self.coins
+    }
+
+    /// Total value held.
+    pub fn total(&self) -> u64 {
+        self.coins.iter().map(|(value, count)| value * count).sum()
+    }
+
+    /// Adds `count` coins of `denomination`.
+    pub fn load(&mut self, denomination: u64, count: u64) {
+        if denomination == 0 || count == 0 {
+            return;
+        }
+        *self.coins.entry(denomination).or_insert(0) += count;
+    }
+
+    /// Takes `coins` out of the box. Denominations that are short are emptied.
+    pub fn withdraw(&mut self, coins: &BTreeMap<u64, u64>) {
+        for (denomination, count) in coins {
+            if let Some(held) = self.coins.get_mut(denomination) {
+                *held = held.saturating_sub(*count);
+                if *held == 0 {
+                    self.coins.remove(denomination);
+                }
+            }
+        }
+    }
+
+    /// Takes every coin out of the box and returns them.
+    pub fn empty(&mut self) -> BTreeMap<u64, u64> {
+        std::mem::take(&mut self.coins)
+    }
+
+    /// Whether `amount` can be paid out exactly with the coins held.
+    pub fn can_make_change(&self, amount: u64) -> bool {
+        self.make_change(amount).is_some()
+    }
+
+    /// Picks the fewest coins held that add up to exactly `amount`.
+    ///
+    /// Unlike a greedy pick this also finds change when larger coins run out, e.g. 6 out of
+    /// `{4: 1, 3: 2}`. Returns `None` if the coins held cannot make the amount.
+    pub fn make_change(&self, amount: u64) -> Option<BTreeMap<u64, u64>> {
+        if amount > self.total() {
+            return None;
+        }
+        let target = usize::try_from(amount).ok()?;
+        let coins: Vec<(usize, u64)> = self
+            .coins
+            .iter()
+            .filter_map(|(value, count)| Some((usize::try_from(*value).ok()?, *count)))
+            .filter(|(value, _)| *value <= target)
+            .collect();
+
+        // fewest[a]: fewest coins adding up to `a` with the denominations looked at so far.
+        // taken[i][a]: how many coins of denomination `i` that best combination uses.
+        let mut fewest: Vec<Option<u64>> = vec![None; target + 1];
+        fewest[0] = Some(0);
+        let mut taken = Vec::with_capacity(coins.len());
+        for &(value, count) in &coins {
+            let previous = fewest.clone();
+            let mut take = vec![0u64; target + 1];
+            for a in value..=target {
+                for k in 1..=count.min((a / value) as u64) {
+                    let Some(n) = previous[a - k as usize * value] else {
+                        continue;
+                    };
+                    if fewest[a].is_none_or(|best| n + k < best) {
+                        fewest[a] = Some(n + k);
+                        take[a] = k;
+                    }
+                }
+            }
+            taken.push(take);
+        }
+        fewest[target]?;
+
+        let mut change = BTreeMap::new();
+        let mut remaining = target;
+        for (i, &(value, _)) in coins.iter().enumerate().rev() {
+            let k = taken[i][remaining];
+            if k > 0 {
+                change.insert(value as u64, k);
+                remaining -= k as usize * value;
+            }
+        }
+        Some(change)
+    }
+}
+
+/// Rebuilds the cash box by applying every cash movement in `entries` in order.
+pub fn replay(entries: &[JournalEntry]) -> CashBox {
+    let mut cash_box = CashBox::default();
+    for entry in entries {
+        match &entry.event {
+            JournalEvent::CashDeposited { denomination } => cash_box.load(*denomination, 1),
+            JournalEvent::FloatLoaded {
+                denomination,
+                count,
+            } => cash_box.load(*denomination, *count),
+            JournalEvent::CashPaidOut { coins } => cash_box.withdraw(coins),
+            JournalEvent::FloatEmptied { .. } => {
+                cash_box.empty();
+            }
+            _ => {}
+        }
+    }
+    cash_box
+}
+
+/// `[cash]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CashConfig {
+    /// Coins and bills customers may insert. Used to decide when the machine can only take the
+    /// exact amount.
+    #[serde(default = "default_denominations")]
+    pub denominations: Vec<u64>,
+}
+
+impl Default for CashConfig {
+    fn default() -> Self {
+        Self {
+            denominations: default_denominations(),
+        }
+    }
+}
+
+fn default_denominations() -> Vec<u64> {
+    vec![1, 2, 5, 10, 20, 50, 100]
+}
+
+/// Whether `cash_box` cannot give change to a customer paying `price` only with coins of one of
+/// the `denominations`.
+pub fn needs_exact_change(cash_box: &CashBox, denominations: &[u64], price: u64) -> bool {
+    denominations
+        .iter()
+        .filter(|denomination| **denomination > 0)
+        .any(|denomination| {
+            let overpaid = (denomination - price % denomination) % denomination;
+            !cash_box.can_make_change(overpaid)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cash_box(coins: &[(u64, u64)]) -> CashBox {
+        let mut cash_box = CashBox::default();
+        for (denomination, count) in coins {
+            cash_box.load(*denomination, *count);
+        }
+        cash_box
+    }
+
+    #[test]
+    fn test_make_change_uses_fewest_coins() {
+        let cash_box = cash_box(&[(1, 10), (2, 5), (5, 2), (10, 1)]);
+        assert_eq!(
+            cash_box.make_change(18),
+            Some(BTreeMap::from([(1, 1), (2, 1), (5, 1), (10, 1)]))
+        );
+        assert_eq!(cash_box.make_change(0), Some(BTreeMap::new()));
+    }
+
+    #[test]
+    fn test_make_change_where_greedy_fails() {
+        let cash_box = cash_box(&[(4, 1), (3, 2)]);
+        assert_eq!(cash_box.make_change(6), Some(BTreeMap::from([(3, 2)])));
+    }
+
+    #[test]
+    fn test_make_change_respects_stock() {
+        let cash_box = cash_box(&[(5, 1), (2, 1)]);
+        assert!(cash_box.can_make_change(7));
+        assert!(!cash_box.can_make_change(4));
+        assert!(!cash_box.can_make_change(10));
+        assert!(!cash_box.can_make_change(u64::MAX));
+    }
+
+    #[test]
+    fn test_withdraw_and_empty() {
+        let mut cash_box = cash_box(&[(5, 2), (1, 3)]);
+        cash_box.withdraw(&BTreeMap::from([(5, 2), (1, 1)]));
+        assert_eq!(cash_box.coins(), &BTreeMap::from([(1, 2)]));
+        assert_eq!(cash_box.empty(), BTreeMap::from([(1, 2)]));
+        assert_eq!(cash_box.total(), 0);
+    }
+
+    #[test]
+    fn test_needs_exact_change() {
+        let denominations = [1, 5, 10];
+        assert!(needs_exact_change(&CashBox::default(), &denominations, 7));
+        // 7 paid with 5s overpays 3, with 10s overpays 3
+        assert!(!needs_exact_change(&cash_box(&[(1, 3)]), &denominations, 7));
+        assert!(!needs_exact_change(&CashBox::default(), &denominations, 10));
+    }
+
+    #[test]
+    fn test_replay_cash_box() {
+        let entries: Vec<JournalEntry> = [
+            JournalEvent::FloatLoaded {
+                denomination: 5,
+                count: 4,
+            },
+            JournalEvent::CashDeposited { denomination: 10 },
+            JournalEvent::CashPaidOut {
+                coins: BTreeMap::from([(5, 1)]),
+            },
+            JournalEvent::FloatEmptied {
+                coins: BTreeMap::from([(5, 3), (10, 1)]),
+            },
+            JournalEvent::FloatLoaded {
+                denomination: 1,
+                count: 2,
+            },
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(seq, event)| JournalEntry::new(seq as u64, None, event))
+        .collect();
+        assert_eq!(replay(&entries), cash_box(&[(1, 2)]));
+    }
+}
@@ -0,0 +1,206 @@
+pub mod nostr;
+mod scripted;
+mod socket;
+mod terminal;
+
+use std::{fmt::Display, future::Future};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+pub use nostr::{setup_nostr_customer_handler, DmProtocol, NostrCustomerHandler};
+pub use scripted::ScriptedInterface;
+pub use socket::UnixSocketInterface;
+pub use terminal::TerminalInterface;
+
+/// Default path of the Unix socket the touchscreen connects to.
+pub const DEFAULT_SOCKET_PATH: &str = "vending_machine.sock";
+
+/// Enum representing errors related to customer interfaces.
+#[derive(Debug)]
+pub enum CustomerError {
+    /// Reading from or writing to the interface failed.
+    Io(String),
+
+    /// The machine stopped listening to customer events.
+    Closed(String),
+
+    /// Talking to the relays failed.
+    Nostr(String),
+}
+
+impl Display for CustomerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(s) => write!(f, "CustomerError::Io: {}", s),
+            Self::Closed(s) => write!(f, "CustomerError::Closed: {}", s),
+            Self::Nostr(s) => write!(f, "CustomerError::Nostr: {}", s),
+        }
+    }
+}
+
+/// What a customer did at the machine.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "data")]
+pub enum CustomerEvent {
+    /// Put `quantity` units of item `id` in the cart
+    RequestItem { id: u64, quantity: u64 },
+    /// Insert a coin or bill
+    InsertMoney(u64),
+    /// Dispense the paid cart
+    DispenseItem,
+    /// Cancel and get the money back
+    Cancel,
+    /// Pay with a serialized Cashu token
+    PayCashu(String),
+    /// Show the items for sale
+    ShowMenu,
+    /// NIP-15 order from a marketplace client, requesting every line at once
+    PlaceOrder {
+        order_id: String,
+        items: Vec<OrderItem>,
+    },
+    /// Add stock (only honoured in admin mode)
+    AddItem {
+        id: u64,
+        name: String,
+        price: u64,
+        count: u64,
+    },
+}
+
+/// Line of a [`CustomerEvent::PlaceOrder`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OrderItem {
+    pub id: u64,
+    pub quantity: u64,
+}
+
+/// Who is operating the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Customer {
+    /// Someone standing at the machine
+    Local,
+    /// Someone ordering over Nostr DMs
+    Nostr(nostr_sdk::PublicKey),
+}
+
+/// A `CustomerEvent` sent in a Nostr DM.
+#[derive(Debug, Clone)]
+pub struct RemoteCustomerEvent {
+    pub sender: nostr_sdk::PublicKey,
+    /// How the customer wrote to the machine, replies use the same
+    pub protocol: DmProtocol,
+    pub event: CustomerEvent,
+}
+
+/// Source of customer events, e.g. a terminal prompt or a touchscreen.
+pub trait CustomerInterface: Send + 'static {
+    /// Forwards customer events to `events` until the interface closes.
+    fn run(
+        self,
+        events: mpsc::Sender<CustomerEvent>,
+    ) -> impl Future<Output = Result<(), CustomerError>> + Send;
+}
+
+/// Runs `interface` in the background, feeding the machine through `events`.
+pub fn spawn_interface<I: CustomerInterface>(
+    interface: I,
+    events: mpsc::Sender<CustomerEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(e) = interface.run(events).await {
+            eprintln!("Customer interface error: {}", e);
+        }
+    })
+}
+
+/// Which interface customers use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CustomerInterfaceKind {
+    /// Numbered prompts on stdin
+    #[default]
+    Terminal,
+    /// Line-delimited JSON over a Unix socket
+    Socket,
+    /// No local interface, e.g. when customers only order over Nostr
+    None,
+}
+
+/// `[customer]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CustomerConfig {
+    #[serde(default)]
+    pub interface: CustomerInterfaceKind,
+
+    /// Path of the socket for the `socket` interface
+    #[serde(default = "default_socket_path")]
+    pub socket_path: String,
+
+    /// Take orders sent to the machine in Nostr DMs
+    #[serde(default = "default_nostr")]
+    pub nostr: bool,
+}
+
+impl Default for CustomerConfig {
+    fn default() -> Self {
+        Self {
+            interface: CustomerInterfaceKind::default(),
+            socket_path: default_socket_path(),
+            nostr: default_nostr(),
+        }
+    }
+}
+
+fn default_socket_path() -> String {
+    DEFAULT_SOCKET_PATH.to_string()
+}
+
+fn default_nostr() -> bool {
+    true
+}
+
+impl CustomerConfig {
+    /// Starts the configured interface, if any.
+    pub fn spawn(&self, events: mpsc::Sender<CustomerEvent>) -> Option<JoinHandle<()>> {
+        match self.interface {
+            CustomerInterfaceKind::Terminal => Some(spawn_interface(TerminalInterface, events)),
+            CustomerInterfaceKind::Socket => Some(spawn_interface(
+                UnixSocketInterface::new(&self.socket_path),
+                events,
+            )),
+            CustomerInterfaceKind::None => None,
+        }
+    }
+}
+
+/// Sends `event` to the machine, failing once the machine stopped listening.
+async fn forward(
+    events: &mpsc::Sender<CustomerEvent>,
+    event: CustomerEvent,
+) -> Result<(), CustomerError> {
+    events
+        .send(event)
+        .await
+        .map_err(|e| CustomerError::Closed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json_format() {
+        let event: CustomerEvent =
+            serde_json::from_str(r#"{"type":"RequestItem","data":{"id":5,"quantity":2}}"#).unwrap();
+        assert_eq!(event, CustomerEvent::RequestItem { id: 5, quantity: 2 });
+
+        let event: CustomerEvent =
+            serde_json::from_str(r#"{"type":"InsertMoney","data":20}"#).unwrap();
+        assert_eq!(event, CustomerEvent::InsertMoney(20));
+
+        let event: CustomerEvent = serde_json::from_str(r#"{"type":"Cancel"}"#).unwrap();
+        assert_eq!(event, CustomerEvent::Cancel);
+    }
+}
@@ -0,0 +1,295 @@
+use std::{sync::Mutex, time::Instant};
+
+use nostr_sdk::{Client, Event, JsonUtil, Keys, Kind, PublicKey};
+use tokio::sync::mpsc;
+
+use super::{CustomerError, CustomerEvent, OrderItem, RemoteCustomerEvent};
+use crate::{
+    admin::{
+        limits::{LimitsConfig, RateLimiter},
+        AdminList,
+    },
+    market,
+};
+
+/// Reply sent to messages that are not a customer command.
+const HELP: &str = "Send `menu` to see the items, `request <id> [quantity]` to order, \
+a Cashu token to pay or `cancel`.";
+
+/// Help replies a sender gets in a row, then one a minute, so that strangers cannot make the
+/// machine sign and publish an event for every message they send.
+const HELP_BURST: u32 = 2;
+const HELP_PER_MINUTE: u32 = 1;
+
+/// How a direct message was sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmProtocol {
+    /// Kind 4 event encrypted with NIP-44
+    EncryptedDm,
+    /// Kind 4 event encrypted with NIP-04, as sent by marketplace clients
+    LegacyDm,
+    /// NIP-17 private message, gift wrapped (NIP-59)
+    PrivateMessage,
+}
+
+/// Sends `message` to `receiver` over `protocol`.
+pub async fn send_message(
+    client: &Client,
+    receiver: PublicKey,
+    protocol: DmProtocol,
+    message: &str,
+) -> Result<(), nostr_sdk::client::Error> {
+    match protocol {
+        DmProtocol::EncryptedDm => {
+            let signer = client.signer().await?;
+            let content = signer
+                .nip44_encrypt(&receiver, message)
+                .await
+                .map_err(nostr_sdk::client::Error::from)?;
+            let event_builder = nostr_sdk::EventBuilder::new(Kind::EncryptedDirectMessage, content)
+                .tag(nostr_sdk::Tag::public_key(receiver));
+            client.send_event_builder(event_builder).await?;
+        }
+        DmProtocol::LegacyDm => {
+            let signer = client.signer().await?;
+            let content = signer
+                .nip04_encrypt(&receiver, message)
+                .await
+                .map_err(nostr_sdk::client::Error::from)?;
+            let event_builder = nostr_sdk::EventBuilder::new(Kind::EncryptedDirectMessage, content)
+                .tag(nostr_sdk::Tag::public_key(receiver));
+            client.send_event_builder(event_builder).await?;
+        }
+        DmProtocol::PrivateMessage => {
+            client.send_private_msg(receiver, message, []).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Turns the text of a customer DM into a `CustomerEvent`.
+///
+/// A NIP-15 order (JSON of type 0) places an order for the products listed. A Cashu token anywhere
+/// in the message is a payment. Otherwise the first word is the command: `menu`,
+/// `request <id> [quantity]` (also `request_item`, `buy`) or `cancel`.
+pub fn parse_message(text: &str) -> Option<CustomerEvent> {
+    if let Ok(order) = nostr_sdk::nips::nip15::CustomerOrder::from_json(text) {
+        if order.r#type != 0 {
+            return None;
+        }
+        let items = order
+            .items
+            .iter()
+            .map(|item| {
+                Some(OrderItem {
+                    id: market::item_id(&item.id)?,
+                    quantity: item.quantity,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        return Some(CustomerEvent::PlaceOrder {
+            order_id: order.id,
+            items,
+        });
+    }
+
+    if let Some(token) = text
+        .split_whitespace()
+        .find(|word| word.starts_with("cashuA") || word.starts_with("cashuB"))
+    {
+        return Some(CustomerEvent::PayCashu(token.to_string()));
+    }
+
+    let mut words = text.split_whitespace();
+    match words.next()?.to_lowercase().as_str() {
+        "menu" | "items" => Some(CustomerEvent::ShowMenu),
+        "cancel" => Some(CustomerEvent::Cancel),
+        "request" | "request_item" | "buy" => {
+            let id = words.next()?.parse().ok()?;
+            let quantity = match words.next() {
+                Some(quantity) => quantity.parse().ok()?,
+                None => 1,
+            };
+            Some(CustomerEvent::RequestItem { id, quantity })
+        }
+        _ => None,
+    }
+}
+
+/// Listens for DMs to the machine from anyone but the admins and forwards customer commands.
+pub struct NostrCustomerHandler {
+    client: Client,
+    keys: Keys,
+    /// Authors whose messages are not customer commands, kept up to date by the admin handler
+    admins: AdminList,
+    events: mpsc::Sender<RemoteCustomerEvent>,
+    help_limiter: Mutex<RateLimiter>,
+}
+
+impl NostrCustomerHandler {
+    pub async fn handle_events(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.client
+            .handle_notifications(|notification| async move {
+                if let nostr_sdk::RelayPoolNotification::Event { event, .. } = notification {
+                    self.handle_event(&event).await;
+                }
+                Ok(false)
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn handle_event(&self, event: &Event) {
+        let Some((sender, protocol, text)) = self.read_message(event).await else {
+            return;
+        };
+        if self.admins.lock().unwrap().contains_key(&sender) {
+            return;
+        }
+        match parse_message(&text) {
+            Some(customer_event) => {
+                let _ = self
+                    .events
+                    .send(RemoteCustomerEvent {
+                        sender,
+                        protocol,
+                        event: customer_event,
+                    })
+                    .await;
+            }
+            None => {
+                let limited = self
+                    .help_limiter
+                    .lock()
+                    .unwrap()
+                    .check(sender, Instant::now())
+                    .is_err();
+                if limited {
+                    return;
+                }
+                if let Err(e) = send_message(&self.client, sender, protocol, HELP).await {
+                    eprintln!("Failed to reply to customer {}: {}", sender, e);
+                }
+            }
+        }
+    }
+
+    /// Decrypts a kind 4 DM (NIP-44, falling back to NIP-04) or unwraps a NIP-17 message.
+    async fn read_message(&self, event: &Event) -> Option<(PublicKey, DmProtocol, String)> {
+        match event.kind {
+            Kind::EncryptedDirectMessage => {
+                let secret_key = self.keys.secret_key();
+                if let Ok(text) =
+                    nostr_sdk::nips::nip44::decrypt(secret_key, &event.pubkey, &event.content)
+                {
+                    return Some((event.pubkey, DmProtocol::EncryptedDm, text));
+                }
+                let text =
+                    nostr_sdk::nips::nip04::decrypt(secret_key, &event.pubkey, &event.content)
+                        .ok()?;
+                Some((event.pubkey, DmProtocol::LegacyDm, text))
+            }
+            Kind::GiftWrap => {
+                let unwrapped = self.client.unwrap_gift_wrap(event).await.ok()?;
+                if unwrapped.rumor.kind != Kind::PrivateDirectMessage {
+                    return None;
+                }
+                Some((
+                    unwrapped.sender,
+                    DmProtocol::PrivateMessage,
+                    unwrapped.rumor.content,
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub async fn setup_nostr_customer_handler(
+    keys: Keys,
+    relays: &[&str],
+    admins: AdminList,
+    events: mpsc::Sender<RemoteCustomerEvent>,
+) -> Result<NostrCustomerHandler, CustomerError> {
+    let client = nostr_sdk::ClientBuilder::new().signer(keys.clone()).build();
+    for &relay in relays {
+        client
+            .add_relay(relay)
+            .await
+            .map_err(|e| CustomerError::Nostr(e.to_string()))?;
+    }
+    client.connect().await;
+
+    let direct_messages = nostr_sdk::Filter::new()
+        .kind(Kind::EncryptedDirectMessage)
+        .pubkey(keys.public_key())
+        .since(nostr_sdk::Timestamp::now());
+    // Gift wraps carry a randomized timestamp, so only ask for the ones arriving from now on
+    let gift_wraps = nostr_sdk::Filter::new()
+        .kind(Kind::GiftWrap)
+        .pubkey(keys.public_key())
+        .limit(0);
+    for filter in [direct_messages, gift_wraps] {
+        client
+            .subscribe(filter, None)
+            .await
+            .map_err(|e| CustomerError::Nostr(e.to_string()))?;
+    }
+
+    Ok(NostrCustomerHandler {
+        client,
+        keys,
+        admins,
+        events,
+        help_limiter: Mutex::new(RateLimiter::new(LimitsConfig {
+            burst: HELP_BURST,
+            per_minute: HELP_PER_MINUTE,
+            ..LimitsConfig::default()
+        })),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_message() {
+        assert_eq!(
+            parse_message("request 5"),
+            Some(CustomerEvent::RequestItem { id: 5, quantity: 1 })
+        );
+        assert_eq!(
+            parse_message("Buy 5 3"),
+            Some(CustomerEvent::RequestItem { id: 5, quantity: 3 })
+        );
+        assert_eq!(
+            parse_message("here you go cashuBo2Ft"),
+            Some(CustomerEvent::PayCashu("cashuBo2Ft".to_string()))
+        );
+        assert_eq!(parse_message("menu"), Some(CustomerEvent::ShowMenu));
+        assert_eq!(parse_message("cancel"), Some(CustomerEvent::Cancel));
+        assert_eq!(parse_message("request five"), None);
+        assert_eq!(parse_message("hello"), None);
+        assert_eq!(parse_message(""), None);
+    }
+
+    #[test]
+    fn test_parse_marketplace_order() {
+        let order = r#"{"id":"order-1","type":0,"name":null,"address":null,"message":null,
+            "contact":{"nostr":null,"phone":null,"email":null},
+            "items":[{"id":"5","quantity":2},{"id":"7","quantity":1}],"shipping_id":"none"}"#;
+        assert_eq!(
+            parse_message(order),
+            Some(CustomerEvent::PlaceOrder {
+                order_id: "order-1".to_string(),
+                items: vec![
+                    OrderItem { id: 5, quantity: 2 },
+                    OrderItem { id: 7, quantity: 1 },
+                ],
+            })
+        );
+        // Products of other stalls
+        assert_eq!(parse_message(&order.replace("\"7\"", "\"tea\"")), None);
+    }
+}
@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use super::{forward, CustomerError, CustomerEvent, CustomerInterface};
+
+/// Plays a fixed list of customer events, e.g. to drive the machine in tests or demos.
+#[derive(Debug, Clone)]
+pub struct ScriptedInterface {
+    events: Vec<CustomerEvent>,
+    delay: Duration,
+}
+
+impl ScriptedInterface {
+    pub fn new(events: Vec<CustomerEvent>) -> Self {
+        Self {
+            events,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Waits `delay` before every event.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+impl CustomerInterface for ScriptedInterface {
+    async fn run(self, events: mpsc::Sender<CustomerEvent>) -> Result<(), CustomerError> {
+        for event in self.events {
+            tokio::time::sleep(self.delay).await;
+            forward(&events, event).await?;
+        }
+        Ok(())
+    }
+}
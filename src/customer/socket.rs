@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::mpsc,
+};
+
+use super::{forward, CustomerError, CustomerEvent, CustomerInterface};
+
+/// Line-delimited JSON over a Unix socket, used by the touchscreen process.
+///
+/// Every line is one `CustomerEvent`, e.g. `{"type":"InsertMoney","data":20}`, and is answered
+/// with `{"status":"accepted"}` or `{"status":"invalid","error":"..."}`.
+#[derive(Debug, Clone)]
+pub struct UnixSocketInterface {
+    path: PathBuf,
+}
+
+impl UnixSocketInterface {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl CustomerInterface for UnixSocketInterface {
+    async fn run(self, events: mpsc::Sender<CustomerEvent>) -> Result<(), CustomerError> {
+        // A socket left behind by a previous run would make bind fail
+        if self.path.exists() {
+            std::fs::remove_file(&self.path).map_err(|e| CustomerError::Io(e.to_string()))?;
+        }
+        let listener = UnixListener::bind(&self.path)
+            .map_err(|e| CustomerError::Io(format!("{}: {}", self.path.display(), e)))?;
+
+        loop {
+            let (stream, _) = listener
+                .accept()
+                .await
+                .map_err(|e| CustomerError::Io(e.to_string()))?;
+            let events = events.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve(stream, events).await {
+                    eprintln!("Customer socket error: {}", e);
+                }
+            });
+        }
+    }
+}
+
+async fn serve(
+    stream: UnixStream,
+    events: mpsc::Sender<CustomerEvent>,
+) -> Result<(), CustomerError> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| CustomerError::Io(e.to_string()))?
+    {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<CustomerEvent>(&line) {
+            Ok(event) => {
+                forward(&events, event).await?;
+                json!({ "status": "accepted" })
+            }
+            Err(e) => json!({ "status": "invalid", "error": e.to_string() }),
+        };
+        writer
+            .write_all(format!("{}\n", reply).as_bytes())
+            .await
+            .map_err(|e| CustomerError::Io(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socket_forwards_events() {
+        let path = std::env::temp_dir().join(format!(
+            "vm-customer-{}.sock",
+            nostr_sdk::Keys::generate().public_key().to_hex()
+        ));
+        let (tx, mut rx) = mpsc::channel(10);
+        let server = tokio::spawn(UnixSocketInterface::new(&path).run(tx));
+
+        let mut stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        stream
+            .write_all(b"{\"type\":\"InsertMoney\",\"data\":20}\nnot json\n")
+            .await
+            .unwrap();
+        let mut replies = BufReader::new(stream).lines();
+        let accepted = replies.next_line().await.unwrap().unwrap();
+        let invalid = replies.next_line().await.unwrap().unwrap();
+
+        assert_eq!(accepted, r#"{"status":"accepted"}"#);
+        assert!(invalid.contains("invalid"));
+        assert_eq!(rx.recv().await, Some(CustomerEvent::InsertMoney(20)));
+
+        server.abort();
+        std::fs::remove_file(path).unwrap();
+    }
+}
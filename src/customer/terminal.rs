@@ -0,0 +1,125 @@
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, Lines, Stdin},
+    sync::mpsc,
+};
+
+use super::{forward, CustomerError, CustomerEvent, CustomerInterface};
+
+/// Numbered prompts on stdin, the way the machine has always been operated locally.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TerminalInterface;
+
+type StdinLines = Lines<BufReader<Stdin>>;
+
+impl CustomerInterface for TerminalInterface {
+    async fn run(self, events: mpsc::Sender<CustomerEvent>) -> Result<(), CustomerError> {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            println!("==============================================================");
+            println!(
+                "Codes: (1) addItem (2) requestItem (3) insertMoney (4) dispenseItem (5) cancel"
+            );
+            let Some(code) = read_number(&mut lines, "Select code: ").await? else {
+                return Ok(());
+            };
+            let event = match code {
+                1 => {
+                    let Some(id) =
+                        read_number(&mut lines, "write the id of the item (number): ").await?
+                    else {
+                        return Ok(());
+                    };
+                    let Some(name) = read_string(
+                        &mut lines,
+                        "write the name of the item (string, empty if it exists): ",
+                    )
+                    .await?
+                    else {
+                        return Ok(());
+                    };
+                    // The price of an existing item is kept, so it is only asked for new ones
+                    let price = if name.is_empty() {
+                        0
+                    } else {
+                        let Some(price) =
+                            read_number(&mut lines, "write the price of the item (number): ")
+                                .await?
+                        else {
+                            return Ok(());
+                        };
+                        price
+                    };
+                    let Some(count) = read_number(
+                        &mut lines,
+                        "write the quantity adding to the stock of that item: ",
+                    )
+                    .await?
+                    else {
+                        return Ok(());
+                    };
+                    CustomerEvent::AddItem {
+                        id,
+                        name,
+                        price,
+                        count,
+                    }
+                }
+                2 => {
+                    let Some(id) = read_number(
+                        &mut lines,
+                        "requesting item. Provide the id of the item (number): ",
+                    )
+                    .await?
+                    else {
+                        return Ok(());
+                    };
+                    let Some(quantity) =
+                        read_number(&mut lines, "how many units (number): ").await?
+                    else {
+                        return Ok(());
+                    };
+                    CustomerEvent::RequestItem { id, quantity }
+                }
+                3 => {
+                    let Some(money) =
+                        read_number(&mut lines, "insert money. Provide the amount (number): ")
+                            .await?
+                    else {
+                        return Ok(());
+                    };
+                    CustomerEvent::InsertMoney(money)
+                }
+                4 => CustomerEvent::DispenseItem,
+                5 => CustomerEvent::Cancel,
+                _ => {
+                    println!("invalid code. Try again");
+                    continue;
+                }
+            };
+            forward(&events, event).await?;
+        }
+    }
+}
+
+/// Prompts until a number is entered. Returns `None` once stdin is closed.
+async fn read_number(lines: &mut StdinLines, text: &str) -> Result<Option<u64>, CustomerError> {
+    loop {
+        let Some(line) = read_string(lines, text).await? else {
+            return Ok(None);
+        };
+        match line.parse() {
+            Ok(number) => return Ok(Some(number)),
+            Err(_) => println!("not a number: {:?}", line),
+        }
+    }
+}
+
+/// Prompts for one line. Returns `None` once stdin is closed.
+async fn read_string(lines: &mut StdinLines, text: &str) -> Result<Option<String>, CustomerError> {
+    println!("{}", text);
+    let line = lines
+        .next_line()
+        .await
+        .map_err(|e| CustomerError::Io(e.to_string()))?;
+    Ok(line.map(|line| line.trim().to_string()))
+}
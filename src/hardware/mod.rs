@@ -0,0 +1,140 @@
+mod simulator;
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vending_machine::Item;
+
+pub use simulator::{Simulator, SimulatorConfig};
+
+/// Why a unit could not be dispensed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DispenseFault {
+    /// The unit got stuck in the slot
+    Jammed,
+    /// The slot held no unit although the inventory says it should
+    SlotEmpty,
+}
+
+impl std::fmt::Display for DispenseFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Jammed => write!(f, "jammed"),
+            Self::SlotEmpty => write!(f, "slot empty"),
+        }
+    }
+}
+
+/// Releases units of an item to the customer.
+pub trait Dispenser: Send + Sync {
+    /// Tries to dispense one unit of `item`.
+    fn dispense(&mut self, item: &Item) -> Result<(), DispenseFault>;
+}
+
+/// Takes in the coins and bills customers insert.
+pub trait CoinAcceptor: Send + Sync {
+    /// Routes an inserted coin into the cash box.
+    fn accept(&mut self, denomination: u64);
+
+    /// Hands an inserted coin straight back to the customer.
+    fn reject(&mut self, denomination: u64);
+}
+
+/// Pays coins out of the cash box to the customer.
+pub trait ChangeReturner: Send + Sync {
+    /// Pays out `coins` (denomination -> count).
+    fn pay_out(&mut self, coins: &BTreeMap<u64, u64>);
+}
+
+/// Shows messages to the customer standing at the machine.
+pub trait Display: Send + Sync {
+    fn show(&mut self, message: &str);
+}
+
+/// The physical parts of the machine every `State` acts through.
+pub struct Hardware {
+    pub dispenser: Box<dyn Dispenser>,
+    pub coin_acceptor: Box<dyn CoinAcceptor>,
+    pub change_returner: Box<dyn ChangeReturner>,
+    pub display: Box<dyn Display>,
+}
+
+impl Hardware {
+    /// Hardware that only prints what it would do. It never fails.
+    pub fn console() -> Self {
+        Self {
+            dispenser: Box::new(Console),
+            coin_acceptor: Box::new(Console),
+            change_returner: Box::new(Console),
+            display: Box::new(Console),
+        }
+    }
+}
+
+/// Stand-in for every part of the machine that prints to stdout.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Console;
+
+impl Dispenser for Console {
+    fn dispense(&mut self, item: &Item) -> Result<(), DispenseFault> {
+        println!("Dispensing Item {} (id: {})", item.name, item.id);
+        Ok(())
+    }
+}
+
+impl CoinAcceptor for Console {
+    fn accept(&mut self, denomination: u64) {
+        println!("accepted coin: {} units", denomination);
+    }
+
+    fn reject(&mut self, denomination: u64) {
+        println!("returning coin: {} units", denomination);
+    }
+}
+
+impl ChangeReturner for Console {
+    fn pay_out(&mut self, coins: &BTreeMap<u64, u64>) {
+        for (denomination, count) in coins {
+            println!("returning {} x {}", count, denomination);
+        }
+    }
+}
+
+impl Display for Console {
+    fn show(&mut self, message: &str) {
+        println!("{}", message);
+    }
+}
+
+/// Which hardware backend drives the machine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HardwareBackend {
+    /// Print every physical effect to stdout
+    #[default]
+    Console,
+    /// Run the in-process simulator
+    Simulator,
+}
+
+/// `[hardware]` section of `config.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HardwareConfig {
+    #[serde(default)]
+    pub backend: HardwareBackend,
+
+    /// Used by the `simulator` backend
+    #[serde(default)]
+    pub simulator: SimulatorConfig,
+}
+
+impl HardwareConfig {
+    /// Creates the configured hardware.
+    pub fn open(&self) -> Hardware {
+        match self.backend {
+            HardwareBackend::Console => Hardware::console(),
+            HardwareBackend::Simulator => Simulator::new(self.simulator.clone()).hardware(),
+        }
+    }
+}
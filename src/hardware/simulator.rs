@@ -0,0 +1,257 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use super::{ChangeReturner, CoinAcceptor, DispenseFault, Dispenser, Display, Hardware};
+use crate::vending_machine::Item;
+
+/// `[hardware.simulator]` section of `config.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SimulatorConfig {
+    /// Time a unit takes to drop, in milliseconds
+    #[serde(default)]
+    pub dispense_latency_ms: u64,
+
+    /// Time the acceptor takes to accept or return a coin, in milliseconds
+    #[serde(default)]
+    pub coin_latency_ms: u64,
+
+    /// Time paying out change takes, in milliseconds
+    #[serde(default)]
+    pub payout_latency_ms: u64,
+
+    /// Jam on every n-th dispense
+    #[serde(default)]
+    pub jam_every: Option<u64>,
+
+    /// Items whose slot always jams
+    #[serde(default)]
+    pub jammed_slots: Vec<u64>,
+
+    /// Items whose slot is empty whatever the inventory says
+    #[serde(default)]
+    pub empty_slots: Vec<u64>,
+}
+
+#[derive(Debug, Default)]
+struct SimulatorState {
+    jammed_slots: HashSet<u64>,
+    empty_slots: HashSet<u64>,
+    dispense_attempts: u64,
+    dispensed: Vec<u64>,
+    accepted: Vec<u64>,
+    rejected: Vec<u64>,
+    paid_out: BTreeMap<u64, u64>,
+    messages: Vec<String>,
+}
+
+/// In-process machine that behaves like the real hardware, with latencies and faults taken from
+/// its config or injected while running.
+///
+/// Clones share the same simulated machine, so a test can keep one to inspect what happened.
+/// Latencies block the calling thread, like a synchronous hardware driver would.
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    config: Arc<SimulatorConfig>,
+    state: Arc<Mutex<SimulatorState>>,
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig) -> Self {
+        let state = SimulatorState {
+            jammed_slots: config.jammed_slots.iter().copied().collect(),
+            empty_slots: config.empty_slots.iter().copied().collect(),
+            ..Default::default()
+        };
+        Self {
+            config: Arc::new(config),
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Every part of the machine, backed by this simulator.
+    pub fn hardware(&self) -> Hardware {
+        Hardware {
+            dispenser: Box::new(self.clone()),
+            coin_acceptor: Box::new(self.clone()),
+            change_returner: Box::new(self.clone()),
+            display: Box::new(self.clone()),
+        }
+    }
+
+    /// Makes the slot of `item_id` jam until `unjam_slot` is called.
+    pub fn jam_slot(&self, item_id: u64) {
+        self.state().jammed_slots.insert(item_id);
+    }
+
+    pub fn unjam_slot(&self, item_id: u64) {
+        self.state().jammed_slots.remove(&item_id);
+    }
+
+    /// Makes the slot of `item_id` empty until `refill_slot` is called.
+    pub fn empty_slot(&self, item_id: u64) {
+        self.state().empty_slots.insert(item_id);
+    }
+
+    pub fn refill_slot(&self, item_id: u64) {
+        self.state().empty_slots.remove(&item_id);
+    }
+
+    /// Ids of the units dispensed so far, in order.
+    pub fn dispensed(&self) -> Vec<u64> {
+        self.state().dispensed.clone()
+    }
+
+    /// Coins routed into the cash box so far, in order.
+    pub fn accepted(&self) -> Vec<u64> {
+        self.state().accepted.clone()
+    }
+
+    /// Coins handed back to the customer at the slot, in order.
+    pub fn rejected(&self) -> Vec<u64> {
+        self.state().rejected.clone()
+    }
+
+    /// Coins paid out so far (denomination -> count).
+    pub fn paid_out(&self) -> BTreeMap<u64, u64> {
+        self.state().paid_out.clone()
+    }
+
+    /// Messages shown on the display so far, in order.
+    pub fn messages(&self) -> Vec<String> {
+        self.state().messages.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, SimulatorState> {
+        // A panic while holding the lock cannot leave the state half-updated
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Blocks for a simulated latency. On a multi-threaded Tokio runtime the worker thread is
+    /// handed over first, so the other tasks (relays, payment watchers) keep running.
+    fn wait(milliseconds: u64) {
+        if milliseconds == 0 {
+            return;
+        }
+        let sleep = || thread::sleep(Duration::from_millis(milliseconds));
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(sleep)
+            }
+            _ => sleep(),
+        }
+    }
+}
+
+impl Dispenser for Simulator {
+    fn dispense(&mut self, item: &Item) -> Result<(), DispenseFault> {
+        Self::wait(self.config.dispense_latency_ms);
+        let mut state = self.state();
+        state.dispense_attempts += 1;
+        let attempt = state.dispense_attempts;
+        if state.empty_slots.contains(&item.id) {
+            return Err(DispenseFault::SlotEmpty);
+        }
+        let jams_now = self
+            .config
+            .jam_every
+            .is_some_and(|every| attempt.is_multiple_of(every));
+        if jams_now || state.jammed_slots.contains(&item.id) {
+            return Err(DispenseFault::Jammed);
+        }
+        state.dispensed.push(item.id);
+        Ok(())
+    }
+}
+
+impl CoinAcceptor for Simulator {
+    fn accept(&mut self, denomination: u64) {
+        Self::wait(self.config.coin_latency_ms);
+        self.state().accepted.push(denomination);
+    }
+
+    fn reject(&mut self, denomination: u64) {
+        Self::wait(self.config.coin_latency_ms);
+        self.state().rejected.push(denomination);
+    }
+}
+
+impl ChangeReturner for Simulator {
+    fn pay_out(&mut self, coins: &BTreeMap<u64, u64>) {
+        Self::wait(self.config.payout_latency_ms);
+        let mut state = self.state();
+        for (denomination, count) in coins {
+            *state.paid_out.entry(*denomination).or_insert(0) += count;
+        }
+    }
+}
+
+impl Display for Simulator {
+    fn show(&mut self, message: &str) {
+        self.state().messages.push(message.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coffee() -> Item {
+        Item::new(1, "Coffee".to_string(), 20, 5)
+    }
+
+    #[test]
+    fn test_jam_every() {
+        let mut simulator = Simulator::new(SimulatorConfig {
+            jam_every: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(simulator.dispense(&coffee()), Ok(()));
+        assert_eq!(simulator.dispense(&coffee()), Err(DispenseFault::Jammed));
+        assert_eq!(simulator.dispense(&coffee()), Ok(()));
+        assert_eq!(simulator.dispensed(), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_injected_faults() {
+        let simulator = Simulator::new(SimulatorConfig {
+            empty_slots: vec![1],
+            ..Default::default()
+        });
+        let mut dispenser = simulator.hardware().dispenser;
+        assert_eq!(dispenser.dispense(&coffee()), Err(DispenseFault::SlotEmpty));
+
+        simulator.refill_slot(1);
+        simulator.jam_slot(1);
+        assert_eq!(dispenser.dispense(&coffee()), Err(DispenseFault::Jammed));
+
+        simulator.unjam_slot(1);
+        assert_eq!(dispenser.dispense(&coffee()), Ok(()));
+    }
+
+    #[test]
+    fn test_records_coins_and_messages() {
+        let simulator = Simulator::new(SimulatorConfig::default());
+        let mut hardware = simulator.hardware();
+        hardware.coin_acceptor.accept(10);
+        hardware.coin_acceptor.reject(50);
+        hardware.change_returner.pay_out(&BTreeMap::from([(2, 1)]));
+        hardware
+            .change_returner
+            .pay_out(&BTreeMap::from([(2, 2), (1, 1)]));
+        hardware.display.show("hello");
+
+        assert_eq!(simulator.accepted(), vec![10]);
+        assert_eq!(simulator.rejected(), vec![50]);
+        assert_eq!(simulator.paid_out(), BTreeMap::from([(1, 1), (2, 3)]));
+        assert_eq!(simulator.messages(), vec!["hello".to_string()]);
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use nostr_sdk::{PublicKey, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::{hardware::DispenseFault, storage::StorageError, vending_machine::Item};
+
+/// Domain events recorded by the vending machine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum JournalEvent {
+    /// A customer put `quantity` units of an item in the cart
+    ItemRequested {
+        item_id: u64,
+        price: u64,
+        #[serde(default = "one")]
+        quantity: u64,
+    },
+    /// Money was credited towards the requested item
+    MoneyInserted { amount: u64 },
+    /// A unit left the machine
+    ItemDispensed { item_id: u64, price: u64 },
+    /// The overpayment was handed back after dispensing
+    ChangeReturned { amount: u64 },
+    /// A transaction was cancelled and `refunded` units were paid back
+    Cancelled { refunded: u64 },
+    /// The machine entered admin mode
+    AdminEntered,
+    /// The machine left admin mode
+    AdminExited,
+    /// Stock was added, creating the item if it did not exist
+    ItemAdded {
+        item_id: u64,
+        name: String,
+        price: u64,
+        count: u64,
+    },
+    /// An item changed price
+    PriceChanged {
+        item_id: u64,
+        old_price: u64,
+        new_price: u64,
+    },
+    /// An item was removed from the menu
+    ItemRemoved { item_id: u64 },
+    /// A customer's coin went into the cash box
+    CashDeposited { denomination: u64 },
+    /// Coins (denomination -> count) left the cash box as change or refund
+    CashPaidOut { coins: BTreeMap<u64, u64> },
+    /// `amount` units could not be paid back and are owed to the customer
+    RefundOwed { amount: u64 },
+    /// Coins were added to the float
+    FloatLoaded { denomination: u64, count: u64 },
+    /// The cash box was emptied, `coins` were taken out
+    FloatEmptied { coins: BTreeMap<u64, u64> },
+    /// A unit could not be dispensed and its slot was taken out of service
+    DispenseFailed { item_id: u64, fault: DispenseFault },
+    /// An admin put a faulty slot back in service
+    FaultCleared { item_id: u64 },
+}
+
+fn one() -> u64 {
+    1
+}
+
+/// A single line of the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Position of the entry in the journal, starting at 0
+    pub seq: u64,
+    /// Unix timestamp (seconds) at which the event happened
+    pub timestamp: u64,
+    /// Admin whose command caused the event, if any
+    pub admin: Option<PublicKey>,
+    pub event: JournalEvent,
+}
+
+/// Append-only log of `JournalEntry`s.
+pub trait Journal: Send + Sync {
+    /// Appends `entry` at the end of the journal.
+    fn append(&self, entry: &JournalEntry) -> Result<(), StorageError>;
+
+    /// Returns every entry in the order it was appended.
+    fn entries(&self) -> Result<Vec<JournalEntry>, StorageError>;
+}
+
+impl JournalEntry {
+    pub fn new(seq: u64, admin: Option<PublicKey>, event: JournalEvent) -> Self {
+        Self {
+            seq,
+            timestamp: Timestamp::now().as_u64(),
+            admin,
+            event,
+        }
+    }
+}
+
+/// Journal stored as one JSON object per line, opened in append mode.
+pub struct FileJournal {
+    path: PathBuf,
+    file: Mutex<fs::File>,
+}
+
+impl FileJournal {
+    /// Opens (or creates) the journal at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| StorageError::Io(e.to_string()))?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl Journal for FileJournal {
+    fn append(&self, entry: &JournalEntry) -> Result<(), StorageError> {
+        let line =
+            serde_json::to_string(entry).map_err(|e| StorageError::Serialization(e.to_string()))?;
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", line).map_err(|e| StorageError::Io(e.to_string()))?;
+        file.sync_data()
+            .map_err(|e| StorageError::Io(e.to_string()))
+    }
+
+    fn entries(&self) -> Result<Vec<JournalEntry>, StorageError> {
+        let file = fs::File::open(&self.path).map_err(|e| StorageError::Io(e.to_string()))?;
+        BufReader::new(file)
+            .lines()
+            .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+            .map(|line| {
+                let line = line.map_err(|e| StorageError::Io(e.to_string()))?;
+                serde_json::from_str(&line).map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .collect()
+    }
+}
+
+/// Journal kept in memory, for tests and machines that do not need an audit trail.
+#[derive(Default)]
+pub struct MemoryJournal {
+    entries: Mutex<Vec<JournalEntry>>,
+}
+
+impl Journal for MemoryJournal {
+    fn append(&self, entry: &JournalEntry) -> Result<(), StorageError> {
+        self.entries.lock().unwrap().push(entry.clone());
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<JournalEntry>, StorageError> {
+        Ok(self.entries.lock().unwrap().clone())
+    }
+}
+
+/// `[journal]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct JournalConfig {
+    /// Path of the JSON-lines journal file
+    #[serde(default = "default_journal_path")]
+    pub path: String,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            path: default_journal_path(),
+        }
+    }
+}
+
+fn default_journal_path() -> String {
+    "journal.jsonl".to_string()
+}
+
+impl JournalConfig {
+    /// Opens the configured journal.
+    pub fn open(&self) -> Result<Box<dyn Journal>, StorageError> {
+        Ok(Box::new(FileJournal::open(&self.path)?))
+    }
+}
+
+/// Rebuilds the inventory by applying every inventory-changing event in `entries` in order.
+pub fn replay(entries: &[JournalEntry]) -> HashMap<u64, Item> {
+    let mut items: HashMap<u64, Item> = HashMap::new();
+    for entry in entries {
+        match &entry.event {
+            JournalEvent::ItemAdded {
+                item_id,
+                name,
+                price,
+                count,
+            } => {
+                items
+                    .entry(*item_id)
+                    .or_insert(Item::new(*item_id, name.clone(), *price, 0))
+                    .increment_count(*count);
+            }
+            JournalEvent::PriceChanged {
+                item_id, new_price, ..
+            } => {
+                if let Some(item) = items.get_mut(item_id) {
+                    item.price = *new_price;
+                }
+            }
+            JournalEvent::ItemRemoved { item_id } => {
+                items.remove(item_id);
+            }
+            JournalEvent::ItemDispensed { item_id, .. } => {
+                if let Some(item) = items.get_mut(item_id) {
+                    item.count = item.count.saturating_sub(1);
+                }
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
+/// Returns the slots still out of service after applying every fault event in `entries`.
+pub fn replay_faults(entries: &[JournalEntry]) -> HashMap<u64, DispenseFault> {
+    let mut faults = HashMap::new();
+    for entry in entries {
+        match &entry.event {
+            JournalEvent::DispenseFailed { item_id, fault } => {
+                faults.insert(*item_id, *fault);
+            }
+            JournalEvent::FaultCleared { item_id } => {
+                faults.remove(item_id);
+            }
+            _ => {}
+        }
+    }
+    faults
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(seq: u64, event: JournalEvent) -> JournalEntry {
+        JournalEntry::new(seq, None, event)
+    }
+
+    #[test]
+    fn test_replay_rebuilds_inventory() {
+        let entries = vec![
+            entry(
+                0,
+                JournalEvent::ItemAdded {
+                    item_id: 1,
+                    name: "Water".to_string(),
+                    price: 100,
+                    count: 5,
+                },
+            ),
+            entry(
+                1,
+                JournalEvent::ItemAdded {
+                    item_id: 2,
+                    name: "Chips".to_string(),
+                    price: 150,
+                    count: 2,
+                },
+            ),
+            entry(
+                2,
+                JournalEvent::ItemRequested {
+                    item_id: 1,
+                    price: 100,
+                    quantity: 1,
+                },
+            ),
+            entry(3, JournalEvent::MoneyInserted { amount: 100 }),
+            entry(
+                4,
+                JournalEvent::ItemDispensed {
+                    item_id: 1,
+                    price: 100,
+                },
+            ),
+            entry(
+                5,
+                JournalEvent::PriceChanged {
+                    item_id: 1,
+                    old_price: 100,
+                    new_price: 120,
+                },
+            ),
+            entry(6, JournalEvent::ItemRemoved { item_id: 2 }),
+        ];
+
+        let items = replay(&entries);
+        assert_eq!(items.len(), 1);
+        let water = &items[&1];
+        assert_eq!(water.count, 4);
+        assert_eq!(water.price, 120);
+    }
+
+    #[test]
+    fn test_replay_faults_keeps_uncleared_slots() {
+        let entries = vec![
+            entry(
+                0,
+                JournalEvent::DispenseFailed {
+                    item_id: 1,
+                    fault: DispenseFault::Jammed,
+                },
+            ),
+            entry(
+                1,
+                JournalEvent::DispenseFailed {
+                    item_id: 2,
+                    fault: DispenseFault::SlotEmpty,
+                },
+            ),
+            entry(2, JournalEvent::FaultCleared { item_id: 1 }),
+        ];
+
+        let faults = replay_faults(&entries);
+        assert_eq!(faults.len(), 1);
+        assert_eq!(faults[&2], DispenseFault::SlotEmpty);
+    }
+
+    #[test]
+    fn test_file_journal_appends_across_reopen() {
+        let path = std::env::temp_dir().join(format!(
+            "vm-journal-{}.jsonl",
+            nostr_sdk::Keys::generate().public_key().to_hex()
+        ));
+        let admin = nostr_sdk::Keys::generate().public_key();
+
+        FileJournal::open(&path)
+            .unwrap()
+            .append(&JournalEntry::new(
+                0,
+                Some(admin),
+                JournalEvent::AdminEntered,
+            ))
+            .unwrap();
+        let journal = FileJournal::open(&path).unwrap();
+        journal
+            .append(&entry(1, JournalEvent::AdminExited))
+            .unwrap();
+
+        let entries = journal.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].admin, Some(admin));
+        assert_eq!(entries[0].event, JournalEvent::AdminEntered);
+        assert_eq!(entries[1].seq, 1);
+
+        fs::remove_file(path).unwrap();
+    }
+}
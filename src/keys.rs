@@ -0,0 +1,227 @@
+use std::{fmt::Display, fs, io::Write, path::Path};
+
+use nostr_sdk::{Keys, ToBech32};
+use serde::Deserialize;
+
+/// Default location of the machine secret key when none is configured.
+pub const DEFAULT_SECRET_KEY_FILE: &str = "machine.nsec";
+
+/// Enum representing errors related to the machine identity.
+#[derive(Debug)]
+pub enum KeysError {
+    /// The secret key could not be parsed as nsec or hex.
+    InvalidSecretKey(String),
+
+    /// Reading or writing the key file failed.
+    Io(String),
+
+    /// The key could not be encoded.
+    Encoding(String),
+}
+
+impl Display for KeysError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidSecretKey(s) => write!(f, "KeysError::InvalidSecretKey: {}", s),
+            Self::Io(s) => write!(f, "KeysError::Io: {}", s),
+            Self::Encoding(s) => write!(f, "KeysError::Encoding: {}", s),
+        }
+    }
+}
+
+/// `[keys]` section of `config.toml`.
+///
+/// The secret key is looked up in the environment variable named by `secret_key_env` first,
+/// then in `secret_key_file`. If neither holds a key, a new one is generated and saved to
+/// `secret_key_file`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeysConfig {
+    /// Path of the file holding the secret key (nsec or hex)
+    #[serde(default = "default_secret_key_file")]
+    pub secret_key_file: String,
+
+    /// Name of an environment variable holding the secret key (nsec or hex)
+    #[serde(default)]
+    pub secret_key_env: Option<String>,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        Self {
+            secret_key_file: default_secret_key_file(),
+            secret_key_env: None,
+        }
+    }
+}
+
+fn default_secret_key_file() -> String {
+    DEFAULT_SECRET_KEY_FILE.to_string()
+}
+
+/// Loads the machine keys described by `config`, generating and saving them on first run.
+///
+/// # Returns
+/// - `Ok(Keys)` with the persisted (or freshly generated) machine identity.
+/// - `Err(KeysError)` if an existing key is invalid or the key file cannot be read or written.
+pub fn load_or_generate(config: &KeysConfig) -> Result<Keys, KeysError> {
+    if let Some(var) = &config.secret_key_env {
+        if let Ok(secret) = std::env::var(var) {
+            return parse_secret_key(&secret);
+        }
+    }
+
+    let path = Path::new(&config.secret_key_file);
+    if path.exists() {
+        return load_from_file(path);
+    }
+
+    let keys = Keys::generate();
+    save_to_file(path, &keys)?;
+    eprintln!("Generated new machine key at {}", path.display());
+    Ok(keys)
+}
+
+/// Parses a secret key given either as a Bech32 `nsec1...` string or as 64-char hex.
+pub fn parse_secret_key(input: &str) -> Result<Keys, KeysError> {
+    Keys::parse(input.trim()).map_err(|e| KeysError::InvalidSecretKey(e.to_string()))
+}
+
+/// Reads a secret key from `path`.
+///
+/// A warning is printed if the file is readable by group or others.
+pub fn load_from_file(path: &Path) -> Result<Keys, KeysError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)
+            .map_err(|e| KeysError::Io(e.to_string()))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            eprintln!(
+                "⚠️ Key file {} is accessible by other users (mode {:o})",
+                path.display(),
+                mode & 0o777
+            );
+        }
+    }
+
+    let secret = fs::read_to_string(path).map_err(|e| KeysError::Io(e.to_string()))?;
+    parse_secret_key(&secret)
+}
+
+/// Writes the secret key of `keys` to `path` as an `nsec`, readable by the owner only.
+///
+/// Fails if the file already exists, so an existing identity is never overwritten.
+pub fn save_to_file(path: &Path, keys: &Keys) -> Result<(), KeysError> {
+    let nsec = keys
+        .secret_key()
+        .to_bech32()
+        .map_err(|e| KeysError::Encoding(e.to_string()))?;
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| KeysError::Io(e.to_string()))?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| KeysError::Io(format!("{}: {}", path.display(), e)))?;
+    writeln!(file, "{}", nsec).map_err(|e| KeysError::Io(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "vm-keys-{}-{}",
+            name,
+            Keys::generate().public_key().to_hex()
+        ));
+        dir.join("machine.nsec")
+    }
+
+    #[test]
+    fn test_parse_secret_key_nsec_and_hex() {
+        let keys = Keys::generate();
+        let nsec = keys.secret_key().to_bech32().unwrap();
+        let hex = keys.secret_key().to_secret_hex();
+
+        assert_eq!(
+            parse_secret_key(&nsec).unwrap().public_key(),
+            keys.public_key()
+        );
+        assert_eq!(
+            parse_secret_key(&hex).unwrap().public_key(),
+            keys.public_key()
+        );
+    }
+
+    #[test]
+    fn test_parse_secret_key_invalid() {
+        let result = parse_secret_key("not_a_key");
+        assert!(matches!(result, Err(KeysError::InvalidSecretKey(_))));
+    }
+
+    #[test]
+    fn test_load_or_generate_is_stable() {
+        let path = temp_path("stable");
+        let config = KeysConfig {
+            secret_key_file: path.to_string_lossy().to_string(),
+            secret_key_env: None,
+        };
+
+        let first = load_or_generate(&config).unwrap();
+        let second = load_or_generate(&config).unwrap();
+        assert_eq!(first.public_key(), second.public_key());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_save_does_not_overwrite() {
+        let path = temp_path("overwrite");
+        save_to_file(&path, &Keys::generate()).unwrap();
+
+        let result = save_to_file(&path, &Keys::generate());
+        assert!(matches!(result, Err(KeysError::Io(_))));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_env_takes_precedence_over_file() {
+        let path = temp_path("env");
+        let keys = Keys::generate();
+        let var = "VENDING_MACHINE_TEST_NSEC";
+        std::env::set_var(var, keys.secret_key().to_bech32().unwrap());
+
+        let config = KeysConfig {
+            secret_key_file: path.to_string_lossy().to_string(),
+            secret_key_env: Some(var.to_string()),
+        };
+        assert_eq!(
+            load_or_generate(&config).unwrap().public_key(),
+            keys.public_key()
+        );
+        assert!(!path.exists());
+
+        std::env::remove_var(var);
+    }
+}
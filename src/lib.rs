@@ -1,4 +1,12 @@
 pub mod admin;
+pub mod cash;
+pub mod customer;
+pub mod hardware;
+pub mod journal;
+pub mod keys;
+pub mod market;
+pub mod payment;
+pub mod storage;
 pub mod vm;
 
 pub use vm::*;
@@ -1,8 +1,16 @@
-use nostr_sdk::Keys;
+use nostr_sdk::{PublicKey, ToBech32};
 use serde::Deserialize;
 use std::fs;
 use vending_machines_nostr::{
-    admin::{commands::AdminCommand, setup_admin_handler},
+    admin::{commands::AdminRequest, setup_admin_handler, AdminConfig},
+    cash::CashConfig,
+    customer::{setup_nostr_customer_handler, CustomerConfig},
+    hardware::HardwareConfig,
+    journal::{self, JournalConfig},
+    keys::{self, KeysConfig},
+    market::MarketConfig,
+    payment::{cashu::CashuMint, nwc::NwcWallet, PaymentsConfig},
+    storage::StorageConfig,
     vending_machine::{VendingMachine, VendingMachineError},
 };
 
@@ -10,11 +18,22 @@ use vending_machines_nostr::{
 struct Config {
     admins: AdminConfig,
     relays: RelayConfig,
-}
-
-#[derive(Deserialize)]
-struct AdminConfig {
-    public_keys: Vec<String>,
+    #[serde(default)]
+    keys: KeysConfig,
+    #[serde(default)]
+    storage: StorageConfig,
+    #[serde(default)]
+    journal: JournalConfig,
+    #[serde(default)]
+    payments: PaymentsConfig,
+    #[serde(default)]
+    cash: CashConfig,
+    #[serde(default)]
+    hardware: HardwareConfig,
+    #[serde(default)]
+    customer: CustomerConfig,
+    #[serde(default)]
+    market: MarketConfig,
 }
 
 #[derive(Deserialize)]
@@ -22,38 +41,183 @@ struct RelayConfig {
     addresses: Vec<String>,
 }
 
+/// What the binary was asked to do on the command line.
+enum Command {
+    /// Run the vending machine
+    Run,
+    /// Print the machine pubkey
+    ShowKeys,
+    /// Print the inventory rebuilt from the journal
+    ReplayJournal,
+}
+
+fn load_config() -> Result<Config, VendingMachineError> {
+    toml::from_str(
+        &fs::read_to_string("config.toml")
+            .map_err(|e| VendingMachineError::Config(e.to_string()))?,
+    )
+    .map_err(|e| VendingMachineError::Config(e.to_string()))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), VendingMachineError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => Command::Run,
+        ["keys", "show"] => Command::ShowKeys,
+        ["journal", "replay"] => Command::ReplayJournal,
+        _ => {
+            return Err(VendingMachineError::Config(format!(
+                "unknown arguments: {}. Usage: vending_machines_nostr [keys show | journal replay]",
+                args.join(" ")
+            )));
+        }
+    };
+
     // Load configuration
-    let config: Config = toml::from_str(
-        &fs::read_to_string("config.toml")
-            .map_err(|e| VendingMachineError::Config(e.to_string()))?
-    ).map_err(|e| VendingMachineError::Config(e.to_string()))?;
+    let config = load_config()?;
 
-    let relay_addresses: Vec<&str> = config.relays.addresses
-        .iter()
-        .map(AsRef::as_ref)
-        .collect();
+    if let Command::ReplayJournal = command {
+        let entries = config
+            .journal
+            .open()
+            .and_then(|journal| journal.entries())
+            .map_err(VendingMachineError::Storage)?;
+        let mut items: Vec<_> = journal::replay(&entries).into_values().collect();
+        items.sort_by_key(|item| item.id);
+        println!("replayed {} journal entries", entries.len());
+        for item in items {
+            println!(
+                "id: {}, name: {}, price: {}, stock: {}",
+                item.id, item.name, item.price, item.count
+            );
+        }
+        return Ok(());
+    }
+
+    // Load (or create on first run) the machine identity
+    let machine_keys = keys::load_or_generate(&config.keys).map_err(VendingMachineError::Keys)?;
+
+    if let Command::ShowKeys = command {
+        let npub = machine_keys
+            .public_key()
+            .to_bech32()
+            .map_err(|e| VendingMachineError::Config(e.to_string()))?;
+        println!("{}", npub);
+        println!("{}", machine_keys.public_key().to_hex());
+        return Ok(());
+    }
+
+    let relay_addresses: Vec<&str> = config.relays.addresses.iter().map(AsRef::as_ref).collect();
 
     // Create admin command channel
-    let (tx, rx) = tokio::sync::mpsc::channel::<AdminCommand>(10);
-    let (_, shutdown_rx) = tokio::sync::mpsc::channel::<bool>(1);
+    let (tx, rx) = tokio::sync::mpsc::channel::<AdminRequest>(config.admins.limits.backlog);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::mpsc::channel::<bool>(1);
+
+    // Stop gracefully on Ctrl-C
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let _ = shutdown_tx.send(true).await;
+        }
+    });
+
+    println!("Vending machine pubkey: {}", machine_keys.public_key());
 
-    let admin_keys = Keys::generate();
-    println!("Admin vm: {}", admin_keys.public_key());
-    
     // Create and configure admin handler with config
     let admin_handler = setup_admin_handler(
-        admin_keys.clone(),
-        &config.admins.public_keys,
+        machine_keys.clone(),
+        &config.admins,
         &relay_addresses,
         tx,
+        config
+            .admins
+            .replay
+            .open()
+            .map_err(VendingMachineError::Storage)?,
     )
     .await
     .map_err(VendingMachineError::AdminError)?;
 
+    // Open the inventory store the machine restores from
+    let store = config
+        .storage
+        .open()
+        .map_err(VendingMachineError::Storage)?;
+
+    // Open the append-only transaction journal
+    let journal = config
+        .journal
+        .open()
+        .map_err(VendingMachineError::Storage)?;
+
     // Create vending machine
-    let mut vm = VendingMachine::new(admin_keys, &relay_addresses, rx, shutdown_rx).await?;
+    let mut vm = VendingMachine::new(
+        machine_keys.clone(),
+        &relay_addresses,
+        rx,
+        shutdown_rx,
+        store,
+        journal,
+    )
+    .await?;
+    vm.set_denominations(config.cash.denominations.clone());
+    vm.set_hardware(config.hardware.open());
+    let admin_pubkeys: Vec<PublicKey> = config
+        .admins
+        .admins()
+        .map_err(VendingMachineError::Storage)?
+        .iter()
+        .filter_map(|admin| PublicKey::parse(&admin.pubkey).ok())
+        .collect();
+    vm.set_admin_pubkeys(admin_pubkeys);
+
+    // List the machine on Nostr marketplaces
+    if config.market.enabled {
+        vm.set_market(config.market.clone());
+        if let Err(e) = vm.publish_market().await {
+            eprintln!("Failed to publish marketplace stall: {}", e);
+        }
+    }
+
+    // Connect the Lightning wallet, if configured
+    if let Some(nwc) = &config.payments.nwc {
+        let wallet = NwcWallet::connect(nwc)
+            .await
+            .map_err(VendingMachineError::Payment)?;
+        vm.set_lightning_wallet(wallet);
+    }
+
+    // Redeem Cashu tokens at the configured mint
+    if let Some(cashu) = &config.payments.cashu {
+        vm.set_cashu_mint(CashuMint::new(cashu).map_err(VendingMachineError::Payment)?);
+    }
+
+    // Let customers operate the machine locally
+    let customer_task = config.customer.spawn(vm.customer_sender());
+
+    // Take orders and payments sent in DMs
+    let nostr_customer_task = if config.customer.nostr {
+        let nostr_customer_handler = setup_nostr_customer_handler(
+            machine_keys.clone(),
+            &relay_addresses,
+            admin_handler.admin_list(),
+            vm.remote_customer_sender(),
+        )
+        .await
+        .map_err(|e| VendingMachineError::Config(e.to_string()))?;
+        Some(tokio::spawn(async move {
+            if let Err(e) = nostr_customer_handler.handle_events().await {
+                eprintln!("Nostr customer handler error: {}", e);
+            }
+        }))
+    } else {
+        None
+    };
 
     // Spawn admin listener task
     let admin_task = tokio::spawn(async move {
@@ -65,8 +229,15 @@ async fn main() -> Result<(), VendingMachineError> {
     // Run the main machine loop
     vm.run_machine().await?;
 
-    // Clean shutdown (optional)
+    // Refund, save and announce we are going offline
+    vm.shut_down().await?;
     admin_task.abort();
+    if let Some(nostr_customer_task) = nostr_customer_task {
+        nostr_customer_task.abort();
+    }
+    if let Some(customer_task) = customer_task {
+        customer_task.abort();
+    }
 
     Ok(())
 }
@@ -0,0 +1,131 @@
+use nostr_sdk::{
+    nips::{
+        nip01::Coordinate,
+        nip09::EventDeletionRequest,
+        nip15::{ProductData, StallData},
+    },
+    EventBuilder, JsonUtil, Kind, PublicKey, Tag,
+};
+use serde::Deserialize;
+
+use crate::vm::vending_machine::Item;
+
+/// `[market]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MarketConfig {
+    /// Publish the machine as a NIP-15 stall with one product per item
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// `d` tag of the stall event
+    #[serde(default = "default_stall_id")]
+    pub stall_id: String,
+
+    /// Name marketplace clients show for the stall
+    #[serde(default = "default_stall_name")]
+    pub stall_name: String,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// Currency item prices are listed in
+    #[serde(default = "default_currency")]
+    pub currency: String,
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            stall_id: default_stall_id(),
+            stall_name: default_stall_name(),
+            description: None,
+            currency: default_currency(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_stall_id() -> String {
+    "vending_machine".to_string()
+}
+
+fn default_stall_name() -> String {
+    "Vending machine".to_string()
+}
+
+fn default_currency() -> String {
+    "sat".to_string()
+}
+
+/// Id of the product listing `item_id`.
+pub fn product_id(item_id: u64) -> String {
+    item_id.to_string()
+}
+
+/// Item listed as `product_id`, if it is one of ours.
+pub fn item_id(product_id: &str) -> Option<u64> {
+    product_id.parse().ok()
+}
+
+/// Kind 30017 event describing the stall.
+pub fn stall_event(config: &MarketConfig) -> EventBuilder {
+    let mut stall = StallData::new(&config.stall_id, &config.stall_name, &config.currency);
+    if let Some(description) = &config.description {
+        stall = stall.description(description);
+    }
+    EventBuilder::stall_data(stall)
+}
+
+/// Kind 30018 event listing `item` with its price and the `available` units, those not reserved
+/// in a cart.
+pub fn product_event(config: &MarketConfig, item: &Item, available: u64) -> EventBuilder {
+    let product = ProductData::new(
+        &product_id(item.id),
+        &config.stall_id,
+        &item.name,
+        &config.currency,
+    )
+    .price(item.price as f64)
+    .quantity(available);
+    // `EventBuilder::product_data` tags the product with the stall id, which would make every
+    // product of the stall replace the previous one. The `d` tag must be the product id.
+    EventBuilder::new(Kind::SetProduct, product.as_json()).tag(Tag::identifier(product_id(item.id)))
+}
+
+/// NIP-09 request to delete the listing of `item_id` published by `merchant`.
+pub fn product_deletion_event(merchant: PublicKey, item_id: u64) -> EventBuilder {
+    EventBuilder::delete(
+        EventDeletionRequest::new().coordinate(
+            Coordinate::new(Kind::SetProduct, merchant).identifier(product_id(item_id)),
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_product_event() {
+        let keys = nostr_sdk::Keys::generate();
+        let event = product_event(
+            &MarketConfig::default(),
+            &Item::new(7, "Coffee".to_string(), 20, 3),
+            2,
+        )
+        .sign_with_keys(&keys)
+        .unwrap();
+        assert_eq!(event.kind, Kind::SetProduct);
+        assert_eq!(event.tags.identifier(), Some("7"));
+
+        let product = ProductData::from_json(&event.content).unwrap();
+        assert_eq!(product.stall_id, "vending_machine");
+        assert_eq!(product.price, 20.0);
+        assert_eq!(product.quantity, 2);
+        assert_eq!(item_id(&product.id), Some(7));
+    }
+}
@@ -0,0 +1,106 @@
+//! Blind Diffie-Hellman key exchange used by Cashu mints (NUT-00).
+
+use nostr_sdk::{
+    hashes::{sha256, Hash},
+    secp256k1::{rand, PublicKey, Scalar, SecretKey},
+    SECP256K1,
+};
+
+use crate::payment::PaymentError;
+
+const DOMAIN_SEPARATOR: &[u8] = b"Secp256k1_HashToCurve_Cashu_";
+
+/// Maps `message` to a point on the curve (`Y` in NUT-00).
+pub fn hash_to_curve(message: &[u8]) -> Result<PublicKey, PaymentError> {
+    let msg_hash = sha256::Hash::hash(&[DOMAIN_SEPARATOR, message].concat());
+    for counter in 0u32..(1 << 16) {
+        let mut bytes = msg_hash.to_byte_array().to_vec();
+        bytes.extend_from_slice(&counter.to_le_bytes());
+        let hash = sha256::Hash::hash(&bytes);
+
+        let mut candidate = [0u8; 33];
+        candidate[0] = 0x02;
+        candidate[1..].copy_from_slice(hash.as_ref());
+        if let Ok(point) = PublicKey::from_slice(&candidate) {
+            return Ok(point);
+        }
+    }
+    Err(PaymentError::InvalidToken(
+        "no curve point found for secret".to_string(),
+    ))
+}
+
+/// Blinds `secret`, returning the blinded message `B_` and the blinding factor `r`.
+pub fn blind(secret: &[u8]) -> Result<(PublicKey, SecretKey), PaymentError> {
+    let y = hash_to_curve(secret)?;
+    let r = SecretKey::new(&mut rand::thread_rng());
+    let blinded = y
+        .combine(&PublicKey::from_secret_key(SECP256K1, &r))
+        .map_err(|e| PaymentError::InvalidToken(e.to_string()))?;
+    Ok((blinded, r))
+}
+
+/// Removes the blinding factor `r` from the mint signature `C_` made with mint key `K`.
+pub fn unblind(
+    blind_signature: &PublicKey,
+    r: &SecretKey,
+    mint_key: &PublicKey,
+) -> Result<PublicKey, PaymentError> {
+    let r_k = mint_key
+        .mul_tweak(SECP256K1, &Scalar::from(*r))
+        .map_err(|e| PaymentError::Mint(e.to_string()))?;
+    blind_signature
+        .combine(&r_k.negate(SECP256K1))
+        .map_err(|e| PaymentError::Mint(e.to_string()))
+}
+
+/// Signs the blinded message `B_` with mint key `k` (mint side).
+pub fn sign(blinded: &PublicKey, k: &SecretKey) -> Result<PublicKey, PaymentError> {
+    blinded
+        .mul_tweak(SECP256K1, &Scalar::from(*k))
+        .map_err(|e| PaymentError::Mint(e.to_string()))
+}
+
+/// Checks that `C` is the mint signature over `secret` for mint key `k` (mint side).
+pub fn verify(k: &SecretKey, signature: &PublicKey, secret: &[u8]) -> bool {
+    hash_to_curve(secret)
+        .and_then(|y| sign(&y, k))
+        .is_ok_and(|expected| expected == *signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::util::hex;
+
+    #[test]
+    fn test_hash_to_curve_vectors() {
+        let zero = hex::decode("0000000000000000000000000000000000000000000000000000000000000000")
+            .unwrap();
+        assert_eq!(
+            hash_to_curve(&zero).unwrap().to_string(),
+            "024cce997d3b518f739663b757deaec95bcd9473c30a14ac2fd04023a739d1a725"
+        );
+
+        let one = hex::decode("0000000000000000000000000000000000000000000000000000000000000001")
+            .unwrap();
+        assert_eq!(
+            hash_to_curve(&one).unwrap().to_string(),
+            "022e7158e11c9506f1aa4248bf531298daa7febd6194f003edcd9b93ade6253acf"
+        );
+    }
+
+    #[test]
+    fn test_blind_sign_unblind_verifies() {
+        let k = SecretKey::new(&mut rand::thread_rng());
+        let mint_key = PublicKey::from_secret_key(SECP256K1, &k);
+        let secret = b"my secret";
+
+        let (blinded, r) = blind(secret).unwrap();
+        let blind_signature = sign(&blinded, &k).unwrap();
+        let signature = unblind(&blind_signature, &r, &mint_key).unwrap();
+
+        assert!(verify(&k, &signature, secret));
+        assert!(!verify(&k, &signature, b"another secret"));
+    }
+}
@@ -0,0 +1,314 @@
+pub mod dhke;
+mod token;
+
+use std::{collections::HashMap, fs, path::PathBuf, sync::Mutex, time::Duration};
+
+use nostr_sdk::{secp256k1::PublicKey, Keys};
+use serde::{Deserialize, Serialize};
+
+pub use token::{Proof, Token};
+
+use super::PaymentError;
+
+/// Unit of the ecash accepted. Items are priced in sats.
+pub const UNIT: &str = "sat";
+
+/// `[payments.cashu]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CashuConfig {
+    /// URL of the only mint whose tokens are accepted
+    pub mint_url: String,
+
+    /// File the redeemed ecash is saved to
+    #[serde(default = "default_proofs_path")]
+    pub proofs_path: String,
+
+    /// Seconds to wait for the mint to answer
+    #[serde(default = "default_mint_timeout")]
+    pub mint_timeout_secs: u64,
+}
+
+fn default_proofs_path() -> String {
+    "cashu_proofs.json".to_string()
+}
+
+fn default_mint_timeout() -> u64 {
+    10
+}
+
+#[derive(Serialize)]
+struct BlindedMessage {
+    amount: u64,
+    id: String,
+    #[serde(rename = "B_")]
+    b: String,
+}
+
+#[derive(Deserialize)]
+struct BlindSignature {
+    amount: u64,
+    #[serde(rename = "C_")]
+    c: String,
+}
+
+#[derive(Serialize)]
+struct SwapRequest<'a> {
+    inputs: &'a [Proof],
+    outputs: Vec<BlindedMessage>,
+}
+
+#[derive(Deserialize)]
+struct SwapResponse {
+    signatures: Vec<BlindSignature>,
+}
+
+#[derive(Deserialize)]
+struct KeysResponse {
+    keysets: Vec<Keyset>,
+}
+
+#[derive(Deserialize)]
+struct Keyset {
+    id: String,
+    unit: String,
+    keys: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct MintErrorResponse {
+    detail: String,
+}
+
+/// Client for the configured Cashu mint, redeeming customer tokens into the machine's own ecash
+/// and paying refunds out of it.
+pub struct CashuMint {
+    url: String,
+    http: reqwest::Client,
+    proofs_path: PathBuf,
+    /// Serializes writes to the proofs file
+    proofs_lock: Mutex<()>,
+}
+
+impl CashuMint {
+    pub fn new(config: &CashuConfig) -> Result<Self, PaymentError> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.mint_timeout_secs))
+            .build()
+            .map_err(|e| PaymentError::Config(e.to_string()))?;
+        Ok(Self {
+            url: config.mint_url.trim_end_matches('/').to_string(),
+            http,
+            proofs_path: PathBuf::from(&config.proofs_path),
+            proofs_lock: Mutex::new(()),
+        })
+    }
+
+    /// Swaps the proofs of `token` for fresh ones only the machine knows (NUT-03), so the
+    /// customer cannot spend them again, and saves them to the proofs file.
+    ///
+    /// # Returns
+    /// The redeemed amount.
+    pub async fn redeem(&self, token: &Token) -> Result<u64, PaymentError> {
+        if token.mint.trim_end_matches('/') != self.url {
+            return Err(PaymentError::Rejected(format!(
+                "tokens from mint {} are not accepted, use {}",
+                token.mint, self.url
+            )));
+        }
+        if token.unit != UNIT {
+            return Err(PaymentError::Rejected(format!(
+                "tokens in {} are not accepted, use {}",
+                token.unit, UNIT
+            )));
+        }
+        let keyset = self.active_keyset(&token.unit).await?;
+        let proofs = self
+            .swap(&keyset, &token.proofs, split_amount(token.amount()?))
+            .await?;
+
+        let amount = proofs.iter().map(|proof| proof.amount).sum();
+        self.save_proofs(proofs)?;
+        Ok(amount)
+    }
+
+    /// Takes `amount` out of the saved ecash as a token for someone else, e.g. a refund. The
+    /// saved proofs covering it are swapped for the amount and the change, which is saved back.
+    pub async fn send(&self, amount: u64) -> Result<Token, PaymentError> {
+        let keyset = self.active_keyset(UNIT).await?;
+        let inputs = self.take_proofs(amount)?;
+        let change = inputs.iter().map(|proof| proof.amount).sum::<u64>() - amount;
+        let sent_outputs = split_amount(amount);
+        let sent_count = sent_outputs.len();
+        let outputs = sent_outputs
+            .into_iter()
+            .chain(split_amount(change))
+            .collect();
+
+        let mut proofs = match self.swap(&keyset, &inputs, outputs).await {
+            Ok(proofs) => proofs,
+            Err(e) => {
+                self.save_proofs(inputs)?;
+                return Err(e);
+            }
+        };
+        let change = proofs.split_off(sent_count);
+        self.save_proofs(change)?;
+        Ok(Token {
+            mint: self.url.clone(),
+            unit: UNIT.to_string(),
+            memo: None,
+            proofs,
+        })
+    }
+
+    /// Swaps `inputs` at the mint (NUT-03) for fresh proofs of the `amounts` given, in order,
+    /// whose secrets only the machine knows.
+    async fn swap(
+        &self,
+        keyset: &Keyset,
+        inputs: &[Proof],
+        amounts: Vec<u64>,
+    ) -> Result<Vec<Proof>, PaymentError> {
+        let mut outputs = Vec::new();
+        let mut blinding = Vec::new();
+        for amount in amounts {
+            let secret = Keys::generate().secret_key().to_secret_hex();
+            let (blinded, r) = dhke::blind(secret.as_bytes())?;
+            outputs.push(BlindedMessage {
+                amount,
+                id: keyset.id.clone(),
+                b: blinded.to_string(),
+            });
+            blinding.push((secret, r));
+        }
+
+        let response = self
+            .http
+            .post(format!("{}/v1/swap", self.url))
+            .json(&SwapRequest { inputs, outputs })
+            .send()
+            .await
+            .map_err(|e| PaymentError::Mint(e.to_string()))?;
+        let swap: SwapResponse = parse_mint_response(response).await?;
+
+        let mut proofs = Vec::new();
+        for (signature, (secret, r)) in swap.signatures.into_iter().zip(blinding) {
+            let mint_key = keyset
+                .keys
+                .get(&signature.amount.to_string())
+                .ok_or_else(|| PaymentError::Mint(format!("no key for {}", signature.amount)))
+                .and_then(|key| parse_point(key))?;
+            let blind_signature = parse_point(&signature.c)?;
+            proofs.push(Proof {
+                amount: signature.amount,
+                id: keyset.id.clone(),
+                secret,
+                c: dhke::unblind(&blind_signature, &r, &mint_key)?.to_string(),
+            });
+        }
+        Ok(proofs)
+    }
+
+    async fn active_keyset(&self, unit: &str) -> Result<Keyset, PaymentError> {
+        let response = self
+            .http
+            .get(format!("{}/v1/keys", self.url))
+            .send()
+            .await
+            .map_err(|e| PaymentError::Mint(e.to_string()))?;
+        let keys: KeysResponse = parse_mint_response(response).await?;
+        keys.keysets
+            .into_iter()
+            .find(|keyset| keyset.unit == unit)
+            .ok_or_else(|| PaymentError::Rejected(format!("mint has no keyset for unit {}", unit)))
+    }
+
+    /// Appends `proofs` to the proofs file, keeping what is already stored.
+    fn save_proofs(&self, proofs: Vec<Proof>) -> Result<(), PaymentError> {
+        let _guard = self.proofs_lock.lock().unwrap();
+        let mut stored = self.load_proofs()?;
+        stored.extend(proofs);
+        self.write_proofs(&stored)
+    }
+
+    /// Removes stored proofs worth at least `amount` from the proofs file, largest first, and
+    /// returns them.
+    fn take_proofs(&self, amount: u64) -> Result<Vec<Proof>, PaymentError> {
+        let _guard = self.proofs_lock.lock().unwrap();
+        let mut stored = self.load_proofs()?;
+        stored.sort_by_key(|proof| proof.amount);
+        let mut taken = Vec::new();
+        let mut total = 0;
+        while total < amount {
+            let Some(proof) = stored.pop() else {
+                return Err(PaymentError::Rejected(format!(
+                    "only {} sats of ecash left",
+                    total
+                )));
+            };
+            total += proof.amount;
+            taken.push(proof);
+        }
+        self.write_proofs(&stored)?;
+        Ok(taken)
+    }
+
+    fn load_proofs(&self) -> Result<Vec<Proof>, PaymentError> {
+        match fs::read_to_string(&self.proofs_path) {
+            Ok(raw) => serde_json::from_str(&raw).map_err(|e| PaymentError::Mint(e.to_string())),
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+
+    /// Replaces the proofs file with `proofs`, atomically.
+    fn write_proofs(&self, proofs: &[Proof]) -> Result<(), PaymentError> {
+        let raw = serde_json::to_string_pretty(proofs).unwrap();
+        let mut tmp = self.proofs_path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, raw).map_err(|e| PaymentError::Mint(e.to_string()))?;
+        fs::rename(&tmp, &self.proofs_path).map_err(|e| PaymentError::Mint(e.to_string()))
+    }
+}
+
+async fn parse_mint_response<T: for<'de> Deserialize<'de>>(
+    response: reqwest::Response,
+) -> Result<T, PaymentError> {
+    if !response.status().is_success() {
+        let status = response.status();
+        let detail = response
+            .json::<MintErrorResponse>()
+            .await
+            .map(|error| error.detail)
+            .unwrap_or_else(|_| status.to_string());
+        return Err(PaymentError::Mint(detail));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| PaymentError::Mint(e.to_string()))
+}
+
+fn parse_point(hex: &str) -> Result<PublicKey, PaymentError> {
+    hex.parse()
+        .map_err(|_| PaymentError::Mint(format!("invalid point {}", hex)))
+}
+
+/// Splits `amount` into the powers of two mints issue notes for.
+pub fn split_amount(amount: u64) -> Vec<u64> {
+    (0..64)
+        .map(|bit| 1u64 << bit)
+        .filter(|value| amount & value != 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_amount() {
+        assert_eq!(split_amount(0), Vec::<u64>::new());
+        assert_eq!(split_amount(13), vec![1, 4, 8]);
+        assert_eq!(split_amount(64), vec![64]);
+    }
+}
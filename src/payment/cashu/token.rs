@@ -0,0 +1,276 @@
+use base64::{
+    alphabet,
+    engine::{general_purpose::GeneralPurpose, DecodePaddingMode, GeneralPurposeConfig},
+    Engine,
+};
+use ciborium::Value;
+use nostr_sdk::util::hex;
+use serde::{Deserialize, Serialize};
+
+use crate::payment::PaymentError;
+
+/// URL-safe base64 accepting tokens with or without padding.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// A single ecash note.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proof {
+    pub amount: u64,
+    /// Keyset id (hex)
+    pub id: String,
+    pub secret: String,
+    /// Unblinded mint signature (hex compressed point)
+    #[serde(rename = "C")]
+    pub c: String,
+}
+
+/// A Cashu token: proofs from a single mint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub mint: String,
+    pub unit: String,
+    pub memo: Option<String>,
+    pub proofs: Vec<Proof>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenV3 {
+    token: Vec<TokenV3Entry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenV3Entry {
+    mint: String,
+    proofs: Vec<Proof>,
+}
+
+impl Token {
+    /// Parses a serialized `cashuA...` (V3, JSON) or `cashuB...` (V4, CBOR) token.
+    pub fn parse(input: &str) -> Result<Self, PaymentError> {
+        let input = input.trim();
+        if let Some(encoded) = input.strip_prefix("cashuA") {
+            Self::parse_v3(&decode(encoded)?)
+        } else if let Some(encoded) = input.strip_prefix("cashuB") {
+            Self::parse_v4(&decode(encoded)?)
+        } else {
+            Err(PaymentError::InvalidToken(
+                "token must start with cashuA or cashuB".to_string(),
+            ))
+        }
+    }
+
+    /// Total value of the proofs. Fails if it does not fit in a `u64`, which only a forged
+    /// token can do.
+    pub fn amount(&self) -> Result<u64, PaymentError> {
+        self.proofs
+            .iter()
+            .try_fold(0u64, |total, proof| total.checked_add(proof.amount))
+            .ok_or_else(|| PaymentError::InvalidToken("token amount overflows".to_string()))
+    }
+
+    /// Serializes the token in the V3 (`cashuA`) format.
+    pub fn to_v3_string(&self) -> String {
+        let v3 = TokenV3 {
+            token: vec![TokenV3Entry {
+                mint: self.mint.clone(),
+                proofs: self.proofs.clone(),
+            }],
+            unit: Some(self.unit.clone()),
+            memo: self.memo.clone(),
+        };
+        // Serializing plain strings and integers cannot fail
+        let json = serde_json::to_vec(&v3).unwrap();
+        format!("cashuA{}", BASE64.encode(json))
+    }
+
+    fn parse_v3(bytes: &[u8]) -> Result<Self, PaymentError> {
+        let v3: TokenV3 =
+            serde_json::from_slice(bytes).map_err(|e| PaymentError::InvalidToken(e.to_string()))?;
+        let mut entries = v3.token.into_iter();
+        let entry = entries
+            .next()
+            .ok_or_else(|| PaymentError::InvalidToken("token has no proofs".to_string()))?;
+        if entries.next().is_some() {
+            return Err(PaymentError::InvalidToken(
+                "tokens from several mints are not supported".to_string(),
+            ));
+        }
+        Ok(Self {
+            mint: entry.mint,
+            unit: v3.unit.unwrap_or_else(|| "sat".to_string()),
+            memo: v3.memo,
+            proofs: entry.proofs,
+        })
+    }
+
+    fn parse_v4(bytes: &[u8]) -> Result<Self, PaymentError> {
+        let value: Value =
+            ciborium::from_reader(bytes).map_err(|e| PaymentError::InvalidToken(e.to_string()))?;
+
+        let mint = text(field(&value, "m")?)?;
+        let unit = text(field(&value, "u")?)?;
+        let memo = field(&value, "d").ok().map(text).transpose()?;
+
+        let mut proofs = Vec::new();
+        for keyset in array(field(&value, "t")?)? {
+            let id = hex::encode(bytes_of(field(keyset, "i")?)?);
+            for proof in array(field(keyset, "p")?)? {
+                proofs.push(Proof {
+                    amount: integer(field(proof, "a")?)?,
+                    id: id.clone(),
+                    secret: text(field(proof, "s")?)?,
+                    c: hex::encode(bytes_of(field(proof, "c")?)?),
+                });
+            }
+        }
+
+        Ok(Self {
+            mint,
+            unit,
+            memo,
+            proofs,
+        })
+    }
+}
+
+fn decode(encoded: &str) -> Result<Vec<u8>, PaymentError> {
+    BASE64
+        .decode(encoded)
+        .map_err(|e| PaymentError::InvalidToken(e.to_string()))
+}
+
+fn field<'a>(value: &'a Value, key: &str) -> Result<&'a Value, PaymentError> {
+    value
+        .as_map()
+        .and_then(|map| {
+            map.iter()
+                .find(|(k, _)| k.as_text() == Some(key))
+                .map(|(_, v)| v)
+        })
+        .ok_or_else(|| PaymentError::InvalidToken(format!("missing field {}", key)))
+}
+
+fn text(value: &Value) -> Result<String, PaymentError> {
+    value
+        .as_text()
+        .map(str::to_string)
+        .ok_or_else(|| PaymentError::InvalidToken("expected text".to_string()))
+}
+
+fn array(value: &Value) -> Result<&Vec<Value>, PaymentError> {
+    value
+        .as_array()
+        .ok_or_else(|| PaymentError::InvalidToken("expected array".to_string()))
+}
+
+fn bytes_of(value: &Value) -> Result<&Vec<u8>, PaymentError> {
+    value
+        .as_bytes()
+        .ok_or_else(|| PaymentError::InvalidToken("expected bytes".to_string()))
+}
+
+fn integer(value: &Value) -> Result<u64, PaymentError> {
+    value
+        .as_integer()
+        .and_then(|i| u64::try_from(i).ok())
+        .ok_or_else(|| PaymentError::InvalidToken("expected amount".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Token {
+        Token {
+            mint: "http://localhost:3338".to_string(),
+            unit: "sat".to_string(),
+            memo: Some("coffee".to_string()),
+            proofs: vec![
+                Proof {
+                    amount: 2,
+                    id: "009a1f293253e41e".to_string(),
+                    secret: "secret-a".to_string(),
+                    c: "02bc9097997d81afb2cc7346b5e4345a9346bd2a506eb7958598a72f0cf85163ea"
+                        .to_string(),
+                },
+                Proof {
+                    amount: 8,
+                    id: "009a1f293253e41e".to_string(),
+                    secret: "secret-b".to_string(),
+                    c: "029e8e5050b890a7d6c0968db16bc1d5d5fa040ea1de284f6ec69d61299f671059"
+                        .to_string(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_v3_roundtrip() {
+        let token = sample();
+        let parsed = Token::parse(&token.to_v3_string()).unwrap();
+        assert_eq!(parsed, token);
+        assert_eq!(parsed.amount().unwrap(), 10);
+    }
+
+    #[test]
+    fn test_amount_overflow_is_rejected() {
+        let mut token = sample();
+        token.proofs[0].amount = u64::MAX;
+        assert!(matches!(token.amount(), Err(PaymentError::InvalidToken(_))));
+    }
+
+    #[test]
+    fn test_parse_v4() {
+        let token = sample();
+        let proof = |p: &Proof| {
+            Value::Map(vec![
+                (Value::Text("a".into()), Value::Integer(p.amount.into())),
+                (Value::Text("s".into()), Value::Text(p.secret.clone())),
+                (
+                    Value::Text("c".into()),
+                    Value::Bytes(hex::decode(&p.c).unwrap()),
+                ),
+            ])
+        };
+        let value = Value::Map(vec![
+            (Value::Text("m".into()), Value::Text(token.mint.clone())),
+            (Value::Text("u".into()), Value::Text("sat".into())),
+            (Value::Text("d".into()), Value::Text("coffee".into())),
+            (
+                Value::Text("t".into()),
+                Value::Array(vec![Value::Map(vec![
+                    (
+                        Value::Text("i".into()),
+                        Value::Bytes(hex::decode("009a1f293253e41e").unwrap()),
+                    ),
+                    (
+                        Value::Text("p".into()),
+                        Value::Array(token.proofs.iter().map(proof).collect()),
+                    ),
+                ])]),
+            ),
+        ]);
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&value, &mut cbor).unwrap();
+
+        let parsed = Token::parse(&format!("cashuB{}", BASE64.encode(cbor))).unwrap();
+        assert_eq!(parsed, token);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_prefix() {
+        assert!(matches!(
+            Token::parse("lnbc1..."),
+            Err(PaymentError::InvalidToken(_))
+        ));
+    }
+}
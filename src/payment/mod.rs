@@ -0,0 +1,79 @@
+pub mod cashu;
+pub mod nwc;
+
+use std::fmt::Display;
+
+use serde::Deserialize;
+
+use cashu::CashuConfig;
+use nwc::NwcConfig;
+
+/// Enum representing errors related to payments.
+#[derive(Debug)]
+pub enum PaymentError {
+    /// The payment configuration is invalid
+    Config(String),
+
+    /// Talking to the relays failed
+    Nostr(String),
+
+    /// The wallet answered with an error
+    Wallet(String),
+
+    /// The wallet did not answer in time
+    Timeout(String),
+
+    /// The ecash token could not be parsed
+    InvalidToken(String),
+
+    /// The mint refused or failed to redeem a token
+    Mint(String),
+
+    /// The payment does not fit the current transaction
+    Rejected(String),
+}
+
+impl Display for PaymentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(s) => write!(f, "PaymentError::Config: {}", s),
+            Self::Nostr(s) => write!(f, "PaymentError::Nostr: {}", s),
+            Self::Wallet(s) => write!(f, "PaymentError::Wallet: {}", s),
+            Self::Timeout(s) => write!(f, "PaymentError::Timeout: {}", s),
+            Self::InvalidToken(s) => write!(f, "PaymentError::InvalidToken: {}", s),
+            Self::Mint(s) => write!(f, "PaymentError::Mint: {}", s),
+            Self::Rejected(s) => write!(f, "PaymentError::Rejected: {}", s),
+        }
+    }
+}
+
+/// Payment notifications delivered to the vending machine loop.
+#[derive(Debug, Clone)]
+pub enum PaymentEvent {
+    /// A Lightning invoice created by the machine was paid
+    InvoiceSettled { payment_hash: String, amount: u64 },
+    /// A Lightning invoice expired unpaid
+    InvoiceExpired { payment_hash: String },
+}
+
+/// A Lightning invoice waiting to be paid.
+#[derive(Debug, Clone)]
+pub struct Invoice {
+    /// BOLT11 payment request shown to the customer
+    pub bolt11: String,
+    pub payment_hash: String,
+    /// Amount in sats (the machine's price unit)
+    pub amount: u64,
+}
+
+/// `[payments]` section of `config.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PaymentsConfig {
+    /// Lightning wallet reached through Nostr Wallet Connect
+    #[serde(default)]
+    pub nwc: Option<NwcConfig>,
+
+    /// Cashu ecash received in encrypted DMs
+    #[serde(default)]
+    pub cashu: Option<CashuConfig>,
+}
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use nostr_sdk::{
+    nips::nip47::{
+        LookupInvoiceRequest, MakeInvoiceRequest, NostrWalletConnectURI, Request, Response,
+    },
+    Client, Filter, Keys, Kind, RelayPoolNotification,
+};
+use serde::Deserialize;
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+
+use super::{Invoice, PaymentError, PaymentEvent};
+
+/// `[payments.nwc]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NwcConfig {
+    /// `nostr+walletconnect://...` connection string issued by the wallet
+    pub uri: String,
+
+    /// Seconds before an unpaid invoice expires
+    #[serde(default = "default_invoice_expiry")]
+    pub invoice_expiry_secs: u64,
+
+    /// Seconds between two settlement checks
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_secs: u64,
+
+    /// Seconds to wait for the wallet to answer a request
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout_secs: u64,
+}
+
+fn default_invoice_expiry() -> u64 {
+    300
+}
+
+fn default_poll_interval() -> u64 {
+    2
+}
+
+fn default_request_timeout() -> u64 {
+    10
+}
+
+/// Lightning wallet reached through Nostr Wallet Connect (NIP-47).
+///
+/// Cloning is cheap: clones share the same relay connections.
+#[derive(Clone)]
+pub struct NwcWallet {
+    uri: NostrWalletConnectURI,
+    client: Client,
+    invoice_expiry: Duration,
+    poll_interval: Duration,
+    request_timeout: Duration,
+}
+
+impl NwcWallet {
+    /// Parses the connection string in `config` and connects to the wallet relays.
+    pub async fn connect(config: &NwcConfig) -> Result<Self, PaymentError> {
+        let uri = NostrWalletConnectURI::parse(&config.uri)
+            .map_err(|e| PaymentError::Config(e.to_string()))?;
+
+        let client = nostr_sdk::ClientBuilder::new()
+            .signer(Keys::new(uri.secret.clone()))
+            .build();
+        for relay in uri.relays.iter() {
+            client
+                .add_relay(relay)
+                .await
+                .map_err(|e| PaymentError::Nostr(e.to_string()))?;
+        }
+        client.connect().await;
+
+        Ok(Self {
+            uri,
+            client,
+            invoice_expiry: Duration::from_secs(config.invoice_expiry_secs),
+            poll_interval: Duration::from_secs(config.poll_interval_secs),
+            request_timeout: Duration::from_secs(config.request_timeout_secs),
+        })
+    }
+
+    /// Asks the wallet for a BOLT11 invoice of `amount` sats.
+    pub async fn make_invoice(
+        &self,
+        amount: u64,
+        description: &str,
+    ) -> Result<Invoice, PaymentError> {
+        let response = self
+            .request(Request::make_invoice(MakeInvoiceRequest {
+                amount: amount * 1000,
+                description: Some(description.to_string()),
+                description_hash: None,
+                expiry: Some(self.invoice_expiry.as_secs()),
+            }))
+            .await?
+            .to_make_invoice()
+            .map_err(|e| PaymentError::Wallet(e.to_string()))?;
+
+        Ok(Invoice {
+            bolt11: response.invoice,
+            payment_hash: response.payment_hash,
+            amount,
+        })
+    }
+
+    /// Returns whether the invoice identified by `payment_hash` has been paid.
+    pub async fn is_settled(&self, payment_hash: &str) -> Result<bool, PaymentError> {
+        let response = self
+            .request(Request::lookup_invoice(LookupInvoiceRequest {
+                payment_hash: Some(payment_hash.to_string()),
+                invoice: None,
+            }))
+            .await?
+            .to_lookup_invoice()
+            .map_err(|e| PaymentError::Wallet(e.to_string()))?;
+
+        Ok(response.settled_at.is_some())
+    }
+
+    /// Polls the wallet until `invoice` is paid or has expired, then notifies `events`.
+    pub async fn watch_invoice(self, invoice: Invoice, events: mpsc::Sender<PaymentEvent>) {
+        let deadline = tokio::time::Instant::now() + self.invoice_expiry;
+        while tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(self.poll_interval).await;
+            match self.is_settled(&invoice.payment_hash).await {
+                Ok(true) => {
+                    let _ = events
+                        .send(PaymentEvent::InvoiceSettled {
+                            payment_hash: invoice.payment_hash,
+                            amount: invoice.amount,
+                        })
+                        .await;
+                    return;
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!("Error checking invoice {}: {}", invoice.payment_hash, e),
+            }
+        }
+        println!("Invoice {} expired", invoice.payment_hash);
+        let _ = events
+            .send(PaymentEvent::InvoiceExpired {
+                payment_hash: invoice.payment_hash,
+            })
+            .await;
+    }
+
+    /// Sends `request` to the wallet and waits for the matching response.
+    async fn request(&self, request: Request) -> Result<Response, PaymentError> {
+        let event = request
+            .to_event(&self.uri)
+            .map_err(|e| PaymentError::Nostr(e.to_string()))?;
+
+        // Subscribe before sending: responses are ephemeral and never stored by relays
+        let mut notifications = self.client.notifications();
+        let filter = Filter::new()
+            .kind(Kind::WalletConnectResponse)
+            .author(self.uri.public_key)
+            .event(event.id);
+        let subscription = self
+            .client
+            .subscribe(filter, None)
+            .await
+            .map_err(|e| PaymentError::Nostr(e.to_string()))?;
+
+        self.client
+            .send_event(&event)
+            .await
+            .map_err(|e| PaymentError::Nostr(e.to_string()))?;
+
+        let response = tokio::time::timeout(self.request_timeout, async {
+            loop {
+                match notifications.recv().await {
+                    Ok(RelayPoolNotification::Event { event: reply, .. })
+                        if reply.kind == Kind::WalletConnectResponse
+                            && reply.pubkey == self.uri.public_key
+                            && reply.tags.event_ids().any(|id| *id == event.id) =>
+                    {
+                        return Response::from_event(&self.uri, &reply)
+                            .map_err(|e| PaymentError::Wallet(e.to_string()));
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => {
+                        return Err(PaymentError::Nostr("notifications closed".to_string()))
+                    }
+                }
+            }
+        })
+        .await;
+
+        self.client.unsubscribe(&subscription.val).await;
+
+        let response = response.map_err(|_| {
+            PaymentError::Timeout(format!("no response from wallet to {}", event.id))
+        })??;
+        if let Some(error) = response.error {
+            return Err(PaymentError::Wallet(error.to_string()));
+        }
+        Ok(response)
+    }
+}
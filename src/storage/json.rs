@@ -0,0 +1,81 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{InventoryStore, StorageError};
+use crate::vending_machine::Item;
+
+/// Stores the inventory as a JSON array in a single file.
+///
+/// Saves write to a temporary file next to the target and rename it over the old one, so a
+/// crash in the middle of a save never leaves a truncated inventory behind.
+pub struct JsonFileStore {
+    path: PathBuf,
+}
+
+impl JsonFileStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl InventoryStore for JsonFileStore {
+    fn load(&self) -> Result<Vec<Item>, StorageError> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let raw = fs::read_to_string(&self.path).map_err(|e| StorageError::Io(e.to_string()))?;
+        serde_json::from_str(&raw).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+
+    fn save(&self, items: &[Item]) -> Result<(), StorageError> {
+        let raw = serde_json::to_string_pretty(items)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, raw).map_err(|e| StorageError::Io(e.to_string()))?;
+        fs::rename(&tmp, &self.path).map_err(|e| StorageError::Io(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "vm-inventory-{}-{}.json",
+            name,
+            nostr_sdk::Keys::generate().public_key().to_hex()
+        ))
+    }
+
+    #[test]
+    fn test_load_missing_file_is_empty() {
+        let store = JsonFileStore::new(temp_path("missing"));
+        assert!(store.load().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let path = temp_path("roundtrip");
+        let store = JsonFileStore::new(&path);
+        let items = vec![
+            Item::new(1, "Water".to_string(), 100, 5),
+            Item::new(2, "Chips".to_string(), 150, 0),
+        ];
+        store.save(&items).unwrap();
+
+        let mut loaded = JsonFileStore::new(&path).load().unwrap();
+        loaded.sort_by_key(|item| item.id);
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].name, "Water");
+        assert_eq!(loaded[1].price, 150);
+
+        fs::remove_file(path).unwrap();
+    }
+}
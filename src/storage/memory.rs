@@ -0,0 +1,32 @@
+use std::sync::Mutex;
+
+use super::{InventoryStore, StorageError};
+use crate::vending_machine::Item;
+
+/// Volatile store keeping the last saved snapshot in memory.
+///
+/// Useful for tests and for machines that should start empty on every boot.
+#[derive(Default)]
+pub struct MemoryStore {
+    items: Mutex<Vec<Item>>,
+}
+
+impl MemoryStore {
+    /// Creates a store that will return `items` on the first load.
+    pub fn with_items(items: Vec<Item>) -> Self {
+        Self {
+            items: Mutex::new(items),
+        }
+    }
+}
+
+impl InventoryStore for MemoryStore {
+    fn load(&self) -> Result<Vec<Item>, StorageError> {
+        Ok(self.items.lock().unwrap().clone())
+    }
+
+    fn save(&self, items: &[Item]) -> Result<(), StorageError> {
+        *self.items.lock().unwrap() = items.to_vec();
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+mod json;
+mod memory;
+mod sqlite;
+
+use std::fmt::Display;
+
+use serde::Deserialize;
+
+use crate::vending_machine::Item;
+
+pub use json::JsonFileStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+/// Enum representing errors related to inventory persistence.
+#[derive(Debug)]
+pub enum StorageError {
+    /// Reading or writing the backing file failed.
+    Io(String),
+
+    /// The stored inventory could not be (de)serialized.
+    Serialization(String),
+
+    /// The SQLite database returned an error.
+    Sqlite(String),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(s) => write!(f, "StorageError::Io: {}", s),
+            Self::Serialization(s) => write!(f, "StorageError::Serialization: {}", s),
+            Self::Sqlite(s) => write!(f, "StorageError::Sqlite: {}", s),
+        }
+    }
+}
+
+/// Persistence backend for the machine inventory.
+///
+/// The vending machine loads the inventory once when it is created and saves a full snapshot
+/// after every successful change to the menu or stock.
+pub trait InventoryStore: Send + Sync {
+    /// Loads the persisted inventory. An empty store returns an empty list.
+    fn load(&self) -> Result<Vec<Item>, StorageError>;
+
+    /// Replaces the persisted inventory with `items`.
+    fn save(&self, items: &[Item]) -> Result<(), StorageError>;
+}
+
+/// Available storage backends.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Single JSON file, rewritten atomically on every save
+    #[default]
+    Json,
+    /// Embedded SQLite database
+    Sqlite,
+    /// Nothing is persisted
+    Memory,
+}
+
+/// `[storage]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageConfig {
+    /// Backend used to persist the inventory
+    #[serde(default)]
+    pub backend: StorageBackend,
+
+    /// Path of the JSON file or SQLite database
+    #[serde(default = "default_storage_path")]
+    pub path: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::default(),
+            path: default_storage_path(),
+        }
+    }
+}
+
+fn default_storage_path() -> String {
+    "inventory.json".to_string()
+}
+
+impl StorageConfig {
+    /// Opens the configured backend.
+    pub fn open(&self) -> Result<Box<dyn InventoryStore>, StorageError> {
+        Ok(match self.backend {
+            StorageBackend::Json => Box::new(JsonFileStore::new(&self.path)),
+            StorageBackend::Sqlite => Box::new(SqliteStore::open(&self.path)?),
+            StorageBackend::Memory => Box::new(MemoryStore::default()),
+        })
+    }
+}
@@ -0,0 +1,103 @@
+use std::{path::Path, sync::Mutex};
+
+use rusqlite::{params, Connection};
+
+use super::{InventoryStore, StorageError};
+use crate::vending_machine::Item;
+
+/// Stores the inventory in an embedded SQLite database.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path` and ensures the schema exists.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path).map_err(sqlite_error)?)
+    }
+
+    /// Opens a private in-memory database.
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::init(Connection::open_in_memory().map_err(sqlite_error)?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS items (
+                id    INTEGER PRIMARY KEY,
+                name  TEXT NOT NULL,
+                price INTEGER NOT NULL,
+                count INTEGER NOT NULL
+            );",
+        )
+        .map_err(sqlite_error)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn sqlite_error(e: rusqlite::Error) -> StorageError {
+    StorageError::Sqlite(e.to_string())
+}
+
+impl InventoryStore for SqliteStore {
+    fn load(&self) -> Result<Vec<Item>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT id, name, price, count FROM items ORDER BY id")
+            .map_err(sqlite_error)?;
+        let items = stmt
+            .query_map([], |row| {
+                Ok(Item::new(
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                ))
+            })
+            .map_err(sqlite_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sqlite_error)?;
+        Ok(items)
+    }
+
+    fn save(&self, items: &[Item]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(sqlite_error)?;
+        tx.execute("DELETE FROM items", []).map_err(sqlite_error)?;
+        for item in items {
+            tx.execute(
+                "INSERT INTO items (id, name, price, count) VALUES (?1, ?2, ?3, ?4)",
+                params![item.id, item.name, item.price, item.count],
+            )
+            .map_err(sqlite_error)?;
+        }
+        tx.commit().map_err(sqlite_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_replaces_previous_snapshot() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        store
+            .save(&[
+                Item::new(1, "Water".to_string(), 100, 5),
+                Item::new(2, "Chips".to_string(), 150, 3),
+            ])
+            .unwrap();
+        store
+            .save(&[Item::new(2, "Chips".to_string(), 175, 2)])
+            .unwrap();
+
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].id, 2);
+        assert_eq!(loaded[0].price, 175);
+        assert_eq!(loaded[0].count, 2);
+    }
+}
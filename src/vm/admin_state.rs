@@ -1,7 +1,11 @@
-use crate::vm::listening_state::ListeningState;
+use crate::{journal::JournalEvent, vm::listening_state::ListeningState};
 
-use super::{state::State, vending_machine::VendingMachine};
+use super::{
+    state::{Reject, State, Transition},
+    vending_machine::VendingMachine,
+};
 
+#[derive(Clone)]
 pub struct AdminState;
 
 impl AdminState {
@@ -13,12 +17,10 @@ impl AdminState {
 impl State for AdminState {
     fn show_commands(&self) {}
 
-    fn cancel(
-        self: Box<Self>,
-        vm: &mut VendingMachine,
-    ) -> Result<Box<dyn State>, super::vending_machine::VendingMachineError> {
+    fn cancel(self: Box<Self>, vm: &mut VendingMachine) -> Transition {
         println!("leaving admin state");
         vm.under_admin = false;
+        vm.record(JournalEvent::AdminExited);
         Ok(Box::new(ListeningState))
     }
 
@@ -26,18 +28,22 @@ impl State for AdminState {
         self: Box<Self>,
         vm: &mut super::vending_machine::VendingMachine,
         item: super::vending_machine::Item,
-    ) -> Result<Box<dyn State>, super::vending_machine::VendingMachineError> {
-        vm.increment_item_count(item)?;
-        Ok(self)
+    ) -> Transition {
+        match vm.increment_item_count(item) {
+            Ok(()) => Ok(self),
+            Err(e) => Err(self.reject(e)),
+        }
     }
 
     fn remove_item(
         self: Box<Self>,
         vm: &mut super::vending_machine::VendingMachine,
         item_id: u64,
-    ) -> Result<Box<dyn State>, super::vending_machine::VendingMachineError> {
-        vm.remove_item_from_menu(item_id)?;
-        Ok(self)
+    ) -> Transition {
+        match vm.remove_item_from_menu(item_id) {
+            Ok(()) => Ok(self),
+            Err(e) => Err(self.reject(e)),
+        }
     }
 
     fn change_price(
@@ -45,8 +51,10 @@ impl State for AdminState {
         vm: &mut super::vending_machine::VendingMachine,
         item_id: u64,
         new_price: u64,
-    ) -> Result<Box<dyn State>, super::vending_machine::VendingMachineError> {
-        vm.change_item_price(item_id, new_price)?;
-        Ok(self)
+    ) -> Transition {
+        match vm.change_item_price(item_id, new_price) {
+            Ok(()) => Ok(self),
+            Err(e) => Err(self.reject(e)),
+        }
     }
 }
@@ -0,0 +1,54 @@
+use super::vending_machine::{Item, VendingMachine};
+
+/// Units of one item in the cart, priced when they were requested.
+#[derive(Debug, Clone)]
+pub(crate) struct CartLine {
+    pub item: Item,
+    pub quantity: u64,
+}
+
+/// Items a customer requested before paying.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Cart {
+    lines: Vec<CartLine>,
+}
+
+impl Cart {
+    /// Adds `quantity` units of `item`, merging them with units already in the cart.
+    pub fn add(&mut self, item: Item, quantity: u64) {
+        match self.lines.iter_mut().find(|line| line.item.id == item.id) {
+            Some(line) => line.quantity += quantity,
+            None => self.lines.push(CartLine { item, quantity }),
+        }
+    }
+
+    /// Releases the units reserved for every line of the cart.
+    pub fn release(&self, vm: &mut VendingMachine) {
+        for line in &self.lines {
+            vm.release(line.item.id, line.quantity);
+        }
+    }
+
+    /// Price of everything in the cart.
+    pub fn total(&self) -> u64 {
+        self.lines
+            .iter()
+            .map(|line| line.item.price * line.quantity)
+            .sum()
+    }
+
+    pub fn lines(&self) -> &[CartLine] {
+        &self.lines
+    }
+
+    /// Shows every line and the running total on the machine's display.
+    pub fn show(&self, vm: &mut VendingMachine) {
+        for line in &self.lines {
+            vm.show(&format!(
+                "{} x {} (id: {}) at {} units",
+                line.quantity, line.item.name, line.item.id, line.item.price
+            ));
+        }
+        vm.show(&format!("total: {} units", self.total()));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::hardware::DispenseFault;
+
+use super::{
+    listening_state::ListeningState,
+    state::{Reject, State, Transition},
+    vending_machine::{VendingMachine, VendingMachineError},
+};
+
+/// A unit failed to dispense. Its slot is out of service until an admin clears the fault; other
+/// items can still be sold.
+#[derive(Clone)]
+pub(crate) struct FaultState {
+    item_id: u64,
+    fault: DispenseFault,
+}
+
+impl FaultState {
+    pub fn new(item_id: u64, fault: DispenseFault) -> Self {
+        Self { item_id, fault }
+    }
+}
+
+impl State for FaultState {
+    fn request_item(
+        self: Box<Self>,
+        vm: &mut VendingMachine,
+        item_id: u64,
+        quantity: u64,
+    ) -> Transition {
+        // The fault keeps being reported until something else is requested
+        match Box::new(ListeningState).request_item(vm, item_id, quantity) {
+            Ok(next) => Ok(next),
+            Err(rejected) => Err(self.reject(rejected.error)),
+        }
+    }
+
+    fn insert_money(self: Box<Self>, _vm: &mut VendingMachine, _money: u64) -> Transition {
+        Err(self.reject(VendingMachineError::InsertMoney("Request item first")))
+    }
+
+    fn dispense_item(self: Box<Self>, _vm: &mut VendingMachine) -> Transition {
+        Err(self.reject(VendingMachineError::Dispense("Request item first")))
+    }
+
+    fn cancel(self: Box<Self>, vm: &mut VendingMachine) -> Transition {
+        vm.show(&format!(
+            "item {} is out of service ({}), waiting for an admin",
+            self.item_id, self.fault
+        ));
+        Ok(self)
+    }
+
+    fn show_commands(&self) {
+        println!(
+            "Item {} out of service ({}). Commands: (2) requestItem",
+            self.item_id, self.fault
+        );
+    }
+
+    fn admin(self: Box<Self>, vm: &mut VendingMachine) -> Transition {
+        Box::new(ListeningState)
+            .admin(vm)
+            .map_err(|rejected| self.reject(rejected.error))
+    }
+}
@@ -1,72 +1,99 @@
+use crate::journal::JournalEvent;
+
 use super::{
+    cart::Cart,
+    fault_state::FaultState,
     listening_state::ListeningState,
-    state::State,
+    state::{Reject, State, Transition},
     vending_machine::{Item, VendingMachine, VendingMachineError},
 };
 
+#[derive(Clone)]
 pub(crate) struct HasMoneyState {
-    paid_item_id: u64,
+    cart: Cart,
+    /// Total money inserted
     money: u64,
 }
 
 impl HasMoneyState {
-    pub fn new(paid_item_id: u64, money: u64) -> Self {
-        Self {
-            paid_item_id,
-            money,
-        }
+    pub fn new(cart: Cart, money: u64) -> Self {
+        Self { cart, money }
     }
 }
 
 impl State for HasMoneyState {
     fn request_item(
         self: Box<Self>,
-        _vm: &VendingMachine,
+        _vm: &mut VendingMachine,
         _item_id: u64,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::RequestItem(
+        _quantity: u64,
+    ) -> Transition {
+        Err(self.reject(VendingMachineError::RequestItem(
             "Item dispense in progress",
-        ))
+        )))
     }
 
-    fn add_item(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-        _item: Item,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::AddItem("Item dispense in progress"))
+    fn add_item(self: Box<Self>, _vm: &mut VendingMachine, _item: Item) -> Transition {
+        Err(self.reject(VendingMachineError::AddItem("Item dispense in progress")))
     }
 
-    fn insert_money(self: Box<Self>, _money: u64) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::InsertMoney(
+    fn insert_money(self: Box<Self>, _vm: &mut VendingMachine, _money: u64) -> Transition {
+        Err(self.reject(VendingMachineError::InsertMoney(
             "Item dispense in progress",
-        ))
+        )))
     }
 
-    fn dispense_item(
-        self: Box<Self>,
-        vm: &mut VendingMachine,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        println!(
-            "Dispensing Item {} (id: {})",
-            vm.get_item(self.paid_item_id).unwrap().name,
-            self.paid_item_id
-        );
+    fn dispense_item(self: Box<Self>, vm: &mut VendingMachine) -> Transition {
+        // Dispensing stops at the first fault. Units that were not dispensed are refunded
+        // together with the overpayment.
+        let mut charged = 0;
+        let mut fault = None;
+        for line in self.cart.lines() {
+            for _ in 0..line.quantity {
+                if fault.is_none() {
+                    match vm.dispense_unit(line.item.id) {
+                        Ok(()) => charged += line.item.price,
+                        Err(e) => {
+                            vm.show(&format!(
+                                "Could not dispense Item {} (id: {}): {}",
+                                line.item.name, line.item.id, e
+                            ));
+                            fault = Some((line.item.id, e));
+                        }
+                    }
+                }
+                vm.release(line.item.id, 1);
+            }
+        }
 
-        vm.sell_item_unit(self.paid_item_id);
+        let change = self.money - charged;
+        if change > 0 {
+            vm.show(&format!("returning change: {} units", change));
+            vm.return_change(change);
+        }
+        if let Some((item_id, fault)) = fault {
+            vm.report_fault(item_id, fault);
+            return Ok(Box::new(FaultState::new(item_id, fault)));
+        }
         Ok(Box::new(ListeningState))
     }
 
-    fn cancel(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        println!("paying back money: {} units", self.money);
-        println!("cancel");
+    fn cancel(self: Box<Self>, vm: &mut VendingMachine) -> Transition {
+        self.cart.release(vm);
+        vm.show(&format!("paying back money: {} units", self.money));
+        vm.refund(self.money);
+        vm.record(JournalEvent::Cancelled {
+            refunded: self.money,
+        });
+        vm.show("cancel");
         Ok(Box::new(ListeningState))
     }
 
     fn show_commands(&self) {
         println!("Commands: (4) dispenseItem (5) cancel");
     }
+
+    fn in_transaction(&self) -> bool {
+        true
+    }
 }
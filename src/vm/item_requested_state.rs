@@ -1,56 +1,111 @@
+use crate::journal::JournalEvent;
+
 use super::{
+    cart::Cart,
     has_money_state::HasMoneyState,
-    state::State,
+    listening_state::ListeningState,
+    state::{Reject, State, Transition},
     vending_machine::{Item, VendingMachine, VendingMachineError},
 };
 
+#[derive(Clone)]
 pub(crate) struct ItemRequestedState {
-    item: Item,
+    cart: Cart,
+    /// Money inserted so far
+    credit: u64,
 }
 
 impl ItemRequestedState {
-    pub fn new(item: Item) -> Self {
-        Self { item }
+    pub fn new(cart: Cart) -> Self {
+        Self { cart, credit: 0 }
     }
 }
 
 impl State for ItemRequestedState {
-    fn add_item(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-        _item: Item,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::AddItem("Item dispense in progress"))
+    fn add_item(self: Box<Self>, _vm: &mut VendingMachine, _item: Item) -> Transition {
+        Err(self.reject(VendingMachineError::AddItem("Item dispense in progress")))
     }
 
     fn request_item(
-        self: Box<Self>,
-        _vm: &VendingMachine,
-        _item_id: u64,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::RequestItem("Requested another item"))
-    }
-
-    fn insert_money(self: Box<Self>, money: u64) -> Result<Box<dyn State>, VendingMachineError> {
-        if money != self.item.price {
-            println!(
-                "Inserted wrong amount: {} units, please insert {} units",
-                money, self.item.price,
-            );
-            return Ok(self);
+        mut self: Box<Self>,
+        vm: &mut VendingMachine,
+        item_id: u64,
+        quantity: u64,
+    ) -> Transition {
+        let Some(item) = vm.get_item(item_id).cloned() else {
+            vm.show(&format!("invalid item id: {}", item_id));
+            return Err(self.reject(VendingMachineError::ItemDoesNotExist(item_id)));
+        };
+        if !vm.reserve(item_id, quantity) {
+            let available = vm.available(item_id);
+            vm.show(&format!(
+                "Only {} more of {} (id: {}) available",
+                available, item.name, item.id
+            ));
+            return Err(self.reject(VendingMachineError::InvalidLine {
+                item_id,
+                reason: format!("only {} more available", available),
+            }));
+        }
+        vm.show(&format!(
+            "Item id:{} - name:{} added to cart",
+            item_id, item.name
+        ));
+        vm.record(JournalEvent::ItemRequested {
+            item_id,
+            price: item.price,
+            quantity,
+        });
+        self.cart.add(item, quantity);
+        self.cart.show(vm);
+        Ok(self)
+    }
+
+    fn insert_money(self: Box<Self>, vm: &mut VendingMachine, mut money: u64) -> Transition {
+        vm.record(JournalEvent::MoneyInserted { amount: money });
+        money += self.credit;
+        let total = self.cart.total();
+        if money < total {
+            vm.show(&format!(
+                "Credit: {} units, please insert {} more units",
+                money,
+                total - money,
+            ));
+            return Ok(Box::new(Self {
+                cart: self.cart,
+                credit: money,
+            }));
         }
-        println!("Money entered is ok: {} units", money);
-        Ok(Box::new(HasMoneyState::new(self.item.id, money)))
+        vm.show(&format!("Money entered is ok: {} units", money));
+        Ok(Box::new(HasMoneyState::new(self.cart, money)))
     }
 
-    fn dispense_item(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::Dispense("Insert money first"))
+    fn dispense_item(self: Box<Self>, _vm: &mut VendingMachine) -> Transition {
+        Err(self.reject(VendingMachineError::Dispense("Insert money first")))
+    }
+
+    fn cancel(self: Box<Self>, vm: &mut VendingMachine) -> Transition {
+        self.cart.release(vm);
+        if self.credit > 0 {
+            vm.show(&format!("paying back money: {} units", self.credit));
+            vm.refund(self.credit);
+        }
+        vm.show("cancel");
+        vm.record(JournalEvent::Cancelled {
+            refunded: self.credit,
+        });
+        Ok(Box::new(ListeningState))
     }
 
     fn show_commands(&self) {
-        println!("Commands: (3) insertMoney (5) cancel");
+        println!("Commands: (2) requestItem (3) insertMoney (5) cancel");
+    }
+
+    fn amount_due(&self) -> Option<u64> {
+        Some(self.cart.total().saturating_sub(self.credit))
+    }
+
+    fn in_transaction(&self) -> bool {
+        true
     }
 }
@@ -1,51 +1,92 @@
+use crate::journal::JournalEvent;
+
 use super::{
     admin_state::AdminState,
+    cart::Cart,
     item_requested_state::ItemRequestedState,
-    state::State,
+    state::{Reject, State, Transition},
     vending_machine::{VendingMachine, VendingMachineError},
 };
 
+#[derive(Clone)]
 pub(crate) struct ListeningState;
 
 impl State for ListeningState {
     fn request_item(
         self: Box<Self>,
-        vm: &VendingMachine,
+        vm: &mut VendingMachine,
         item_id: u64,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        if let Some(item) = vm.get_item(item_id) {
-            if item.count == 0 {
-                println!("Item {} (id: {}) out of stock", item.name, item.id);
-                return Ok(self);
-            }
-            println!("Item id:{} - name:{} requested", item_id, item.name);
-            return Ok(Box::new(ItemRequestedState::new(item.clone())));
+        quantity: u64,
+    ) -> Transition {
+        let Some(item) = vm.get_item(item_id).cloned() else {
+            vm.show(&format!("invalid item id: {}", item_id));
+            return Err(self.reject(VendingMachineError::ItemDoesNotExist(item_id)));
+        };
+        if vm.is_out_of_service(item_id) {
+            vm.show(&format!(
+                "Item {} (id: {}) out of service",
+                item.name, item.id
+            ));
+            return Err(self.reject(VendingMachineError::InvalidLine {
+                item_id,
+                reason: "out of service".to_string(),
+            }));
+        }
+        if item.count == 0 {
+            vm.show(&format!(
+                "Item {} (id: {}) out of stock",
+                item.name, item.id
+            ));
+            return Err(self.reject(VendingMachineError::InvalidLine {
+                item_id,
+                reason: "out of stock".to_string(),
+            }));
+        }
+        if !vm.reserve(item_id, quantity) {
+            let available = vm.available(item_id);
+            vm.show(&format!(
+                "Only {} of {} (id: {}) available",
+                available, item.name, item.id
+            ));
+            return Err(self.reject(VendingMachineError::InvalidLine {
+                item_id,
+                reason: format!("only {} available", available),
+            }));
         }
-        println!("invalid item id: {}", item_id);
-        Ok(self)
+        vm.show(&format!(
+            "Item id:{} - name:{} requested",
+            item_id, item.name
+        ));
+        if vm.exact_change_only() {
+            vm.show("Exact change only");
+        }
+        vm.record(JournalEvent::ItemRequested {
+            item_id,
+            price: item.price,
+            quantity,
+        });
+        let mut cart = Cart::default();
+        cart.add(item, quantity);
+        cart.show(vm);
+        Ok(Box::new(ItemRequestedState::new(cart)))
     }
 
-    fn dispense_item(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::Dispense("Request item first"))
+    fn dispense_item(self: Box<Self>, _vm: &mut VendingMachine) -> Transition {
+        Err(self.reject(VendingMachineError::Dispense("Request item first")))
     }
 
-    fn insert_money(self: Box<Self>, _money: u64) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::InsertMoney("Request item first"))
+    fn insert_money(self: Box<Self>, _vm: &mut VendingMachine, _money: u64) -> Transition {
+        Err(self.reject(VendingMachineError::InsertMoney("Request item first")))
     }
 
     fn show_commands(&self) {
         println!("Commands: (1) addItem (2) requestItem");
     }
 
-    fn admin(
-        self: Box<Self>,
-        vm: &mut VendingMachine,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
+    fn admin(self: Box<Self>, vm: &mut VendingMachine) -> Transition {
         println!("admin state");
         vm.under_admin = true;
+        vm.record(JournalEvent::AdminEntered);
         Ok(Box::new(AdminState::new()))
     }
 }
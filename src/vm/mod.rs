@@ -1,6 +1,7 @@
 mod admin_state;
+mod cart;
+mod fault_state;
 mod has_money_state;
-mod helper;
 mod item_requested_state;
 mod listening_state;
 mod state;
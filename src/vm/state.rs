@@ -2,77 +2,111 @@ use crate::vm::listening_state::ListeningState;
 
 use super::vending_machine::{Item, VendingMachine, VendingMachineError};
 
-pub(crate) trait State: Send + Sync {
+/// A command the current state refused. The state is handed back unchanged, so the machine keeps
+/// the credit and cart of the customer.
+pub(crate) struct Rejected {
+    pub state: Box<dyn State>,
+    pub error: VendingMachineError,
+}
+
+/// The state a command leads to, or why it was refused.
+pub(crate) type Transition = Result<Box<dyn State>, Rejected>;
+
+pub(crate) trait Reject {
+    /// Refuses a command with `error`, handing the state back.
+    fn reject(self: Box<Self>, error: VendingMachineError) -> Rejected;
+}
+
+impl<T: State + 'static> Reject for T {
+    fn reject(self: Box<Self>, error: VendingMachineError) -> Rejected {
+        Rejected { state: self, error }
+    }
+}
+
+pub(crate) trait Snapshot {
+    /// Copies the state, so it can be put back if a batch of admin commands fails.
+    fn snapshot(&self) -> Box<dyn State>;
+}
+
+impl<T: State + Clone + 'static> Snapshot for T {
+    fn snapshot(&self) -> Box<dyn State> {
+        Box::new(self.clone())
+    }
+}
+
+pub(crate) trait State: Reject + Snapshot + Send + Sync {
     // user commands
     fn request_item(
         self: Box<Self>,
-        _vm: &VendingMachine,
+        _vm: &mut VendingMachine,
         _item_id: u64,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::Unauthorized(
+        _quantity: u64,
+    ) -> Transition {
+        Err(self.reject(VendingMachineError::Unauthorized(
             "Cannot request item in current state",
-        ))
+        )))
     }
 
-    fn insert_money(self: Box<Self>, _money: u64) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::Unauthorized(
+    fn insert_money(self: Box<Self>, _vm: &mut VendingMachine, _money: u64) -> Transition {
+        Err(self.reject(VendingMachineError::Unauthorized(
             "Cannot insert moneey in current state",
-        ))
+        )))
     }
-    fn dispense_item(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::Unauthorized(
+    fn dispense_item(self: Box<Self>, _vm: &mut VendingMachine) -> Transition {
+        Err(self.reject(VendingMachineError::Unauthorized(
             "Cannot dispense item in current state",
-        ))
+        )))
     }
 
     // generics
     fn show_commands(&self);
-    fn cancel(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
+
+    /// Name of the concrete state, e.g. `ListeningState`
+    fn name(&self) -> &'static str {
+        // Extract just the struct name from the fully qualified path
+        let type_name = std::any::type_name::<Self>();
+        type_name.rsplit("::").next().unwrap_or(type_name)
+    }
+
+    /// Amount the customer still has to pay in this state, if any
+    fn amount_due(&self) -> Option<u64> {
+        None
+    }
+
+    /// Whether a customer is in the middle of a purchase in this state
+    fn in_transaction(&self) -> bool {
+        false
+    }
+
+    fn cancel(self: Box<Self>, _vm: &mut VendingMachine) -> Transition {
         println!("cancel");
         Ok(Box::new(ListeningState))
     }
 
     // admin methods
-    fn admin(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::Unauthorized(
+    fn admin(self: Box<Self>, _vm: &mut VendingMachine) -> Transition {
+        Err(self.reject(VendingMachineError::Unauthorized(
             "Cannot go to admin in current state",
-        ))
+        )))
     }
-    fn add_item(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-        _item: Item,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::Unauthorized(
+    fn add_item(self: Box<Self>, _vm: &mut VendingMachine, _item: Item) -> Transition {
+        Err(self.reject(VendingMachineError::Unauthorized(
             "Cannot add items in current state",
-        ))
+        )))
     }
-    fn remove_item(
-        self: Box<Self>,
-        _vm: &mut VendingMachine,
-        _item_id: u64,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::Unauthorized(
+    fn remove_item(self: Box<Self>, _vm: &mut VendingMachine, _item_id: u64) -> Transition {
+        Err(self.reject(VendingMachineError::Unauthorized(
             "Cannot remove items in current state",
-        ))
+        )))
     }
     fn change_price(
         self: Box<Self>,
         _vm: &mut VendingMachine,
         _item_id: u64,
         _new_price: u64,
-    ) -> Result<Box<dyn State>, VendingMachineError> {
-        Err(VendingMachineError::Unauthorized(
+    ) -> Transition {
+        Err(self.reject(VendingMachineError::Unauthorized(
             "Cannot change price in current state",
-        ))
+        )))
     }
 }
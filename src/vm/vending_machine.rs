@@ -1,10 +1,43 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+};
 
-use serde::Serialize;
-use tokio::{sync::mpsc, time::Instant};
+use nostr_sdk::{
+    nips::nip15::{MerchantPaymentRequest, MerchantVerifyPayment, PaymentOption},
+    JsonUtil,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
 
-use super::{helper, listening_state::ListeningState, state::State};
-use crate::admin::{commands::AdminCommand, AdminError};
+use super::{
+    listening_state::ListeningState,
+    state::{Rejected, State, Transition},
+};
+use crate::{
+    admin::{
+        commands::{
+            AdminCommand, AdminCommandResult, AdminRequest, AdminResponse, RelayReport,
+            StatusReport,
+        },
+        AdminError,
+    },
+    cash::{self, CashBox, CashConfig},
+    customer::{
+        nostr::{send_message, DmProtocol},
+        Customer, CustomerEvent, OrderItem, RemoteCustomerEvent,
+    },
+    hardware::{DispenseFault, Dispenser, Hardware},
+    journal::{self, Journal, JournalEntry, JournalEvent},
+    keys::KeysError,
+    market::{self, MarketConfig},
+    payment::{
+        cashu::{CashuMint, Token},
+        nwc::NwcWallet,
+        Invoice, PaymentError, PaymentEvent,
+    },
+    storage::{InventoryStore, StorageError},
+};
 
 #[derive(Debug)]
 pub enum VendingMachineError {
@@ -14,10 +47,19 @@ pub enum VendingMachineError {
     InsertMoney(&'static str),
     RequestItem(&'static str),
     Unauthorized(&'static str),
+    Busy(&'static str),
     AdminError(AdminError),
     ItemDoesNotExist(u64),
+    /// A line of an order or cart cannot be served
+    InvalidLine {
+        item_id: u64,
+        reason: String,
+    },
     Nostr(nostr_sdk::client::Error),
     Config(String),
+    Keys(KeysError),
+    Storage(StorageError),
+    Payment(PaymentError),
 }
 
 impl Display for VendingMachineError {
@@ -29,17 +71,28 @@ impl Display for VendingMachineError {
             Self::AddItem(s) => write!(f, "VendingMachineError::AddItem: {}", s),
             Self::RequestItem(s) => write!(f, "VendingMachineError::RequestItem: {}", s),
             Self::Unauthorized(s) => write!(f, "VendingMachineError::Unauthorized: {}", s),
+            Self::Busy(s) => write!(f, "VendingMachineError::Busy: {}", s),
             Self::AdminError(s) => write!(f, "VendingMachineError::AdminError: {:?}", s),
             Self::ItemDoesNotExist(s) => {
                 write!(f, "VendingMachineError::ItemDoesNotExist: {:?}", s)
             }
+            Self::InvalidLine { item_id, reason } => {
+                write!(
+                    f,
+                    "VendingMachineError::InvalidLine: item {}: {}",
+                    item_id, reason
+                )
+            }
             Self::Nostr(s) => write!(f, "VendingMachineError::Nostr: {:?}", s),
             Self::Config(msg) => write!(f, "Configuration error: {}", msg),
+            Self::Keys(e) => write!(f, "VendingMachineError::Keys: {}", e),
+            Self::Storage(e) => write!(f, "VendingMachineError::Storage: {}", e),
+            Self::Payment(e) => write!(f, "VendingMachineError::Payment: {}", e),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item {
     pub id: u64,
     pub name: String,
@@ -66,20 +119,139 @@ impl Item {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Addressable event kind (NIP-78 app-specific data) the machine state is published as.
+pub const STATE_EVENT_KIND: u16 = 30078;
+
+/// `d` tag of the state event. Relays keep only the latest event per author, kind and `d` tag.
+pub const STATE_EVENT_IDENTIFIER: &str = "vending_machine_state";
+
+/// Version of the [`VendingMachineUpdate`] schema. Bump it on incompatible changes.
+pub const STATE_SCHEMA_VERSION: u32 = 1;
+
+/// Filter matching the current state event published by `machine`.
+pub fn state_filter(machine: nostr_sdk::PublicKey) -> nostr_sdk::Filter {
+    nostr_sdk::Filter::new()
+        .kind(nostr_sdk::Kind::Custom(STATE_EVENT_KIND))
+        .author(machine)
+        .identifier(STATE_EVENT_IDENTIFIER)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VendingMachineUpdate {
+    /// Schema version, see [`STATE_SCHEMA_VERSION`]
+    pub version: u32,
+    /// `false` once the machine shut down
+    #[serde(default = "online")]
+    pub online: bool,
     pub under_admin: bool,
     pub items: Vec<Item>,
     pub state: String,
+    /// The float cannot give change for every listed price
+    pub exact_change_only: bool,
+    /// Items whose slot is out of service
+    pub out_of_service: Vec<u64>,
+}
+
+fn online() -> bool {
+    true
+}
+
+impl VendingMachineUpdate {
+    /// Parses the content of a state event, rejecting schema versions this build does not know.
+    pub fn parse(content: &str) -> Result<Self, VendingMachineError> {
+        let update: Self = serde_json::from_str(content)
+            .map_err(|e| VendingMachineError::Config(e.to_string()))?;
+        if update.version != STATE_SCHEMA_VERSION {
+            return Err(VendingMachineError::Config(format!(
+                "unsupported state schema version {}",
+                update.version
+            )));
+        }
+        Ok(update)
+    }
+}
+
+/// How long a soft reboot waits for the relays to reconnect.
+const RECONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// How often admins are reminded of faults nobody cleared yet.
+const FAULT_REMINDER_SECS: u64 = 300;
+
+/// How long the machine waits for the wallet to create an invoice before going on without one.
+const INVOICE_TIMEOUT_SECS: u64 = 5;
+
+/// A Lightning invoice watched for settlement.
+struct WatchedInvoice {
+    /// Customer the invoice was made for
+    customer: Customer,
+    /// Purchase the invoice was made for
+    purchase: u64,
+    watcher: JoinHandle<()>,
+}
+
+/// Money to pay back to a customer once the current transition is done.
+struct Refund {
+    customer: Customer,
+    amount: u64,
+    /// Change from a purchase, journaled once it is paid
+    change: bool,
 }
 
 pub struct VendingMachine {
     pub(crate) under_admin: bool,
     state: Option<Box<dyn State>>,
     items: HashMap<u64, Item>,
-    admin_commands: mpsc::Receiver<AdminCommand>,
+    /// Units held for customers who requested them but have not been served yet
+    reservations: HashMap<u64, u64>,
+    store: Box<dyn InventoryStore>,
+    journal: Box<dyn Journal>,
+    journal_seq: u64,
+    cash_box: CashBox,
+    hardware: Hardware,
+    /// Slots that failed to dispense, until an admin clears them
+    out_of_service: HashMap<u64, DispenseFault>,
+    admin_pubkeys: Vec<nostr_sdk::PublicKey>,
+    fault_notified_at: Option<Instant>,
+    denominations: Vec<u64>,
+    acting_admin: Option<nostr_sdk::PublicKey>,
+    /// Journal events of the batch being applied, written once all of it succeeded
+    batch: Option<Vec<JournalEvent>>,
+    admin_commands: mpsc::Receiver<AdminRequest>,
+    lightning: Option<NwcWallet>,
+    /// Invoice for the amount due in the current state
+    pending_invoice: Option<Invoice>,
+    /// Invoices watched until they are paid or expire, by payment hash. Superseded ones are
+    /// still watched, so a late payment is credited or refunded.
+    watched_invoices: HashMap<String, WatchedInvoice>,
+    /// Counts the purchases that ended, identifies the one in progress
+    purchase: u64,
+    cashu: Option<CashuMint>,
+    payment_events_sender: mpsc::Sender<PaymentEvent>,
+    payment_events: mpsc::Receiver<PaymentEvent>,
+    customer_events_sender: mpsc::Sender<CustomerEvent>,
+    customer_events: mpsc::Receiver<CustomerEvent>,
+    remote_customer_events_sender: mpsc::Sender<RemoteCustomerEvent>,
+    remote_customer_events: mpsc::Receiver<RemoteCustomerEvent>,
+    /// Customer the current purchase belongs to
+    session: Option<Customer>,
+    /// How each Nostr customer last wrote to the machine
+    reply_protocols: HashMap<nostr_sdk::PublicKey, DmProtocol>,
+    /// Refunds left to settle once the current transition is done
+    refunds: Vec<Refund>,
+    /// Messages shown while serving a Nostr customer, sent to them in one DM
+    outbox: Option<Vec<String>>,
+    /// NIP-15 order the current purchase was placed with
+    order: Option<String>,
+    market: Option<MarketConfig>,
+    /// Items whose NIP-15 product listing is out of date
+    market_changes: HashSet<u64>,
+    machine_pubkey: nostr_sdk::PublicKey,
     nostr_client: nostr_sdk::Client,
     shutdown: mpsc::Receiver<bool>,
+    /// An admin asked for a shutdown, `run_machine` returns after replying
+    stop_requested: bool,
+    online: bool,
+    started_at: Instant,
     last_activity: Option<Instant>,
 }
 
@@ -87,9 +259,25 @@ impl VendingMachine {
     pub async fn new(
         nostr_keys: nostr_sdk::Keys,
         admin_relays: &[&str],
-        admin_commands: mpsc::Receiver<AdminCommand>,
+        admin_commands: mpsc::Receiver<AdminRequest>,
         shutdown: mpsc::Receiver<bool>,
+        store: Box<dyn InventoryStore>,
+        journal: Box<dyn Journal>,
     ) -> Result<Self, VendingMachineError> {
+        // Restore the inventory saved before the last shutdown
+        let items = store
+            .load()
+            .map_err(VendingMachineError::Storage)?
+            .into_iter()
+            .map(|item| (item.id, item))
+            .collect();
+        let entries = journal.entries().map_err(VendingMachineError::Storage)?;
+        let journal_seq = entries.len() as u64;
+        // The cash box is only tracked through the journal
+        let cash_box = cash::replay(&entries);
+        let out_of_service = journal::replay_faults(&entries);
+
+        let machine_pubkey = nostr_keys.public_key();
         let nostr_client = nostr_sdk::ClientBuilder::new()
             .signer(nostr_keys.clone())
             .build();
@@ -103,11 +291,49 @@ impl VendingMachine {
         }
         nostr_client.connect().await;
 
+        let (payment_events_sender, payment_events) = mpsc::channel(10);
+        let (customer_events_sender, customer_events) = mpsc::channel(10);
+        let (remote_customer_events_sender, remote_customer_events) = mpsc::channel(10);
+
         Ok(Self {
             under_admin: false,
             state: Some(Box::new(ListeningState)),
-            items: HashMap::new(),
+            items,
+            reservations: HashMap::new(),
+            store,
+            journal,
+            journal_seq,
+            cash_box,
+            hardware: Hardware::console(),
+            out_of_service,
+            admin_pubkeys: Vec::new(),
+            fault_notified_at: None,
+            denominations: CashConfig::default().denominations,
+            acting_admin: None,
+            batch: None,
             admin_commands,
+            lightning: None,
+            pending_invoice: None,
+            watched_invoices: HashMap::new(),
+            purchase: 0,
+            cashu: None,
+            payment_events_sender,
+            payment_events,
+            customer_events_sender,
+            customer_events,
+            remote_customer_events_sender,
+            remote_customer_events,
+            session: None,
+            reply_protocols: HashMap::new(),
+            refunds: Vec::new(),
+            outbox: None,
+            order: None,
+            market: None,
+            market_changes: HashSet::new(),
+            machine_pubkey,
+            stop_requested: false,
+            online: true,
+            started_at: Instant::now(),
             last_activity: None,
             shutdown,
             nostr_client,
@@ -118,34 +344,111 @@ impl VendingMachine {
         self.under_admin
     }
 
-    pub async fn send_update(&self) -> Result<(), VendingMachineError> {
-        let state_name = match self.state.as_ref() {
-            Some(state) => {
-                // Get concrete type name using std::any::type_name
-                let type_name = std::any::type_name_of_val(&**state);
-                // Extract just the struct name from the fully qualified path
-                type_name
-                    .split("::")
-                    .last()
-                    .unwrap_or("Unknown")
-                    .to_string()
-            }
+    /// Enables Lightning payments: an invoice is created whenever money is due.
+    pub fn set_lightning_wallet(&mut self, wallet: NwcWallet) {
+        self.lightning = Some(wallet);
+    }
+
+    /// Enables Cashu payments redeemed at `mint`.
+    pub fn set_cashu_mint(&mut self, mint: CashuMint) {
+        self.cashu = Some(mint);
+    }
+
+    /// Lists the machine on Nostr marketplaces as configured, see [`crate::market`].
+    pub fn set_market(&mut self, market: MarketConfig) {
+        self.market = Some(market);
+    }
+
+    /// Sets the coins and bills customers may insert, see [`CashConfig`].
+    pub fn set_denominations(&mut self, denominations: Vec<u64>) {
+        self.denominations = denominations;
+    }
+
+    /// Replaces the hardware the machine acts through, e.g. with a [`crate::hardware::Simulator`].
+    pub fn set_hardware(&mut self, hardware: Hardware) {
+        self.hardware = hardware;
+    }
+
+    /// Replaces only the dispenser units are released through.
+    pub fn set_dispenser(&mut self, dispenser: Box<dyn Dispenser>) {
+        self.hardware.dispenser = dispenser;
+    }
+
+    /// Shows `message` on the machine's display.
+    pub(crate) fn show(&mut self, message: &str) {
+        self.hardware.display.show(message);
+        if let Some(outbox) = self.outbox.as_mut() {
+            outbox.push(message.to_string());
+        }
+    }
+
+    /// Admins notified of faults.
+    pub fn set_admin_pubkeys(&mut self, admin_pubkeys: Vec<nostr_sdk::PublicKey>) {
+        self.admin_pubkeys = admin_pubkeys;
+    }
+
+    /// Coins currently held by the machine.
+    pub fn cash_box(&self) -> &CashBox {
+        &self.cash_box
+    }
+
+    /// Whether the float cannot give change for some listed item, so customers must insert the
+    /// exact amount.
+    pub fn exact_change_only(&self) -> bool {
+        self.items
+            .values()
+            .filter(|item| item.count > 0)
+            .any(|item| cash::needs_exact_change(&self.cash_box, &self.denominations, item.price))
+    }
+
+    /// Sender through which external payment providers notify the machine.
+    pub fn payment_sender(&self) -> mpsc::Sender<PaymentEvent> {
+        self.payment_events_sender.clone()
+    }
+
+    /// Sender through which customer interfaces feed the machine, see [`crate::customer`].
+    pub fn customer_sender(&self) -> mpsc::Sender<CustomerEvent> {
+        self.customer_events_sender.clone()
+    }
+
+    /// Sender through which customers ordering over Nostr DMs feed the machine, see
+    /// [`crate::customer::NostrCustomerHandler`].
+    pub fn remote_customer_sender(&self) -> mpsc::Sender<RemoteCustomerEvent> {
+        self.remote_customer_events_sender.clone()
+    }
+
+    /// Name of the current state, e.g. `ListeningState`.
+    pub fn state_name(&self) -> String {
+        match self.state.as_ref() {
+            Some(state) => state.name().to_string(),
             None => "NoState".to_string(),
-        };
+        }
+    }
 
+    pub async fn send_update(&self) -> Result<(), VendingMachineError> {
+        let state_name = self.state_name();
+
+        let mut items: Vec<Item> = self.items.values().cloned().collect();
+        items.sort_by_key(|item| item.id);
+        let mut out_of_service: Vec<u64> = self.out_of_service.keys().copied().collect();
+        out_of_service.sort_unstable();
         let update = VendingMachineUpdate {
+            version: STATE_SCHEMA_VERSION,
+            online: self.online,
             under_admin: self.under_admin,
-            items: self.items.values().cloned().collect(),
+            items,
             state: state_name,
+            exact_change_only: self.exact_change_only(),
+            out_of_service,
         };
 
         // Send the update to the Nostr client
         let event_builder = nostr_sdk::EventBuilder::new(
-            nostr_sdk::Kind::TextNote,
+            nostr_sdk::Kind::Custom(STATE_EVENT_KIND),
             serde_json::to_string(&update).unwrap(),
         )
         .tag(nostr_sdk::Tag::all_relays())
-        .tag(nostr_sdk::Tag::identifier("vending_machine_state"));
+        .tag(nostr_sdk::Tag::identifier(STATE_EVENT_IDENTIFIER));
 
         let event = self
             .nostr_client
@@ -161,29 +464,64 @@ impl VendingMachine {
 
     pub async fn update_last_activity(&mut self) -> Result<(), VendingMachineError> {
         self.last_activity = Some(Instant::now());
+        if self.batch.is_some() {
+            // Published once the whole batch is applied
+            return Ok(());
+        }
+        self.publish_market_changes().await;
         self.send_update().await
     }
 
+    /// Publishes the NIP-15 stall and a product for every item.
+    pub async fn publish_market(&mut self) -> Result<(), VendingMachineError> {
+        let Some(config) = self.market.clone() else {
+            return Ok(());
+        };
+        self.nostr_client
+            .send_event_builder(market::stall_event(&config))
+            .await
+            .map_err(VendingMachineError::Nostr)?;
+        self.market_changes.extend(self.items.keys().copied());
+        self.publish_market_changes().await;
+        Ok(())
+    }
+
+    /// Republishes the product listings of items changed since the last call and deletes the
+    /// listings of items taken off the menu.
+    async fn publish_market_changes(&mut self) {
+        let Some(config) = self.market.as_ref() else {
+            return;
+        };
+        for item_id in std::mem::take(&mut self.market_changes) {
+            let event_builder = match self.items.get(&item_id) {
+                Some(item) => market::product_event(config, item, self.available(item_id)),
+                None => market::product_deletion_event(self.machine_pubkey, item_id),
+            };
+            if let Err(e) = self.nostr_client.send_event_builder(event_builder).await {
+                eprintln!("Failed to publish listing of item {}: {}", item_id, e);
+            }
+        }
+    }
+
+    /// Marks the product listing of `item_id` for republishing.
+    fn listing_changed(&mut self, item_id: u64) {
+        if self.market.is_some() {
+            self.market_changes.insert(item_id);
+        }
+    }
+
     pub async fn add_item(&mut self, item: Item) -> Result<(), VendingMachineError> {
         if !self.under_admin {
             return Err(VendingMachineError::Unauthorized("only admin can add item"));
         }
 
-        if let Some(state) = self.state.take() {
-            self.state = Some(state.add_item(self, item)?);
-            self.update_last_activity().await?;
-            return Ok(());
-        }
-
-        Err(VendingMachineError::AddItem("invalid state"))
+        self.transition(|state, vm| state.add_item(vm, item))?;
+        self.update_last_activity().await
     }
 
     pub async fn admin(&mut self) -> Result<(), VendingMachineError> {
-        if let Some(state) = self.state.take() {
-            self.state = Some(state.admin(self)?);
-            self.update_last_activity().await?;
-        }
-        Ok(())
+        self.transition(|state, vm| state.admin(vm))?;
+        self.update_last_activity().await
     }
 
     pub async fn change_price(
@@ -191,60 +529,461 @@ impl VendingMachine {
         item_id: u64,
         new_price: u64,
     ) -> Result<(), VendingMachineError> {
-        if let Some(state) = self.state.take() {
-            self.state = Some(state.change_price(self, item_id, new_price)?);
-            self.update_last_activity().await?;
-        }
-        Ok(())
+        self.transition(|state, vm| state.change_price(vm, item_id, new_price))?;
+        self.update_last_activity().await
     }
 
     pub async fn remove_item(&mut self, item_id: u64) -> Result<(), VendingMachineError> {
-        if let Some(state) = self.state.take() {
-            self.state = Some(state.remove_item(self, item_id)?);
-            self.update_last_activity().await?;
+        self.transition(|state, vm| state.remove_item(vm, item_id))?;
+        self.update_last_activity().await
+    }
+
+    pub async fn request_item(&mut self, item_id: u64) -> Result<(), VendingMachineError> {
+        self.request_items(item_id, 1).await
+    }
+
+    /// Puts `quantity` units of `item_id` in the cart. The cart is paid for at once.
+    pub async fn request_items(
+        &mut self,
+        item_id: u64,
+        quantity: u64,
+    ) -> Result<(), VendingMachineError> {
+        if quantity == 0 {
+            return Err(VendingMachineError::RequestItem(
+                "request at least one unit",
+            ));
+        }
+        self.transition(|state, vm| state.request_item(vm, item_id, quantity))?;
+        self.sync_invoice().await;
+        self.update_last_activity().await
+    }
+
+    /// Puts every line of an order in the cart, or none if one of them cannot be served.
+    async fn request_order(&mut self, items: &[OrderItem]) -> Result<(), VendingMachineError> {
+        self.check_order(items)?;
+        for item in items {
+            self.request_items(item.id, item.quantity).await?;
         }
         Ok(())
     }
 
-    pub async fn request_item(&mut self, item_id: u64) -> Result<(), VendingMachineError> {
-        if let Some(state) = self.state.take() {
-            self.state = Some(state.request_item(self, item_id)?);
-            self.update_last_activity().await?;
-            return Ok(());
+    /// Checks that each line of an order asks for at least one unit of a listed item, and that
+    /// enough units are available for all lines of the same item.
+    fn check_order(&self, items: &[OrderItem]) -> Result<(), VendingMachineError> {
+        if items.is_empty() {
+            return Err(VendingMachineError::RequestItem("the order has no items"));
         }
-        Err(VendingMachineError::AddItem("invalid state"))
+        let mut requested: HashMap<u64, u64> = HashMap::new();
+        for line in items {
+            let invalid = |reason: String| VendingMachineError::InvalidLine {
+                item_id: line.id,
+                reason,
+            };
+            if line.quantity == 0 {
+                return Err(invalid("order at least one unit".to_string()));
+            }
+            if self.get_item(line.id).is_none() {
+                return Err(invalid("no such item".to_string()));
+            }
+            let total = requested.entry(line.id).or_insert(0);
+            *total = total.saturating_add(line.quantity);
+            let available = self.available(line.id);
+            if *total > available {
+                return Err(invalid(format!("only {} available", available)));
+            }
+        }
+        Ok(())
     }
 
+    /// Inserts a coin or bill worth `money` into the machine.
+    ///
+    /// The coin is refused if it overpays by an amount the float cannot give back.
     pub async fn insert_money(&mut self, money: u64) -> Result<(), VendingMachineError> {
-        if let Some(state) = self.state.take() {
-            self.state = Some(state.insert_money(money)?);
-            self.update_last_activity().await?;
-            return Ok(());
+        if !self.denominations.contains(&money) {
+            self.hardware.coin_acceptor.reject(money);
+            self.show(&format!("{} units is not an accepted coin or bill", money));
+            return Err(VendingMachineError::InsertMoney("unknown coin or bill"));
+        }
+        let change = self
+            .state
+            .as_ref()
+            .and_then(|state| state.amount_due())
+            .map_or(0, |due| money.saturating_sub(due));
+        if !self.cash_box.can_make_change(change) {
+            self.hardware.coin_acceptor.reject(money);
+            self.show(&format!("Cannot give {} units of change", change));
+            return Err(VendingMachineError::InsertMoney(
+                "cannot make change, insert the exact amount",
+            ));
+        }
+        if let Err(e) = self.transition(|state, vm| state.insert_money(vm, money)) {
+            self.hardware.coin_acceptor.reject(money);
+            return Err(e);
         }
-        Err(VendingMachineError::AddItem("invalid state"))
+        // The coin is credited now, it is kept even if the new state cannot be published
+        self.hardware.coin_acceptor.accept(money);
+        self.cash_box.load(money, 1);
+        self.record(JournalEvent::CashDeposited {
+            denomination: money,
+        });
+        if let Err(e) = self.credited().await {
+            eprintln!("Failed to publish the credit: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Credits `money` paid by any means towards the requested item.
+    async fn credit(&mut self, money: u64) -> Result<(), VendingMachineError> {
+        self.transition(|state, vm| state.insert_money(vm, money))?;
+        self.credited().await
+    }
+
+    /// Brings the invoice and the published state in line with a credit.
+    async fn credited(&mut self) -> Result<(), VendingMachineError> {
+        self.sync_invoice().await;
+        self.update_last_activity().await
     }
 
     pub async fn dispense_item(&mut self) -> Result<(), VendingMachineError> {
-        if let Some(state) = self.state.take() {
-            self.state = Some(state.dispense_item(self)?);
-            self.update_last_activity().await?;
+        self.transition(|state, vm| state.dispense_item(vm))?;
+        self.settle_refunds().await;
+        self.sync_invoice().await;
+        self.notify_faults().await;
+        self.update_last_activity().await
+    }
+
+    pub async fn cancel(&mut self) -> Result<(), VendingMachineError> {
+        if self.state.is_none() {
+            // Without a state there is no cart left to hold units for
+            for item_id in std::mem::take(&mut self.reservations).into_keys() {
+                self.listing_changed(item_id);
+            }
+            self.state = Some(Box::new(ListeningState));
             return Ok(());
         }
-        Err(VendingMachineError::AddItem("invalid state"))
+        self.transition(|state, vm| state.cancel(vm))?;
+        self.settle_refunds().await;
+        self.sync_invoice().await;
+        self.update_last_activity().await
     }
 
-    pub async fn cancel(&mut self) -> Result<(), VendingMachineError> {
-        if let Some(state) = self.state.take() {
-            self.state = Some(state.cancel(self)?);
-            self.update_last_activity().await?;
+    /// Hands the current state to `transition`. A refused transition leaves the state as it was.
+    fn transition(
+        &mut self,
+        transition: impl FnOnce(Box<dyn State>, &mut Self) -> Transition,
+    ) -> Result<(), VendingMachineError> {
+        let state = self
+            .state
+            .take()
+            .ok_or(VendingMachineError::AddItem("invalid state"))?;
+        let in_transaction = state.in_transaction();
+        match transition(state, self) {
+            Ok(next) => {
+                if in_transaction && !next.in_transaction() {
+                    self.purchase += 1;
+                }
+                self.state = Some(next);
+                Ok(())
+            }
+            Err(Rejected { state, error }) => {
+                self.state = Some(state);
+                Err(error)
+            }
+        }
+    }
+
+    /// Keeps the Lightning invoice in line with the amount due in the current state.
+    ///
+    /// A new invoice is created (and watched for settlement) when money becomes due, and the
+    /// pending one is superseded once nothing is due anymore or the amount changed. The machine
+    /// loop waits for the wallet, so a wallet that is slow to answer leaves the cart without an
+    /// invoice until the amount due changes again.
+    async fn sync_invoice(&mut self) {
+        let amount_due = self.state.as_ref().and_then(|state| state.amount_due());
+
+        if let Some(invoice) = self.pending_invoice.take() {
+            if amount_due == Some(invoice.amount) {
+                self.pending_invoice = Some(invoice);
+                return;
+            }
+        }
+
+        let (Some(wallet), Some(amount)) = (self.lightning.clone(), amount_due) else {
+            return;
+        };
+        let invoice = tokio::time::timeout(
+            std::time::Duration::from_secs(INVOICE_TIMEOUT_SECS),
+            wallet.make_invoice(amount, "vending machine purchase"),
+        )
+        .await;
+        match invoice {
+            Ok(Ok(invoice)) => {
+                self.show(&format!(
+                    "Pay {} sats with Lightning: {}",
+                    amount, invoice.bolt11
+                ));
+                let watcher = tokio::spawn(
+                    wallet.watch_invoice(invoice.clone(), self.payment_events_sender.clone()),
+                );
+                self.watched_invoices.insert(
+                    invoice.payment_hash.clone(),
+                    WatchedInvoice {
+                        customer: self.session.unwrap_or(Customer::Local),
+                        purchase: self.purchase,
+                        watcher,
+                    },
+                );
+                self.pending_invoice = Some(invoice);
+            }
+            Ok(Err(e)) => eprintln!("Could not create Lightning invoice: {}", e),
+            Err(_) => eprintln!(
+                "Could not create Lightning invoice: no answer from the wallet in {} seconds",
+                INVOICE_TIMEOUT_SECS
+            ),
+        }
+    }
+
+    /// Amount the customer still has to pay for the cart, if anything is due.
+    pub fn amount_due(&self) -> Option<u64> {
+        self.state.as_ref().and_then(|state| state.amount_due())
+    }
+
+    /// The Lightning invoice the customer is expected to pay, if any.
+    pub fn pending_invoice(&self) -> Option<&Invoice> {
+        self.pending_invoice.as_ref()
+    }
+
+    async fn handle_payment_event(
+        &mut self,
+        event: PaymentEvent,
+    ) -> Result<(), VendingMachineError> {
+        match event {
+            PaymentEvent::InvoiceSettled {
+                payment_hash,
+                amount,
+            } => {
+                let Some(watched) = self.watched_invoices.remove(&payment_hash) else {
+                    eprintln!(
+                        "⚠️ Received {} sats for unknown invoice {}",
+                        amount, payment_hash
+                    );
+                    return Ok(());
+                };
+                if self
+                    .pending_invoice()
+                    .is_some_and(|invoice| invoice.payment_hash == payment_hash)
+                {
+                    self.pending_invoice = None;
+                }
+                println!("Lightning invoice {} paid", payment_hash);
+                let in_transaction = self
+                    .state
+                    .as_ref()
+                    .is_some_and(|state| state.in_transaction());
+                if !in_transaction || watched.purchase != self.purchase {
+                    // Paid after its purchase ended, nothing is left to credit it to
+                    println!("Refunding late payment of {} sats", amount);
+                    self.refunds.push(Refund {
+                        customer: watched.customer,
+                        amount,
+                        change: false,
+                    });
+                    self.settle_refunds().await;
+                    return Ok(());
+                }
+                // An invoice superseded within the purchase is credited too, anything paid
+                // over the amount due is returned as change
+                let Some(owner) = self.remote_owner() else {
+                    return self.credit(amount).await;
+                };
+                self.outbox = Some(Vec::new());
+                let result = match self.credit(amount).await {
+                    Ok(()) => self.dispense_if_paid().await,
+                    Err(e) => Err(e),
+                };
+                self.reply_to_customer(owner, result).await
+            }
+            PaymentEvent::InvoiceExpired { payment_hash } => {
+                self.watched_invoices.remove(&payment_hash);
+                Ok(())
+            }
+        }
+    }
+
+    /// Credits a Cashu token once it is redeemed at the mint.
+    async fn pay_cashu(&mut self, token: &str) -> Result<(), VendingMachineError> {
+        match self.redeem_cashu(token).await {
+            Ok(amount) => {
+                self.show(&format!("Payment of {} sats received", amount));
+                self.credit(amount).await
+            }
+            Err(e) => {
+                self.show(&format!("Payment failed: {}", e));
+                Err(VendingMachineError::Payment(e))
+            }
+        }
+    }
+
+    /// Checks a Cashu token against the amount due and redeems it at the configured mint.
+    async fn redeem_cashu(&self, raw: &str) -> Result<u64, PaymentError> {
+        let mint = self
+            .cashu
+            .as_ref()
+            .ok_or_else(|| PaymentError::Rejected("Cashu payments are not enabled".to_string()))?;
+        let token = Token::parse(raw)?;
+
+        let amount_due = self
+            .state
+            .as_ref()
+            .and_then(|state| state.amount_due())
+            .ok_or_else(|| PaymentError::Rejected("request an item first".to_string()))?;
+        let amount = token.amount()?;
+        if amount > amount_due {
+            return Err(PaymentError::Rejected(format!(
+                "token is worth {} sats, only {} sats are due",
+                amount, amount_due
+            )));
+        }
+
+        mint.redeem(&token).await
+    }
+
+    /// Sends `message` to `receiver` the way they last wrote to the machine, as a NIP-17
+    /// private message if they never did.
+    pub async fn send_direct_message(
+        &self,
+        receiver: nostr_sdk::PublicKey,
+        message: &str,
+    ) -> Result<(), VendingMachineError> {
+        let protocol = self
+            .reply_protocols
+            .get(&receiver)
+            .copied()
+            .unwrap_or(DmProtocol::PrivateMessage);
+        send_message(&self.nostr_client, receiver, protocol, message)
+            .await
+            .map_err(VendingMachineError::Nostr)
+    }
+
+    /// Sends the messages collected while serving `customer` to them in one DM, appending the
+    /// error if serving them failed, and passes `result` on.
+    async fn reply_to_customer(
+        &mut self,
+        customer: nostr_sdk::PublicKey,
+        result: Result<(), VendingMachineError>,
+    ) -> Result<(), VendingMachineError> {
+        let mut messages = self.outbox.take().unwrap_or_default();
+        if let Err(e) = &result {
+            messages.push(format!("Error: {}", e));
+        }
+        if messages.is_empty() {
+            return result;
+        }
+        let protocol = self
+            .reply_protocols
+            .get(&customer)
+            .copied()
+            .unwrap_or(DmProtocol::EncryptedDm);
+        if let Err(e) =
+            send_message(&self.nostr_client, customer, protocol, &messages.join("\n")).await
+        {
+            eprintln!("Failed to reply to customer {}: {}", customer, e);
+        }
+        result
+    }
+
+    /// Makes `customer` the owner of the purchase. Fails while someone else is in the middle of
+    /// one, so a second customer cannot take over the cart or the credit.
+    fn claim_session(&mut self, customer: Customer) -> Result<(), VendingMachineError> {
+        let in_transaction = self
+            .state
+            .as_ref()
+            .is_some_and(|state| state.in_transaction());
+        match self.session {
+            Some(owner) if in_transaction && owner != customer => Err(VendingMachineError::Busy(
+                "the machine is serving another customer",
+            )),
+            _ => {
+                if !in_transaction {
+                    self.order = None;
+                }
+                self.session = Some(customer);
+                Ok(())
+            }
+        }
+    }
+
+    /// Nostr customer in the middle of a purchase, if any.
+    fn remote_owner(&self) -> Option<nostr_sdk::PublicKey> {
+        let in_transaction = self
+            .state
+            .as_ref()
+            .is_some_and(|state| state.in_transaction());
+        match self.session {
+            Some(Customer::Nostr(owner)) if in_transaction => Some(owner),
+            _ => None,
+        }
+    }
+
+    /// Dispenses as soon as a Nostr customer has paid, they have no dispense button to press.
+    async fn dispense_if_paid(&mut self) -> Result<(), VendingMachineError> {
+        let paid = self
+            .state
+            .as_ref()
+            .is_some_and(|state| state.in_transaction() && state.amount_due().is_none());
+        let Some(owner) = self.remote_owner().filter(|_| paid) else {
             return Ok(());
+        };
+        self.dispense_item().await?;
+        if let Some(order_id) = self.order.take() {
+            let status = MerchantVerifyPayment {
+                id: order_id,
+                r#type: 2,
+                paid: true,
+                shipped: self.state_name() == "ListeningState",
+            };
+            self.send_order_message(owner, &status.as_json()).await;
         }
-        self.state = Some(Box::new(ListeningState));
         Ok(())
     }
 
+    /// Sends `owner` a NIP-15 payment request for the Lightning invoice of their order.
+    async fn send_payment_request(&self, owner: nostr_sdk::PublicKey) {
+        let (Some(order_id), Some(invoice)) = (self.order.clone(), self.pending_invoice()) else {
+            return;
+        };
+        let request = MerchantPaymentRequest {
+            id: order_id,
+            r#type: 1,
+            payment_options: vec![PaymentOption {
+                r#type: "ln".to_string(),
+                link: invoice.bolt11.clone(),
+            }],
+        };
+        self.send_order_message(owner, &request.as_json()).await;
+    }
+
+    /// Sends a NIP-15 order message on its own, marketplace clients expect nothing but the JSON.
+    async fn send_order_message(&self, owner: nostr_sdk::PublicKey, message: &str) {
+        let protocol = self
+            .reply_protocols
+            .get(&owner)
+            .copied()
+            .unwrap_or(DmProtocol::EncryptedDm);
+        if let Err(e) = send_message(&self.nostr_client, owner, protocol, message).await {
+            eprintln!("Failed to send order update to {}: {}", owner, e);
+        }
+    }
+
+    /// Whether a customer ordering over Nostr has a way to pay.
+    fn takes_remote_payments(&self) -> bool {
+        self.lightning.is_some() || self.cashu.is_some()
+    }
+
     pub fn show_commands(&self) {
-        self.state.as_ref().unwrap().show_commands()
+        if let Some(state) = self.state.as_ref() {
+            state.show_commands()
+        }
     }
 
     pub fn show_items(&self) {
@@ -259,6 +998,191 @@ impl VendingMachine {
         println!("----------------------------------------------------------");
     }
 
+    /// Shows every item that can be requested with its price.
+    pub fn show_menu(&mut self) {
+        let mut items: Vec<Item> = self.items.values().cloned().collect();
+        items.sort_by_key(|item| item.id);
+        self.show("Menu:");
+        for item in items {
+            let available = self.available(item.id);
+            if available > 0 {
+                self.show(&format!(
+                    "id: {}, name: {}, price: {}, available: {}",
+                    item.id, item.name, item.price, available
+                ));
+            }
+        }
+    }
+
+    pub fn show_faults(&self) {
+        for (item_id, fault) in &self.out_of_service {
+            println!("item {} out of service: {}", item_id, fault);
+        }
+    }
+
+    pub fn show_cash_box(&self) {
+        println!("cash box ({} units): ", self.cash_box.total());
+        for (denomination, count) in self.cash_box.coins() {
+            println!("{} x {}", count, denomination);
+        }
+        if self.exact_change_only() {
+            println!("exact change only");
+        }
+    }
+
+    /// Adds `count` coins of `denomination` to the float.
+    pub async fn load_float(
+        &mut self,
+        denomination: u64,
+        count: u64,
+    ) -> Result<(), VendingMachineError> {
+        if !self.under_admin {
+            return Err(VendingMachineError::Unauthorized(
+                "only admin can load the float",
+            ));
+        }
+        self.cash_box.load(denomination, count);
+        self.record(JournalEvent::FloatLoaded {
+            denomination,
+            count,
+        });
+        self.update_last_activity().await
+    }
+
+    /// Takes every coin out of the cash box and returns them.
+    pub async fn empty_float(&mut self) -> Result<BTreeMap<u64, u64>, VendingMachineError> {
+        if !self.under_admin {
+            return Err(VendingMachineError::Unauthorized(
+                "only admin can empty the float",
+            ));
+        }
+        let coins = self.cash_box.empty();
+        self.record(JournalEvent::FloatEmptied {
+            coins: coins.clone(),
+        });
+        self.update_last_activity().await?;
+        Ok(coins)
+    }
+
+    /// Pays `amount` back to the customer in coins from the cash box.
+    ///
+    /// Returns `false` if the coins held cannot make the amount; nothing is paid out then.
+    pub(crate) fn pay_out(&mut self, amount: u64) -> bool {
+        if amount == 0 {
+            return true;
+        }
+        let Some(coins) = self.cash_box.make_change(amount) else {
+            eprintln!("⚠️ Cannot pay out {} units from the cash box", amount);
+            return false;
+        };
+        self.hardware.change_returner.pay_out(&coins);
+        self.cash_box.withdraw(&coins);
+        self.record(JournalEvent::CashPaidOut { coins });
+        true
+    }
+
+    /// Pays `amount` back to the customer of the current purchase the way they paid: in coins at
+    /// the machine, in ecash to a Nostr customer once the transition is done.
+    pub(crate) fn refund(&mut self, amount: u64) {
+        self.pay_back(amount, false);
+    }
+
+    /// Returns `amount` of change like [`Self::refund`], journaled once it is paid.
+    pub(crate) fn return_change(&mut self, amount: u64) {
+        self.pay_back(amount, true);
+    }
+
+    fn pay_back(&mut self, amount: u64, change: bool) {
+        if let Some(customer @ Customer::Nostr(_)) = self.session.filter(|_| amount > 0) {
+            self.refunds.push(Refund {
+                customer,
+                amount,
+                change,
+            });
+            return;
+        }
+        if self.pay_out(amount) {
+            if change {
+                self.record(JournalEvent::ChangeReturned { amount });
+            }
+            return;
+        }
+        self.refunds.push(Refund {
+            customer: Customer::Local,
+            amount,
+            change,
+        });
+    }
+
+    /// Pays the refunds queued by the last transition or a late payment: coins at the machine,
+    /// ecash to Nostr customers. Refunds that cannot be paid are journaled as owed and the
+    /// admins are told.
+    async fn settle_refunds(&mut self) {
+        for refund in std::mem::take(&mut self.refunds) {
+            let Refund {
+                customer,
+                amount,
+                change,
+            } = refund;
+            let paid = match customer {
+                Customer::Nostr(receiver) => self
+                    .send_ecash(receiver, amount)
+                    .await
+                    .map_err(|e| (receiver.to_string(), e.to_string())),
+                Customer::Local if self.pay_out(amount) => Ok(()),
+                Customer::Local => Err((
+                    "the customer at the machine".to_string(),
+                    "the cash box cannot make the amount".to_string(),
+                )),
+            };
+            let (customer, error) = match paid {
+                Ok(()) => {
+                    if change {
+                        self.record(JournalEvent::ChangeReturned { amount });
+                    }
+                    continue;
+                }
+                Err(unpaid) => unpaid,
+            };
+            self.show(&format!(
+                "Could not pay back {} units: {}. An operator was told.",
+                amount, error
+            ));
+            self.record(JournalEvent::RefundOwed { amount });
+            let message = format!("⚠️ {} units are owed to {}: {}", amount, customer, error);
+            for admin in self.admin_pubkeys.clone() {
+                if let Err(e) = self.send_direct_message(admin, &message).await {
+                    eprintln!("Failed to notify admin {} of refund: {}", admin, e);
+                }
+            }
+        }
+    }
+
+    /// Sends `receiver` `amount` sats of the machine's ecash as a Cashu token in a DM.
+    async fn send_ecash(
+        &self,
+        receiver: nostr_sdk::PublicKey,
+        amount: u64,
+    ) -> Result<(), VendingMachineError> {
+        let mint = self.cashu.as_ref().ok_or_else(|| {
+            VendingMachineError::Payment(PaymentError::Rejected(
+                "Cashu payments are not enabled".to_string(),
+            ))
+        })?;
+        let token = mint
+            .send(amount)
+            .await
+            .map_err(VendingMachineError::Payment)?
+            .to_v3_string();
+        let message = format!("Refund of {} sats: {}", amount, token);
+        if let Err(e) = self.send_direct_message(receiver, &message).await {
+            // The token is the only copy of the ecash, keep it for the operator
+            eprintln!("Undelivered refund token for {}: {}", receiver, token);
+            return Err(e);
+        }
+        Ok(())
+    }
+
     pub(crate) fn increment_item_count(
         &mut self,
         add_items: Item,
@@ -266,11 +1190,25 @@ impl VendingMachine {
         if !self.under_admin {
             return Err(VendingMachineError::Unauthorized("only admin can add item"));
         }
+        // The name and price are only taken for new items
+        if add_items.name.is_empty() && !self.items.contains_key(&add_items.id) {
+            return Err(VendingMachineError::AddItem(
+                "a new item needs a name and a price",
+            ));
+        }
+        let event = JournalEvent::ItemAdded {
+            item_id: add_items.id,
+            name: add_items.name.clone(),
+            price: add_items.price,
+            count: add_items.count,
+        };
         self.items
             .entry(add_items.id)
             .or_insert(Item::new(add_items.id, add_items.name, add_items.price, 0))
             .increment_count(add_items.count);
-        Ok(())
+        self.listing_changed(add_items.id);
+        self.record(event);
+        self.persist_items()
     }
 
     pub(crate) fn remove_item_from_menu(
@@ -280,10 +1218,47 @@ impl VendingMachine {
         if !self.under_admin {
             return Err(VendingMachineError::Unauthorized("only admin can add item"));
         }
-        self.items
-            .remove(&item_id)
-            .map(|_| ())
-            .ok_or(VendingMachineError::ItemDoesNotExist(item_id))
+        if !self.items.contains_key(&item_id) {
+            return Err(VendingMachineError::ItemDoesNotExist(item_id));
+        }
+        self.items.remove(&item_id);
+        self.listing_changed(item_id);
+        self.record(JournalEvent::ItemRemoved { item_id });
+        self.persist_items()
+    }
+
+    /// Units of `item_id` that can still be requested.
+    pub fn available(&self, item_id: u64) -> u64 {
+        if self.is_out_of_service(item_id) {
+            return 0;
+        }
+        let reserved = self.reservations.get(&item_id).copied().unwrap_or(0);
+        self.get_item(item_id)
+            .map_or(0, |item| item.count.saturating_sub(reserved))
+    }
+
+    /// Holds `quantity` units of `item_id` for the current customer.
+    ///
+    /// Returns `false` and reserves nothing if fewer units are available.
+    pub(crate) fn reserve(&mut self, item_id: u64, quantity: u64) -> bool {
+        if self.available(item_id) < quantity {
+            return false;
+        }
+        *self.reservations.entry(item_id).or_insert(0) += quantity;
+        self.listing_changed(item_id);
+        true
+    }
+
+    /// Gives back `quantity` reserved units of `item_id`.
+    pub(crate) fn release(&mut self, item_id: u64, quantity: u64) {
+        let Some(reserved) = self.reservations.get_mut(&item_id) else {
+            return;
+        };
+        *reserved = reserved.saturating_sub(quantity);
+        if *reserved == 0 {
+            self.reservations.remove(&item_id);
+        }
+        self.listing_changed(item_id);
     }
 
     pub(crate) fn change_item_price(
@@ -295,8 +1270,14 @@ impl VendingMachine {
             return Err(VendingMachineError::Unauthorized("only admin can add item"));
         }
         if let Some(item) = self.items.get_mut(&item_id) {
-            item.price = price;
-            return Ok(());
+            let old_price = std::mem::replace(&mut item.price, price);
+            self.listing_changed(item_id);
+            self.record(JournalEvent::PriceChanged {
+                item_id,
+                old_price,
+                new_price: price,
+            });
+            return self.persist_items();
         }
         Err(VendingMachineError::ItemDoesNotExist(item_id))
     }
@@ -305,41 +1286,212 @@ impl VendingMachine {
         self.items.get(&item_id)
     }
 
-    pub(crate) fn sell_item_unit(&mut self, item_id: u64) {
-        self.items
-            .entry(item_id)
-            .and_modify(|item| item.sell_unit());
+    /// Whether the slot of `item_id` failed and was not cleared by an admin yet.
+    pub fn is_out_of_service(&self, item_id: u64) -> bool {
+        self.out_of_service.contains_key(&item_id)
+    }
+
+    /// Dispenses one unit of `item_id` and takes it out of stock if the dispenser succeeded.
+    pub(crate) fn dispense_unit(&mut self, item_id: u64) -> Result<(), DispenseFault> {
+        let Some(item) = self
+            .items
+            .get(&item_id)
+            .filter(|item| item.count > 0)
+            .cloned()
+        else {
+            return Err(DispenseFault::SlotEmpty);
+        };
+        self.hardware.dispenser.dispense(&item)?;
+        self.sell_item_unit(item_id);
+        Ok(())
+    }
+
+    /// Takes the slot of `item_id` out of service and has admins notified.
+    pub(crate) fn report_fault(&mut self, item_id: u64, fault: DispenseFault) {
+        self.out_of_service.insert(item_id, fault);
+        self.record(JournalEvent::DispenseFailed { item_id, fault });
+        self.fault_notified_at = None;
+    }
+
+    /// Puts the slot of `item_id` back in service.
+    pub async fn clear_fault(&mut self, item_id: u64) -> Result<(), VendingMachineError> {
+        if !self.under_admin {
+            return Err(VendingMachineError::Unauthorized(
+                "only admin can clear faults",
+            ));
+        }
+        if self.out_of_service.remove(&item_id).is_none() {
+            println!("item {} has no fault", item_id);
+            return Ok(());
+        }
+        self.record(JournalEvent::FaultCleared { item_id });
+        self.update_last_activity().await
+    }
+
+    /// Sends admins a DM listing the slots out of service, then again every
+    /// `FAULT_REMINDER_SECS` until every fault is cleared.
+    async fn notify_faults(&mut self) {
+        if self.out_of_service.is_empty() {
+            return;
+        }
+        if self
+            .fault_notified_at
+            .is_some_and(|at| at.elapsed().as_secs() < FAULT_REMINDER_SECS)
+        {
+            return;
+        }
+        self.fault_notified_at = Some(Instant::now());
+
+        let faults: Vec<String> = self
+            .out_of_service
+            .iter()
+            .map(|(item_id, fault)| format!("item {}: {}", item_id, fault))
+            .collect();
+        let message = format!(
+            "⚠️ Out of service: {}. Send ClearFault once fixed.",
+            faults.join(", ")
+        );
+        for admin in self.admin_pubkeys.clone() {
+            if let Err(e) = self.send_direct_message(admin, &message).await {
+                eprintln!("Failed to notify admin {} of fault: {}", admin, e);
+            }
+        }
+    }
+
+    /// Takes one unit of `item_id` out of stock. Returns `false` if there was none to sell.
+    pub(crate) fn sell_item_unit(&mut self, item_id: u64) -> bool {
+        let Some(item) = self.items.get_mut(&item_id).filter(|item| item.count > 0) else {
+            return false;
+        };
+        item.sell_unit();
+        let price = item.price;
+        self.listing_changed(item_id);
+        self.record(JournalEvent::ItemDispensed { item_id, price });
+        // The unit has already left the machine, so a failed save must not undo the sale
+        if let Err(e) = self.persist_items() {
+            eprintln!("Failed to persist inventory after sale: {}", e);
+        }
+        true
+    }
+
+    /// Appends `event` to the journal, attributed to the admin whose command is being processed.
+    pub(crate) fn record(&mut self, event: JournalEvent) {
+        if let Some(batch) = self.batch.as_mut() {
+            batch.push(event);
+            return;
+        }
+        let entry = JournalEntry::new(self.journal_seq, self.acting_admin, event);
+        match self.journal.append(&entry) {
+            Ok(()) => self.journal_seq += 1,
+            Err(e) => eprintln!("Failed to append to journal: {}", e),
+        }
+    }
+
+    /// Replaces the inventory with the one rebuilt by replaying the journal and persists it.
+    pub fn rebuild_from_journal(&mut self) -> Result<(), VendingMachineError> {
+        let entries = self
+            .journal
+            .entries()
+            .map_err(VendingMachineError::Storage)?;
+        self.items = journal::replay(&entries);
+        self.market_changes.extend(self.items.keys().copied());
+        self.persist_items()
+    }
+
+    /// Saves the current inventory snapshot to the configured store.
+    fn persist_items(&self) -> Result<(), VendingMachineError> {
+        if self.batch.is_some() {
+            return Ok(());
+        }
+        let items: Vec<Item> = self.items.values().cloned().collect();
+        self.store
+            .save(&items)
+            .map_err(VendingMachineError::Storage)
     }
 
     // Process the next admin command if available
     pub async fn process_next_admin_command(
+        &mut self,
+        request: &AdminRequest,
+    ) -> Result<AdminCommandResult, VendingMachineError> {
+        self.acting_admin = Some(request.pubkey);
+        let result = self.execute_admin_command(&request.command).await;
+        self.acting_admin = None;
+        result
+    }
+
+    /// Builds the report sent in answer to `AdminCommand::Status`.
+    pub async fn status_report(&self) -> StatusReport {
+        let mut items: Vec<Item> = self.items.values().cloned().collect();
+        items.sort_by_key(|item| item.id);
+        let mut relays: Vec<RelayReport> = self
+            .nostr_client
+            .relays()
+            .await
+            .into_iter()
+            .map(|(url, relay)| RelayReport {
+                url: url.to_string(),
+                connected: relay.is_connected(),
+            })
+            .collect();
+        relays.sort_by(|a, b| a.url.cmp(&b.url));
+        StatusReport {
+            state: self.state_name(),
+            under_admin: self.under_admin,
+            items,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            last_activity_secs: self.last_activity.map(|at| at.elapsed().as_secs()),
+            relays,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// Tells the admin whether their command succeeded.
+    async fn respond_to_admin(
+        &self,
+        request: &AdminRequest,
+        result: &Result<AdminCommandResult, VendingMachineError>,
+    ) {
+        let response = match result {
+            Ok(result) => AdminResponse::ok(&request.request_id, result.clone()),
+            Err(e) => AdminResponse::error(&request.request_id, e),
+        };
+        let content = serde_json::to_string(&response).unwrap();
+        if let Err(e) = self.send_direct_message(request.pubkey, &content).await {
+            eprintln!("Failed to reply to admin {}: {}", request.pubkey, e);
+        }
+    }
+
+    async fn execute_admin_command(
         &mut self,
         command: &AdminCommand,
-    ) -> Result<bool, VendingMachineError> {
+    ) -> Result<AdminCommandResult, VendingMachineError> {
         // Process the admin command
         match command {
             AdminCommand::ChangePrice(change_price_req) => {
                 self.change_price(change_price_req.id, change_price_req.price)
                     .await?;
-                Ok(true)
+                Ok(AdminCommandResult::Done)
             }
             AdminCommand::RemoveItem(item_id) => {
                 self.remove_item(item_id.to_owned()).await?;
-                Ok(true)
+                Ok(AdminCommandResult::Done)
             }
             AdminCommand::RequestAdminState => {
                 self.admin().await?;
-                Ok(true)
+                Ok(AdminCommandResult::Done)
             }
             AdminCommand::Reboot => {
                 println!("Admin requested reboot");
-                // Implement reboot logic
-                Ok(true)
+                self.reboot().await?;
+                Ok(AdminCommandResult::Done)
             }
             AdminCommand::Status => {
                 println!("Admin requested status");
                 self.show_items();
-                Ok(true)
+                self.show_cash_box();
+                self.show_faults();
+                Ok(AdminCommandResult::Status(self.status_report().await))
             }
             AdminCommand::AddItem(item_data) => {
                 println!(
@@ -353,22 +1505,358 @@ impl VendingMachine {
                     count: item_data.count,
                 })
                 .await?;
-                Ok(true)
+                Ok(AdminCommandResult::Done)
+            }
+            AdminCommand::LoadFloat(float) => {
+                println!(
+                    "Admin loading float: {} x {}",
+                    float.count, float.denomination
+                );
+                self.load_float(float.denomination, float.count).await?;
+                Ok(AdminCommandResult::Done)
+            }
+            AdminCommand::ClearFault(item_id) => {
+                println!("Admin clearing fault of item {}", item_id);
+                self.clear_fault(*item_id).await?;
+                Ok(AdminCommandResult::Done)
+            }
+            AdminCommand::EmptyFloat => {
+                let coins = self.empty_float().await?;
+                println!(
+                    "Admin emptied the cash box: {} units",
+                    coins
+                        .iter()
+                        .map(|(value, count)| value * count)
+                        .sum::<u64>()
+                );
+                Ok(AdminCommandResult::FloatEmptied(coins))
             }
             AdminCommand::Shutdown => {
                 println!("Admin requested shutdown");
-                Ok(true)
+                self.stop_requested = true;
+                Ok(AdminCommandResult::Done)
             }
             AdminCommand::End => {
                 println!("Admin finished working");
                 self.cancel().await?;
-                Ok(true)
+                Ok(AdminCommandResult::Done)
+            }
+            AdminCommand::AddAdmin(request) => {
+                // The admin handler already authorized the key, it is now notified of faults
+                let pubkey = nostr_sdk::PublicKey::parse(&request.pubkey).map_err(|_| {
+                    VendingMachineError::AdminError(AdminError::InvalidNostrPubKey(
+                        request.pubkey.clone(),
+                    ))
+                })?;
+                if !self.admin_pubkeys.contains(&pubkey) {
+                    self.admin_pubkeys.push(pubkey);
+                }
+                Ok(AdminCommandResult::Done)
+            }
+            AdminCommand::RemoveAdmin(input) => {
+                let pubkey = nostr_sdk::PublicKey::parse(input).map_err(|_| {
+                    VendingMachineError::AdminError(AdminError::InvalidNostrPubKey(input.clone()))
+                })?;
+                self.admin_pubkeys.retain(|admin| *admin != pubkey);
+                Ok(AdminCommandResult::Done)
+            }
+            AdminCommand::Batch(commands) => self.execute_batch(commands).await,
+            AdminCommand::Approve(_) | AdminCommand::ListAdmins => {
+                Err(VendingMachineError::AdminError(AdminError::UnknownCommand(
+                    "handled by the admin handler".to_string(),
+                )))
+            }
+        }
+    }
+
+    /// Runs `commands` as one. They are checked against the inventory first; while they are
+    /// applied the journal, the saved inventory and the published state are left untouched, and
+    /// they are all updated once at the end. If a command still fails, the machine is put back
+    /// as it was before the batch.
+    async fn execute_batch(
+        &mut self,
+        commands: &[AdminCommand],
+    ) -> Result<AdminCommandResult, VendingMachineError> {
+        self.validate_batch(commands)?;
+        println!("Admin running a batch of {} commands", commands.len());
+
+        let snapshot = (
+            self.state.as_ref().map(|state| state.snapshot()),
+            self.under_admin,
+            self.items.clone(),
+            self.out_of_service.clone(),
+            self.cash_box.clone(),
+            self.market_changes.clone(),
+        );
+        self.batch = Some(Vec::new());
+        let mut results = Vec::with_capacity(commands.len());
+        let mut failure = None;
+        for command in commands {
+            match Box::pin(self.execute_admin_command(command)).await {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+        let events = self.batch.take().unwrap_or_default();
+
+        if let Some(e) = failure {
+            eprintln!("Batch failed, rolling back: {}", e);
+            (
+                self.state,
+                self.under_admin,
+                self.items,
+                self.out_of_service,
+                self.cash_box,
+                self.market_changes,
+            ) = snapshot;
+            return Err(e);
+        }
+        for event in events {
+            self.record(event);
+        }
+        self.persist_items()?;
+        self.update_last_activity().await?;
+        Ok(AdminCommandResult::Batch(results))
+    }
+
+    /// Checks that every command of a batch can be run, in order, against the current inventory.
+    fn validate_batch(&self, commands: &[AdminCommand]) -> Result<(), VendingMachineError> {
+        if !self.under_admin {
+            return Err(VendingMachineError::Unauthorized(
+                "only admin can run a batch",
+            ));
+        }
+        let mut items: HashSet<u64> = self.items.keys().copied().collect();
+        for command in commands {
+            match command {
+                AdminCommand::AddItem(item) => {
+                    items.insert(item.id);
+                }
+                AdminCommand::RemoveItem(item_id) => {
+                    if !items.remove(item_id) {
+                        return Err(VendingMachineError::ItemDoesNotExist(*item_id));
+                    }
+                }
+                AdminCommand::ChangePrice(change) => {
+                    if !items.contains(&change.id) {
+                        return Err(VendingMachineError::ItemDoesNotExist(change.id));
+                    }
+                }
+                _ if command.batchable() => {}
+                _ => {
+                    return Err(VendingMachineError::AdminError(AdminError::UnknownCommand(
+                        format!("{:?} cannot be part of a batch", command),
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_customer_event(
+        &mut self,
+        event: CustomerEvent,
+    ) -> Result<(), VendingMachineError> {
+        if let Err(e) = self.claim_session(Customer::Local) {
+            self.show("Busy serving a customer ordering online, please wait");
+            return Err(e);
+        }
+        match event {
+            CustomerEvent::RequestItem { id, quantity } => {
+                self.request_items(id, quantity).await?;
+            }
+            CustomerEvent::InsertMoney(money) => {
+                self.insert_money(money).await?;
+            }
+            CustomerEvent::DispenseItem => {
+                self.dispense_item().await?;
+                self.show_items();
+            }
+            CustomerEvent::Cancel => {
+                self.cancel().await?;
+            }
+            CustomerEvent::PayCashu(token) => {
+                self.pay_cashu(&token).await?;
+            }
+            CustomerEvent::ShowMenu => {
+                self.show_menu();
+            }
+            CustomerEvent::PlaceOrder { items, .. } => {
+                self.request_order(&items).await?;
+            }
+            CustomerEvent::AddItem {
+                id,
+                name,
+                price,
+                count,
+            } => {
+                self.add_item(Item::new(id, name, price, count)).await?;
+                self.show_items();
             }
         }
+        self.show_commands();
+        Ok(())
+    }
+
+    async fn handle_remote_customer_event(
+        &mut self,
+        event: RemoteCustomerEvent,
+    ) -> Result<(), VendingMachineError> {
+        self.reply_protocols.insert(event.sender, event.protocol);
+        self.outbox = Some(Vec::new());
+        let result = self.serve_remote_customer(event.sender, event.event).await;
+        self.reply_to_customer(event.sender, result).await
+    }
+
+    async fn serve_remote_customer(
+        &mut self,
+        sender: nostr_sdk::PublicKey,
+        event: CustomerEvent,
+    ) -> Result<(), VendingMachineError> {
+        if let CustomerEvent::ShowMenu = event {
+            self.show_menu();
+            return Ok(());
+        }
+        self.claim_session(Customer::Nostr(sender))?;
+        match event {
+            CustomerEvent::RequestItem { id, quantity } => {
+                if !self.takes_remote_payments() {
+                    return Err(VendingMachineError::RequestItem(
+                        "the machine takes no payments over Nostr",
+                    ));
+                }
+                self.request_items(id, quantity).await?;
+            }
+            CustomerEvent::PlaceOrder { order_id, items } => {
+                if !self.takes_remote_payments() {
+                    return Err(VendingMachineError::RequestItem(
+                        "the machine takes no payments over Nostr",
+                    ));
+                }
+                self.request_order(&items).await?;
+                self.order = Some(order_id);
+                self.send_payment_request(sender).await;
+            }
+            CustomerEvent::PayCashu(token) => {
+                self.pay_cashu(&token).await?;
+            }
+            CustomerEvent::Cancel => {
+                self.cancel().await?;
+            }
+            CustomerEvent::InsertMoney(_)
+            | CustomerEvent::DispenseItem
+            | CustomerEvent::AddItem { .. }
+            | CustomerEvent::ShowMenu => {
+                return Err(VendingMachineError::Unauthorized(
+                    "only available at the machine",
+                ));
+            }
+        }
+        self.dispense_if_paid().await
+    }
+
+    /// Cancels whatever the machine is doing, refunding the customer, and tells a Nostr customer
+    /// whose purchase it was.
+    async fn cancel_notifying_owner(&mut self) -> Result<(), VendingMachineError> {
+        match self.remote_owner() {
+            Some(owner) => {
+                self.outbox = Some(Vec::new());
+                let result = self.cancel().await;
+                self.reply_to_customer(owner, result).await
+            }
+            None => self.cancel().await,
+        }
+    }
+
+    /// Soft reboot: refunds the purchase in progress, restarts the state machine in
+    /// `ListeningState` and reconnects to the relays. The inventory and cash box are kept.
+    pub async fn reboot(&mut self) -> Result<(), VendingMachineError> {
+        if let Err(e) = self.cancel_notifying_owner().await {
+            eprintln!("Failed to cancel before reboot: {}", e);
+        }
+        // Invoices stay watched, a late payment is refunded
+        self.pending_invoice = None;
+        self.purchase += 1;
+        self.state = Some(Box::new(ListeningState));
+        self.under_admin = false;
+        self.session = None;
+        self.order = None;
+        for item_id in std::mem::take(&mut self.reservations).into_keys() {
+            self.listing_changed(item_id);
+        }
+        self.persist_items()?;
+
+        self.reconnect().await?;
+        self.online = true;
+        self.started_at = Instant::now();
+        self.publish_market().await?;
+        self.update_last_activity().await
+    }
+
+    /// Replaces the Nostr client by a new one with the same signer and relays. Reconnecting the
+    /// existing client leaves its first publications waiting for the relay acknowledgement.
+    async fn reconnect(&mut self) -> Result<(), VendingMachineError> {
+        let signer = self
+            .nostr_client
+            .signer()
+            .await
+            .map_err(VendingMachineError::Nostr)?;
+        let client = nostr_sdk::ClientBuilder::new().signer(signer).build();
+        for relay in self.nostr_client.relays().await.into_keys() {
+            client
+                .add_relay(relay)
+                .await
+                .map_err(VendingMachineError::Nostr)?;
+        }
+        let old_client = std::mem::replace(&mut self.nostr_client, client);
+        old_client.shutdown().await;
+
+        let connected = self
+            .nostr_client
+            .try_connect(std::time::Duration::from_secs(RECONNECT_TIMEOUT_SECS))
+            .await;
+        for (relay, error) in connected.failed {
+            eprintln!("Failed to reconnect to {}: {}", relay, error);
+        }
+        // Keep retrying the relays that did not answer in the background
+        self.nostr_client.connect().await;
+        Ok(())
+    }
+
+    /// Graceful shutdown, once `run_machine` returned: refunds the purchase in progress, saves
+    /// the inventory, publishes the machine as offline and disconnects from the relays.
+    pub async fn shut_down(&mut self) -> Result<(), VendingMachineError> {
+        let in_transaction = self
+            .state
+            .as_ref()
+            .is_some_and(|state| state.in_transaction());
+        if in_transaction {
+            if let Err(e) = self.cancel_notifying_owner().await {
+                eprintln!("Failed to refund before shutdown: {}", e);
+            }
+        }
+        self.pending_invoice = None;
+        for (_, watched) in self.watched_invoices.drain() {
+            watched.watcher.abort();
+        }
+        self.persist_items()?;
+        self.online = false;
+        if let Err(e) = self.send_update().await {
+            eprintln!("Failed to publish offline status: {}", e);
+        }
+        self.nostr_client.disconnect().await;
+        Ok(())
     }
 
     pub async fn run_machine(&mut self) -> Result<(), VendingMachineError> {
         loop {
+            if std::mem::take(&mut self.stop_requested) {
+                println!("Shutdown requested by admin. Exiting...");
+                break;
+            }
             tokio::select! {
                 Some(shutdown) = self.shutdown.recv() => {
                     if shutdown {
@@ -376,73 +1864,42 @@ impl VendingMachine {
                         break;
                     }
                 }
-                Some(command) = self.admin_commands.recv() => {
-                    if let Err(e) = self.process_next_admin_command(&command).await {
+                Some(event) = self.payment_events.recv() => {
+                    if let Err(e) = self.handle_payment_event(event).await {
+                        eprintln!("Error processing payment: {}", e);
+                    }
+                }
+                Some(event) = self.customer_events.recv() => {
+                    if let Err(e) = self.handle_customer_event(event).await {
+                        eprintln!("Error processing customer event: {}", e);
+                    }
+                }
+                Some(event) = self.remote_customer_events.recv() => {
+                    if let Err(e) = self.handle_remote_customer_event(event).await {
+                        eprintln!("Error processing Nostr customer event: {}", e);
+                    }
+                }
+                Some(request) = self.admin_commands.recv() => {
+                    self.reply_protocols.insert(request.pubkey, request.protocol);
+                    let result = self.process_next_admin_command(&request).await;
+                    if let Err(e) = &result {
                         eprintln!("Error processing admin command: {}", e);
                     }
+                    self.respond_to_admin(&request, &result).await;
                 }
                 _ = tokio::time::sleep(tokio::time::Duration::from_secs(5)) => {
+                    self.notify_faults().await;
                     if let Some(last_activity) = self.last_activity {
                         if last_activity.elapsed().as_secs() > 60 {
                             println!("No activity for 60 seconds. Cancelling...");
-                            self.cancel().await?;
+                            self.cancel_notifying_owner().await?;
                             continue;
                         }
                     }
                 }
-                else => {
-                    if let Err(e) = self.process_user_input().await {
-                        eprintln!("Error processing user input: {}", e);
-                    }
-                }
             }
         }
 
         Ok(())
     }
-
-    async fn process_user_input(&mut self) -> Result<bool, VendingMachineError> {
-        println!("==============================================================");
-        self.show_commands();
-        let code = helper::read_number("Select code (0 to exit): ");
-
-        match code {
-            1 => {
-                self.show_items();
-                let id = helper::read_number("write the id of the item (number): ");
-                let mut name = String::new();
-                let mut price = 0;
-                if self.get_item(id).is_none() {
-                    name = helper::read_string("write the name of the item (string): ");
-                    price = helper::read_number("write the price of the item (number): ");
-                }
-                let count =
-                    helper::read_number("write the quantity adding to the stock of that item: ");
-                self.add_item(Item::new(id, name, price, count)).await?;
-                self.show_items();
-            }
-            2 => {
-                self.show_items();
-                let id =
-                    helper::read_number("requesting item. Provide the id of the item (number): ");
-                self.request_item(id).await?;
-            }
-            3 => {
-                let money = helper::read_number("insert money. Provide the amount (number): ");
-                self.insert_money(money).await?;
-            }
-            4 => {
-                self.dispense_item().await?;
-                self.show_items();
-            }
-            5 => {
-                self.cancel().await?;
-            }
-            _ => {
-                println!("invalid code. Try again")
-            }
-        }
-
-        Ok(true)
-    }
 }
@@ -1,12 +1,24 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 use helper::{setup_local_relay_client, LOCAL_RELAY_URL};
 use tokio::sync::mpsc;
-use vending_machines_nostr::admin::commands::{AddItemRequest, AdminCommand, ChangePriceRequest};
-use vending_machines_nostr::admin::{setup_admin_handler, AdminHandler};
+use vending_machines_nostr::admin::approval::ApprovalConfig;
+use vending_machines_nostr::admin::commands::{
+    AddAdminRequest, AddItemRequest, AdminCommand, AdminCommandResult, AdminMessage, AdminRequest,
+    AdminResponse, AdminResponseStatus, ChangePriceRequest,
+};
+use vending_machines_nostr::admin::limits::LimitsConfig;
+use vending_machines_nostr::admin::replay::{ReplayConfig, ReplayGuard};
+use vending_machines_nostr::admin::roles::Role;
+use vending_machines_nostr::admin::store::AdminStore;
+use vending_machines_nostr::admin::{setup_admin_handler, AdminConfig, AdminHandler};
+use vending_machines_nostr::customer::nostr::DmProtocol;
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::storage::MemoryStore;
 use vending_machines_nostr::vm::vending_machine::VendingMachine;
 
-use nostr_sdk::{Client, EventBuilder, Keys};
+use nostr_sdk::{Client, EventBuilder, Keys, ToBech32};
 use vending_machines_nostr::vending_machine::Item;
 mod helper;
 
@@ -41,6 +53,21 @@ async fn setup() -> (
     VendingMachine,
     AdminHandler,
     mpsc::Sender<bool>,
+) {
+    // Most tests send commands in kind 4 DMs, like the web admin
+    setup_with(|config| config.legacy_dm = true).await
+}
+
+/// Like `setup`, with the admin configuration adjusted by `configure`.
+async fn setup_with(
+    configure: impl FnOnce(&mut AdminConfig),
+) -> (
+    Keys,
+    Keys,
+    Client,
+    VendingMachine,
+    AdminHandler,
+    mpsc::Sender<bool>,
 ) {
     // Set up Nostr client
     let keys = Keys::generate();
@@ -51,24 +78,110 @@ async fn setup() -> (
     let (tx, rx) = mpsc::channel(10);
     let (shutdown_tx, shutdown_rx) = tokio::sync::mpsc::channel::<bool>(1);
     // Create and configure admin handler
+    let mut config = AdminConfig {
+        public_keys: vec![admin_keys.public_key().to_string()],
+        roles: HashMap::new(),
+        legacy_dm: false,
+        replay: ReplayConfig::default(),
+        approval: ApprovalConfig::default(),
+        admins_path: None,
+        limits: LimitsConfig::default(),
+    };
+    configure(&mut config);
     let admin_handler = setup_admin_handler(
         keys.clone(),
-        &[admin_keys.public_key().to_string()],
+        &config,
         &[LOCAL_RELAY_URL],
         tx.clone(),
+        ReplayGuard::default(),
     )
     .await
     .unwrap();
 
     // Create vending machine
-    let mut vm = VendingMachine::new(keys.clone(), &[LOCAL_RELAY_URL], rx, shutdown_rx)
-        .await
-        .unwrap();
+    let mut vm = VendingMachine::new(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        rx,
+        shutdown_rx,
+        Box::new(MemoryStore::default()),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
     vm.admin().await.unwrap();
 
     (keys, admin_keys, client, vm, admin_handler, shutdown_tx)
 }
 
+async fn send_admin_text(client: &Client, admin_keys: &Keys, keys: &Keys, text: &str) {
+    let encrypted = nostr_sdk::nips::nip44::encrypt(
+        admin_keys.secret_key(),
+        &keys.public_key(),
+        text,
+        nostr_sdk::nips::nip44::Version::V2,
+    )
+    .unwrap();
+    let event = EventBuilder::new(nostr_sdk::Kind::EncryptedDirectMessage, encrypted)
+        .build(admin_keys.public_key())
+        .sign(admin_keys)
+        .await
+        .unwrap();
+    client.send_event(&event).await.unwrap();
+}
+
+async fn admin_responses(client: &Client, admin_keys: &Keys, keys: &Keys) -> Vec<AdminResponse> {
+    let filter = nostr_sdk::Filter::new()
+        .kind(nostr_sdk::Kind::EncryptedDirectMessage)
+        .author(keys.public_key())
+        .pubkey(admin_keys.public_key());
+    client
+        .fetch_events(filter, Duration::from_secs(2))
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|event| {
+            let content = nostr_sdk::nips::nip44::decrypt(
+                admin_keys.secret_key(),
+                &keys.public_key(),
+                &event.content,
+            )
+            .ok()?;
+            serde_json::from_str(&content).ok()
+        })
+        .collect()
+}
+
+/// Responses the machine sent `admin_keys` in NIP-17 private messages.
+async fn private_admin_responses(
+    client: &Client,
+    admin_keys: &Keys,
+    keys: &Keys,
+) -> Vec<AdminResponse> {
+    let filter = nostr_sdk::Filter::new()
+        .kind(nostr_sdk::Kind::GiftWrap)
+        .pubkey(admin_keys.public_key());
+    let mut responses = Vec::new();
+    for gift_wrap in client
+        .fetch_events(filter, Duration::from_secs(2))
+        .await
+        .unwrap()
+    {
+        let Ok(unwrapped) =
+            nostr_sdk::nips::nip59::UnwrappedGift::from_gift_wrap(admin_keys, &gift_wrap).await
+        else {
+            continue;
+        };
+        if unwrapped.sender != keys.public_key() {
+            continue;
+        }
+        if let Ok(response) = serde_json::from_str(&unwrapped.rumor.content) {
+            responses.push(response);
+        }
+    }
+    responses
+}
+
 async fn send_admin_command(
     client: &Client,
     admin_keys: &Keys,
@@ -206,6 +319,31 @@ async fn test_change_price_command_via_nostr() {
     tokio::try_join!(machine, admin_handler).unwrap();
 }
 
+#[tokio::test]
+async fn test_restock_keeps_name_and_price() {
+    let (_keys, _admin_keys, client, mut vm, _admin_handler, _shutdown_tx) = setup().await;
+
+    vm.add_item(Item::new(22, "Test Product".to_string(), 100, 5))
+        .await
+        .unwrap();
+
+    // Restocking an existing item keeps its name and price, a new item needs both
+    vm.add_item(Item::new(22, String::new(), 0, 1))
+        .await
+        .unwrap();
+    let item = vm.get_item(22).unwrap();
+    assert_eq!(item.name, "Test Product");
+    assert_eq!(item.price, 100);
+    assert_eq!(item.count, 6);
+    assert!(vm
+        .add_item(Item::new(23, String::new(), 0, 1))
+        .await
+        .is_err());
+    assert!(vm.get_item(23).is_none());
+
+    client.disconnect().await;
+}
+
 #[tokio::test]
 async fn test_remove_item_command_via_nostr() {
     let (keys, admin_keys, client, mut vm, admin_handler, shutdown_tx) = setup().await;
@@ -226,7 +364,7 @@ async fn test_remove_item_command_via_nostr() {
     });
 
     // Spawn admin handler
-    let _ = tokio::spawn(async move {
+    let _admin_handler = tokio::spawn(async move {
         if let Err(e) = admin_handler.handle_events().await {
             eprintln!("Admin handler error: {}", e);
         }
@@ -268,7 +406,7 @@ async fn test_cancel_via_nostr() {
     });
 
     // Spawn admin handler
-    let _ = tokio::spawn(async move {
+    let _admin_handler = tokio::spawn(async move {
         if let Err(e) = admin_handler.handle_events().await {
             eprintln!("Admin handler error: {}", e);
         }
@@ -289,3 +427,759 @@ async fn test_cancel_via_nostr() {
     machine.await.unwrap();
     client.disconnect().await;
 }
+
+#[tokio::test]
+async fn test_admin_commands_are_acknowledged() {
+    let (keys, admin_keys, client, mut vm, admin_handler, shutdown_tx) = setup().await;
+    vm.add_item(Item::new(12, "Test Product".to_string(), 34, 4))
+        .await
+        .unwrap();
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = admin_handler.handle_events().await {
+            eprintln!("Admin handler error: {}", e);
+        }
+    });
+
+    for (id, command) in [
+        ("status", AdminCommand::Status),
+        ("remove-12", AdminCommand::RemoveItem(12)),
+        ("remove-99", AdminCommand::RemoveItem(99)),
+    ] {
+        let message = AdminMessage {
+            id: Some(id.to_string()),
+            command,
+        };
+        send_admin_text(
+            &client,
+            &admin_keys,
+            &keys,
+            &serde_json::to_string(&message).unwrap(),
+        )
+        .await;
+    }
+    send_admin_text(
+        &client,
+        &admin_keys,
+        &keys,
+        r#"{"id":"bad","type":"Explode"}"#,
+    )
+    .await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let responses = admin_responses(&client, &admin_keys, &keys).await;
+    let response = |id: &str| {
+        responses
+            .iter()
+            .find(|response| response.id == id)
+            .unwrap_or_else(|| panic!("no response to {}", id))
+    };
+    let Some(AdminCommandResult::Status(report)) = &response("status").result else {
+        panic!("Status should answer with a status report");
+    };
+    assert_eq!(report.state, "AdminState");
+    assert!(report.under_admin);
+    assert_eq!(report.items.len(), 1);
+    assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+    assert!(report.relays.iter().all(|relay| relay.connected));
+
+    assert_eq!(response("remove-12").status, AdminResponseStatus::Ok);
+    assert!(matches!(
+        response("remove-12").result,
+        Some(AdminCommandResult::Done)
+    ));
+    assert_eq!(response("remove-99").status, AdminResponseStatus::Error);
+    assert!(response("remove-99")
+        .error
+        .as_ref()
+        .unwrap()
+        .contains("ItemDoesNotExist"));
+    assert_eq!(response("bad").status, AdminResponseStatus::Error);
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_shutdown_command_refunds_and_goes_offline() {
+    let (keys, admin_keys, client, mut vm, admin_handler, _shutdown_tx) = setup().await;
+    vm.add_item(Item::new(12, "Test Product".to_string(), 34, 4))
+        .await
+        .unwrap();
+    vm.cancel().await.unwrap();
+    vm.request_item(12).await.unwrap();
+    vm.insert_money(5).await.unwrap();
+
+    let machine = tokio::spawn(async move {
+        // Returns on the admin's Shutdown, no shutdown signal is sent
+        vm.run_machine().await.unwrap();
+        vm.shut_down().await.unwrap();
+
+        assert_eq!(vm.state_name(), "ListeningState");
+        assert_eq!(vm.cash_box().total(), 0, "the 5 inserted are refunded");
+        assert_eq!(vm.get_item(12).unwrap().count, 4);
+    });
+    tokio::spawn(async move {
+        if let Err(e) = admin_handler.handle_events().await {
+            eprintln!("Admin handler error: {}", e);
+        }
+    });
+
+    send_admin_command(&client, &admin_keys, &keys, AdminCommand::Shutdown)
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(10), machine)
+        .await
+        .expect("the machine should stop on Shutdown")
+        .unwrap();
+
+    let events = client
+        .fetch_events(
+            vending_machines_nostr::vending_machine::state_filter(keys.public_key()),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+    let update = vending_machines_nostr::vending_machine::VendingMachineUpdate::parse(
+        &events.first().unwrap().content,
+    )
+    .unwrap();
+    assert!(!update.online);
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_reboot_keeps_inventory() {
+    let (keys, admin_keys, client, mut vm, admin_handler, shutdown_tx) = setup().await;
+    vm.add_item(Item::new(12, "Test Product".to_string(), 34, 4))
+        .await
+        .unwrap();
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert_eq!(vm.state_name(), "ListeningState");
+        assert!(!vm.is_under_admin());
+        assert_eq!(vm.get_item(12).unwrap().count, 4);
+    });
+    tokio::spawn(async move {
+        if let Err(e) = admin_handler.handle_events().await {
+            eprintln!("Admin handler error: {}", e);
+        }
+    });
+
+    let message = AdminMessage {
+        id: Some("reboot".to_string()),
+        command: AdminCommand::Reboot,
+    };
+    send_admin_text(
+        &client,
+        &admin_keys,
+        &keys,
+        &serde_json::to_string(&message).unwrap(),
+    )
+    .await;
+    tokio::time::sleep(Duration::from_secs(4)).await;
+
+    let responses = admin_responses(&client, &admin_keys, &keys).await;
+    assert!(responses
+        .iter()
+        .any(|response| response.id == "reboot" && response.status == AdminResponseStatus::Ok));
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_stale_admin_commands_are_ignored() {
+    let (keys, admin_keys, client, mut vm, admin_handler, shutdown_tx) = setup().await;
+    vm.add_item(Item::new(12, "Test Product".to_string(), 34, 4))
+        .await
+        .unwrap();
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert!(vm.get_item(12).is_some(), "the stale RemoveItem is not run");
+    });
+    tokio::spawn(async move {
+        if let Err(e) = admin_handler.handle_events().await {
+            eprintln!("Admin handler error: {}", e);
+        }
+    });
+
+    let now = nostr_sdk::Timestamp::now();
+    for (id, command, created_at, expiration) in [
+        ("stale", AdminCommand::RemoveItem(12), now - 3600, None),
+        ("expiring", AdminCommand::Status, now, Some(now + 60)),
+    ] {
+        let message = AdminMessage {
+            id: Some(id.to_string()),
+            command,
+        };
+        let encrypted = nostr_sdk::nips::nip44::encrypt(
+            admin_keys.secret_key(),
+            &keys.public_key(),
+            serde_json::to_string(&message).unwrap(),
+            nostr_sdk::nips::nip44::Version::V2,
+        )
+        .unwrap();
+        let event = EventBuilder::new(nostr_sdk::Kind::EncryptedDirectMessage, encrypted)
+            .custom_created_at(created_at)
+            .tags(expiration.map(nostr_sdk::Tag::expiration))
+            .sign_with_keys(&admin_keys)
+            .unwrap();
+        client.send_event(&event).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let responses = admin_responses(&client, &admin_keys, &keys).await;
+    assert!(responses.iter().all(|response| response.id != "stale"));
+    assert!(responses.iter().any(|response| response.id == "expiring"));
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_private_message_commands() {
+    let (keys, admin_keys, client, mut vm, admin_handler, shutdown_tx) = setup_with(|_| {}).await;
+    vm.add_item(Item::new(12, "Test Product".to_string(), 34, 4))
+        .await
+        .unwrap();
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert!(vm.get_item(12).is_none(), "the NIP-17 RemoveItem is run");
+        assert!(vm.get_item(13).is_none(), "the kind 4 AddItem is not");
+    });
+    tokio::spawn(async move {
+        if let Err(e) = admin_handler.handle_events().await {
+            eprintln!("Admin handler error: {}", e);
+        }
+    });
+
+    let message = AdminMessage {
+        id: Some("remove-12".to_string()),
+        command: AdminCommand::RemoveItem(12),
+    };
+    client
+        .send_private_msg(
+            keys.public_key(),
+            serde_json::to_string(&message).unwrap(),
+            [],
+        )
+        .await
+        .unwrap();
+    send_admin_command(
+        &client,
+        &admin_keys,
+        &keys,
+        AdminCommand::AddItem(AddItemRequest {
+            id: 13,
+            name: "Legacy".to_string(),
+            price: 1,
+            count: 1,
+        }),
+    )
+    .await
+    .unwrap();
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    // The reply comes back gift wrapped, nothing is sent in kind 4 DMs
+    assert!(admin_responses(&client, &admin_keys, &keys)
+        .await
+        .is_empty());
+    let gift_wraps = client
+        .fetch_events(
+            nostr_sdk::Filter::new()
+                .kind(nostr_sdk::Kind::GiftWrap)
+                .pubkey(admin_keys.public_key()),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+    let mut responses = Vec::new();
+    for gift_wrap in gift_wraps {
+        let unwrapped = client.unwrap_gift_wrap(&gift_wrap).await.unwrap();
+        assert_eq!(unwrapped.sender, keys.public_key());
+        responses.push(serde_json::from_str::<AdminResponse>(&unwrapped.rumor.content).unwrap());
+    }
+    assert!(responses
+        .iter()
+        .any(|response| response.id == "remove-12" && response.status == AdminResponseStatus::Ok));
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_commands_are_limited_by_role() {
+    let operator_keys = Keys::generate();
+    let auditor_keys = Keys::generate();
+    let roles = HashMap::from([
+        (operator_keys.public_key().to_hex(), Role::Operator),
+        (auditor_keys.public_key().to_hex(), Role::Auditor),
+    ]);
+    let (keys, _, client, mut vm, admin_handler, shutdown_tx) = setup_with(|config| {
+        config.legacy_dm = true;
+        config.roles = roles;
+    })
+    .await;
+    vm.add_item(Item::new(12, "Test Product".to_string(), 34, 4))
+        .await
+        .unwrap();
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        let item = vm.get_item(12).unwrap();
+        assert_eq!(item.count, 6, "the operator restocked");
+        assert_eq!(item.price, 34, "but could not change the price");
+    });
+    tokio::spawn(async move {
+        if let Err(e) = admin_handler.handle_events().await {
+            eprintln!("Admin handler error: {}", e);
+        }
+    });
+
+    for (admin_keys, id, command) in [
+        (
+            &operator_keys,
+            "restock",
+            AdminCommand::AddItem(AddItemRequest {
+                id: 12,
+                name: "Test Product".to_string(),
+                price: 34,
+                count: 2,
+            }),
+        ),
+        (
+            &operator_keys,
+            "price",
+            AdminCommand::ChangePrice(ChangePriceRequest { id: 12, price: 1 }),
+        ),
+        (&operator_keys, "shutdown", AdminCommand::Shutdown),
+        (&auditor_keys, "status", AdminCommand::Status),
+        (&auditor_keys, "remove", AdminCommand::RemoveItem(12)),
+    ] {
+        let message = AdminMessage {
+            id: Some(id.to_string()),
+            command,
+        };
+        send_admin_text(
+            &client,
+            admin_keys,
+            &keys,
+            &serde_json::to_string(&message).unwrap(),
+        )
+        .await;
+    }
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let mut responses = admin_responses(&client, &operator_keys, &keys).await;
+    responses.extend(admin_responses(&client, &auditor_keys, &keys).await);
+    let status = |id: &str| {
+        responses
+            .iter()
+            .find(|response| response.id == id)
+            .unwrap_or_else(|| panic!("no response to {}", id))
+            .status
+    };
+    assert_eq!(status("restock"), AdminResponseStatus::Ok);
+    assert_eq!(status("price"), AdminResponseStatus::Error);
+    assert_eq!(status("shutdown"), AdminResponseStatus::Error);
+    assert_eq!(status("status"), AdminResponseStatus::Ok);
+    assert_eq!(status("remove"), AdminResponseStatus::Error);
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_sensitive_commands_wait_for_approval() {
+    let second_owner = Keys::generate();
+    let second_owner_pubkey = second_owner.public_key().to_hex();
+    let (keys, admin_keys, client, mut vm, admin_handler, shutdown_tx) = setup_with(|config| {
+        config.legacy_dm = true;
+        config.roles = HashMap::from([(second_owner_pubkey, Role::Owner)]);
+        config.approval = ApprovalConfig {
+            threshold: 2,
+            window_secs: 60,
+        };
+    })
+    .await;
+    vm.add_item(Item::new(12, "Test Product".to_string(), 34, 4))
+        .await
+        .unwrap();
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert!(vm.get_item(12).is_none(), "the approved RemoveItem is run");
+    });
+    tokio::spawn(async move {
+        if let Err(e) = admin_handler.handle_events().await {
+            eprintln!("Admin handler error: {}", e);
+        }
+    });
+
+    let message = AdminMessage {
+        id: Some("remove".to_string()),
+        command: AdminCommand::RemoveItem(12),
+    };
+    send_admin_text(
+        &client,
+        &admin_keys,
+        &keys,
+        &serde_json::to_string(&message).unwrap(),
+    )
+    .await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    // The second owner never wrote to the machine, so is asked for approval in a private message
+    let requests = private_admin_responses(&client, &second_owner, &keys).await;
+    let Some(AdminCommandResult::Pending(pending)) = &requests.first().unwrap().result else {
+        panic!("approvers should get the staged command");
+    };
+    assert_eq!(requests[0].status, AdminResponseStatus::Pending);
+    assert_eq!((pending.approvals, pending.required), (1, 2));
+    assert!(matches!(pending.command, AdminCommand::RemoveItem(12)));
+
+    let message = AdminMessage {
+        id: Some("approve".to_string()),
+        command: AdminCommand::Approve(pending.command_id.clone()),
+    };
+    send_admin_text(
+        &client,
+        &second_owner,
+        &keys,
+        &serde_json::to_string(&message).unwrap(),
+    )
+    .await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let responses = admin_responses(&client, &admin_keys, &keys).await;
+    let statuses: Vec<AdminResponseStatus> = responses
+        .iter()
+        .filter(|response| response.id == "remove")
+        .map(|response| response.status)
+        .collect();
+    assert!(statuses.contains(&AdminResponseStatus::Pending));
+    assert!(statuses.contains(&AdminResponseStatus::Ok));
+    let approvals = admin_responses(&client, &second_owner, &keys).await;
+    assert!(approvals
+        .iter()
+        .any(|response| response.id == "approve" && response.status == AdminResponseStatus::Ok));
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_admins_are_managed_remotely() {
+    let admins_path = std::env::temp_dir().join(format!(
+        "vm-admins-{}.json",
+        Keys::generate().public_key().to_hex()
+    ));
+    let path = admins_path.to_string_lossy().to_string();
+    let (keys, admin_keys, client, mut vm, admin_handler, shutdown_tx) = setup_with(|config| {
+        config.legacy_dm = true;
+        config.admins_path = Some(path);
+    })
+    .await;
+    vm.add_item(Item::new(12, "Test Product".to_string(), 34, 4))
+        .await
+        .unwrap();
+    let restocker = Keys::generate();
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert_eq!(
+            vm.get_item(12).unwrap().count,
+            5,
+            "only one restock counted"
+        );
+    });
+    tokio::spawn(async move {
+        if let Err(e) = admin_handler.handle_events().await {
+            eprintln!("Admin handler error: {}", e);
+        }
+    });
+
+    let send = |admin_keys: Keys, id: &str, command: AdminCommand| {
+        let client = client.clone();
+        let keys = keys.clone();
+        let message = serde_json::to_string(&AdminMessage {
+            id: Some(id.to_string()),
+            command,
+        })
+        .unwrap();
+        async move {
+            send_admin_text(&client, &admin_keys, &keys, &message).await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    };
+    let restock = || {
+        AdminCommand::AddItem(AddItemRequest {
+            id: 12,
+            name: "Test Product".to_string(),
+            price: 34,
+            count: 1,
+        })
+    };
+
+    send(
+        admin_keys.clone(),
+        "add",
+        AdminCommand::AddAdmin(AddAdminRequest {
+            pubkey: restocker.public_key().to_bech32().unwrap(),
+            role: Role::Operator,
+        }),
+    )
+    .await;
+    send(restocker.clone(), "restock", restock()).await;
+    send(admin_keys.clone(), "list", AdminCommand::ListAdmins).await;
+    send(
+        admin_keys.clone(),
+        "remove-self",
+        AdminCommand::RemoveAdmin(admin_keys.public_key().to_hex()),
+    )
+    .await;
+    send(
+        admin_keys.clone(),
+        "remove",
+        AdminCommand::RemoveAdmin(restocker.public_key().to_hex()),
+    )
+    .await;
+    send(restocker.clone(), "restock-again", restock()).await;
+
+    let responses = admin_responses(&client, &admin_keys, &keys).await;
+    let response = |id: &str| {
+        responses
+            .iter()
+            .find(|response| response.id == id)
+            .unwrap_or_else(|| panic!("no response to {}", id))
+    };
+    assert_eq!(response("add").status, AdminResponseStatus::Ok);
+    let Some(AdminCommandResult::Admins(admins)) = &response("list").result else {
+        panic!("ListAdmins should list the admins");
+    };
+    assert_eq!(admins.len(), 2);
+    assert!(admins.iter().any(
+        |admin| admin.pubkey == restocker.public_key().to_hex() && admin.role == Role::Operator
+    ));
+    assert_eq!(response("remove-self").status, AdminResponseStatus::Error);
+    assert_eq!(response("remove").status, AdminResponseStatus::Ok);
+
+    let restocker_responses = admin_responses(&client, &restocker, &keys).await;
+    assert!(restocker_responses
+        .iter()
+        .any(|response| response.id == "restock" && response.status == AdminResponseStatus::Ok));
+    assert!(restocker_responses
+        .iter()
+        .all(|response| response.id != "restock-again"));
+
+    // The list survives a restart
+    let saved = AdminStore::new(&admins_path).load().unwrap().unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].pubkey, admin_keys.public_key().to_hex());
+    std::fs::remove_file(admins_path).unwrap();
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_floods_are_rate_limited_and_bad_commands_lock_out() {
+    let careless_admin = Keys::generate();
+    let careless_pubkey = careless_admin.public_key().to_hex();
+    let (keys, admin_keys, client, mut vm, admin_handler, shutdown_tx) = setup_with(|config| {
+        config.legacy_dm = true;
+        config.roles = HashMap::from([(careless_pubkey, Role::Owner)]);
+        config.limits = LimitsConfig {
+            burst: 3,
+            per_minute: 1,
+            backlog: 10,
+            max_failures: 2,
+            lockout_secs: 600,
+        };
+    })
+    .await;
+    vm.add_item(Item::new(12, "Test Product".to_string(), 34, 4))
+        .await
+        .unwrap();
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = admin_handler.handle_events().await {
+            eprintln!("Admin handler error: {}", e);
+        }
+    });
+
+    for id in ["status-1", "status-2", "status-3", "status-4", "status-5"] {
+        let message = AdminMessage {
+            id: Some(id.to_string()),
+            command: AdminCommand::Status,
+        };
+        send_admin_text(
+            &client,
+            &admin_keys,
+            &keys,
+            &serde_json::to_string(&message).unwrap(),
+        )
+        .await;
+    }
+    for text in [
+        "not a command",
+        r#"{"id":"bad","type":"Explode"}"#,
+        r#"{"id":"status","type":"Status"}"#,
+    ] {
+        send_admin_text(&client, &careless_admin, &keys, text).await;
+    }
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let responses = admin_responses(&client, &admin_keys, &keys).await;
+    let answered = |id: &str| responses.iter().any(|response| response.id == id);
+    assert!(answered("status-1") && answered("status-2") && answered("status-3"));
+    assert!(!answered("status-4") && !answered("status-5"));
+    let limited: Vec<&AdminResponse> = responses
+        .iter()
+        .filter(|response| {
+            response
+                .error
+                .as_ref()
+                .is_some_and(|error| error.contains("too many commands"))
+        })
+        .collect();
+    assert_eq!(limited.len(), 1, "the admin is told once");
+
+    let responses = admin_responses(&client, &careless_admin, &keys).await;
+    assert!(responses.iter().any(|response| response
+        .error
+        .as_ref()
+        .is_some_and(|error| error.contains("too many bad commands"))));
+    assert!(responses.iter().all(|response| response.id != "status"));
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_batch_applies_atomically_with_one_state_update() {
+    let keys = Keys::generate();
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            1,
+            "Water".to_string(),
+            10,
+            3,
+        )])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    vm.admin().await.unwrap();
+    // Replaceable events are ordered by their timestamp in seconds
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let client = setup_local_relay_client(Keys::generate()).await;
+    let mut notifications = client.notifications();
+    client
+        .subscribe(
+            vending_machines_nostr::vending_machine::state_filter(keys.public_key())
+                .since(nostr_sdk::Timestamp::now()),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let batch = |commands| AdminRequest {
+        pubkey: keys.public_key(),
+        request_id: "batch".to_string(),
+        protocol: DmProtocol::PrivateMessage,
+        command: AdminCommand::Batch(commands),
+    };
+    let add = |id, count| {
+        AdminCommand::AddItem(AddItemRequest {
+            id,
+            name: format!("Item {}", id),
+            price: 20,
+            count,
+        })
+    };
+
+    // Item 3 is not stocked: nothing of the batch applies
+    let rejected = batch(vec![
+        add(2, 4),
+        AdminCommand::ChangePrice(ChangePriceRequest { id: 3, price: 5 }),
+    ]);
+    assert!(vm.process_next_admin_command(&rejected).await.is_err());
+    assert!(vm.get_item(2).is_none());
+
+    // Only inventory and float commands can be batched
+    let rejected = batch(vec![add(2, 4), AdminCommand::Shutdown]);
+    assert!(vm.process_next_admin_command(&rejected).await.is_err());
+    assert!(vm.get_item(2).is_none());
+    assert!(vm.is_under_admin());
+    assert_eq!(vm.state_name(), "AdminState");
+
+    let restock = batch(vec![
+        add(1, 2),
+        add(2, 4),
+        AdminCommand::ChangePrice(ChangePriceRequest { id: 2, price: 25 }),
+        AdminCommand::EmptyFloat,
+    ]);
+    let Ok(AdminCommandResult::Batch(results)) = vm.process_next_admin_command(&restock).await
+    else {
+        panic!("the batch should apply");
+    };
+    assert_eq!(results.len(), 4);
+    assert!(matches!(results[3], AdminCommandResult::FloatEmptied(_)));
+    assert_eq!(vm.get_item(1).unwrap().count, 5);
+    assert_eq!(vm.get_item(2).unwrap().price, 25);
+
+    // The machine published its state once, after the whole batch
+    let mut updates = 0;
+    while let Ok(Ok(notification)) =
+        tokio::time::timeout(Duration::from_secs(2), notifications.recv()).await
+    {
+        if let nostr_sdk::RelayPoolNotification::Event { .. } = notification {
+            updates += 1;
+        }
+    }
+    assert_eq!(updates, 1);
+
+    client.disconnect().await;
+}
@@ -0,0 +1,248 @@
+use std::time::Duration;
+
+use helper::{setup_local_relay_client, TestMint, LOCAL_RELAY_URL};
+use nostr_sdk::{Client, EventBuilder, Filter, Keys, Kind, Tag};
+use tokio::sync::mpsc;
+use vending_machines_nostr::admin::AdminList;
+use vending_machines_nostr::customer::setup_nostr_customer_handler;
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::payment::cashu::{CashuConfig, CashuMint, Token};
+use vending_machines_nostr::payment::PaymentError;
+use vending_machines_nostr::storage::MemoryStore;
+use vending_machines_nostr::vending_machine::{Item, VendingMachine};
+mod helper;
+
+async fn setup(mint: &TestMint, proofs_path: &str) -> (Keys, VendingMachine, mpsc::Sender<bool>) {
+    let keys = Keys::generate();
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            1,
+            "Chocolate".to_string(),
+            13,
+            2,
+        )])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    vm.set_cashu_mint(
+        CashuMint::new(&CashuConfig {
+            mint_url: mint.url.clone(),
+            proofs_path: proofs_path.to_string(),
+            mint_timeout_secs: 5,
+        })
+        .unwrap(),
+    );
+
+    let handler = setup_nostr_customer_handler(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        AdminList::default(),
+        vm.remote_customer_sender(),
+    )
+    .await
+    .unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = handler.handle_events().await {
+            eprintln!("Nostr customer handler error: {}", e);
+        }
+    });
+
+    (keys, vm, shutdown_tx)
+}
+
+async fn send_dm(client: &Client, customer: &Keys, machine: &Keys, message: String) {
+    let encrypted = nostr_sdk::nips::nip44::encrypt(
+        customer.secret_key(),
+        &machine.public_key(),
+        message,
+        nostr_sdk::nips::nip44::Version::V2,
+    )
+    .unwrap();
+    let event = EventBuilder::new(Kind::EncryptedDirectMessage, encrypted)
+        .tag(Tag::public_key(machine.public_key()))
+        .sign_with_keys(customer)
+        .unwrap();
+    client.send_event(&event).await.unwrap();
+}
+
+async fn replies(client: &Client, customer: &Keys, machine: &Keys) -> Vec<String> {
+    let filter = Filter::new()
+        .kind(Kind::EncryptedDirectMessage)
+        .author(machine.public_key())
+        .pubkey(customer.public_key());
+    client
+        .fetch_events(filter, Duration::from_secs(2))
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|event| {
+            nostr_sdk::nips::nip44::decrypt(
+                customer.secret_key(),
+                &machine.public_key(),
+                &event.content,
+            )
+            .ok()
+        })
+        .collect()
+}
+
+fn proofs_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!(
+            "vm-cashu-{}-{}.json",
+            name,
+            Keys::generate().public_key().to_hex()
+        ))
+        .to_string_lossy()
+        .to_string()
+}
+
+#[tokio::test]
+async fn test_cashu_token_pays_requested_item() {
+    let mint = TestMint::spawn().await;
+    let path = proofs_path("pay");
+    let (keys, mut vm, shutdown_tx) = setup(&mint, &path).await;
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        // Paid over Nostr, so dispensed without pressing dispense
+        assert_eq!(vm.state_name(), "ListeningState");
+        assert_eq!(vm.get_item(1).unwrap().count, 1);
+    });
+
+    let customer = Keys::generate();
+    let client = setup_local_relay_client(customer.clone()).await;
+    send_dm(&client, &customer, &keys, "request 1".to_string()).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let token = mint.issue_token(13);
+    send_dm(&client, &customer, &keys, format!("here you go {}", token)).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let replies = replies(&client, &customer, &keys).await;
+    assert!(replies
+        .iter()
+        .any(|reply| reply.contains("total: 13 units")));
+    assert!(replies.iter().any(|reply| reply.contains("received")));
+
+    let stored: Vec<serde_json::Value> =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let total: u64 = stored.iter().map(|p| p["amount"].as_u64().unwrap()).sum();
+    assert_eq!(total, 13);
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_cashu_overpayment_is_reported_to_payer() {
+    let mint = TestMint::spawn().await;
+    let path = proofs_path("over");
+    let (keys, mut vm, shutdown_tx) = setup(&mint, &path).await;
+    vm.request_item(1).await.unwrap();
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert_eq!(vm.state_name(), "ItemRequestedState");
+    });
+
+    let customer = Keys::generate();
+    let client = setup_local_relay_client(customer.clone()).await;
+    send_dm(&client, &customer, &keys, mint.issue_token(20)).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let replies = replies(&client, &customer, &keys).await;
+    assert!(replies.iter().any(|reply| reply.contains("Payment failed")));
+    assert!(!std::path::Path::new(&path).exists());
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_tokens_in_other_units_are_rejected() {
+    let mint = TestMint::spawn().await;
+    let path = proofs_path("unit");
+    let cashu = CashuMint::new(&CashuConfig {
+        mint_url: mint.url.clone(),
+        proofs_path: path.clone(),
+        mint_timeout_secs: 5,
+    })
+    .unwrap();
+
+    let mut token = Token::parse(&mint.issue_token(13)).unwrap();
+    token.unit = "usd".to_string();
+    assert!(matches!(
+        cashu.redeem(&token).await,
+        Err(PaymentError::Rejected(_))
+    ));
+    assert!(!std::path::Path::new(&path).exists());
+}
+
+#[tokio::test]
+async fn test_cancelled_cashu_payment_is_refunded_in_ecash() {
+    let mint = TestMint::spawn().await;
+    let path = proofs_path("refund");
+    let (keys, mut vm, shutdown_tx) = setup(&mint, &path).await;
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert_eq!(vm.state_name(), "ListeningState");
+        // Paid back in ecash, no coins leave the cash box
+        assert_eq!(vm.cash_box().total(), 0);
+    });
+
+    let customer = Keys::generate();
+    let client = setup_local_relay_client(customer.clone()).await;
+    send_dm(&client, &customer, &keys, "request 1".to_string()).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    send_dm(&client, &customer, &keys, mint.issue_token(5)).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    send_dm(&client, &customer, &keys, "cancel".to_string()).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let replies = replies(&client, &customer, &keys).await;
+    let refund = replies
+        .iter()
+        .find_map(|reply| reply.strip_prefix("Refund of 5 sats: "))
+        .expect("refund token");
+    let token = Token::parse(refund).unwrap();
+    assert_eq!(token.mint, mint.url);
+    assert_eq!(token.amount().unwrap(), 5);
+
+    // The refund came out of the redeemed ecash
+    let stored: Vec<serde_json::Value> =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(stored.is_empty());
+
+    // The customer can redeem the refund
+    let customer_path = proofs_path("customer");
+    let wallet = CashuMint::new(&CashuConfig {
+        mint_url: mint.url.clone(),
+        proofs_path: customer_path.clone(),
+        mint_timeout_secs: 5,
+    })
+    .unwrap();
+    assert_eq!(wallet.redeem(&token).await.unwrap(), 5);
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(customer_path).unwrap();
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use helper::LOCAL_RELAY_URL;
+use nostr_sdk::Keys;
+use tokio::sync::mpsc;
+use vending_machines_nostr::customer::{spawn_interface, CustomerEvent, ScriptedInterface};
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::storage::MemoryStore;
+use vending_machines_nostr::vending_machine::{Item, VendingMachine};
+mod helper;
+
+#[tokio::test]
+async fn test_scripted_customer_buys_item() {
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        Keys::generate(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            5,
+            "Coffee".to_string(),
+            20,
+            3,
+        )])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+
+    spawn_interface(
+        ScriptedInterface::new(vec![
+            // Not allowed before requesting an item, must not stop the machine
+            CustomerEvent::InsertMoney(10),
+            CustomerEvent::RequestItem { id: 5, quantity: 2 },
+            // Refused before paying, the cart is kept
+            CustomerEvent::DispenseItem,
+            CustomerEvent::InsertMoney(20),
+            CustomerEvent::InsertMoney(20),
+            CustomerEvent::DispenseItem,
+        ])
+        .with_delay(Duration::from_millis(100)),
+        vm.customer_sender(),
+    );
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert_eq!(vm.state_name(), "ListeningState");
+        assert_eq!(vm.get_item(5).unwrap().count, 1);
+    });
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+}
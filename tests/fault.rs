@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use helper::{setup_local_relay_client, LOCAL_RELAY_URL};
+use nostr_sdk::{Filter, Keys, Kind};
+use tokio::sync::mpsc;
+use vending_machines_nostr::hardware::{DispenseFault, Dispenser};
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::storage::MemoryStore;
+use vending_machines_nostr::vending_machine::{Item, VendingMachine};
+mod helper;
+
+/// Dispenses `remaining` units, then jams.
+struct JammingDispenser {
+    remaining: u64,
+}
+
+impl Dispenser for JammingDispenser {
+    fn dispense(&mut self, _item: &Item) -> Result<(), DispenseFault> {
+        if self.remaining == 0 {
+            return Err(DispenseFault::Jammed);
+        }
+        self.remaining -= 1;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_jam_refunds_and_takes_slot_out_of_service() {
+    let keys = Keys::generate();
+    let admin_keys = Keys::generate();
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![
+            Item::new(5, "Coffee".to_string(), 20, 3),
+            Item::new(6, "Tea".to_string(), 10, 3),
+        ])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    vm.set_dispenser(Box::new(JammingDispenser { remaining: 1 }));
+    vm.set_admin_pubkeys(vec![admin_keys.public_key()]);
+
+    vm.request_items(5, 2).await.unwrap();
+    vm.insert_money(20).await.unwrap();
+    vm.insert_money(20).await.unwrap();
+    vm.dispense_item().await.unwrap();
+
+    assert_eq!(vm.state_name(), "FaultState");
+    assert_eq!(vm.get_item(5).unwrap().count, 2);
+    assert!(vm.is_out_of_service(5));
+    assert_eq!(vm.available(5), 0);
+    // The unit that jammed was paid back
+    assert_eq!(vm.cash_box().total(), 20);
+
+    // The faulty item cannot be requested, others can
+    assert!(vm.request_item(5).await.is_err());
+    assert_eq!(vm.state_name(), "FaultState");
+    vm.request_item(6).await.unwrap();
+    assert_eq!(vm.state_name(), "ItemRequestedState");
+    vm.cancel().await.unwrap();
+
+    let client = setup_local_relay_client(admin_keys.clone()).await;
+    // Admins that never wrote to the machine are notified in NIP-17 private messages
+    let filter = Filter::new()
+        .kind(Kind::GiftWrap)
+        .pubkey(admin_keys.public_key());
+    let notices = client
+        .fetch_events(filter, Duration::from_secs(2))
+        .await
+        .unwrap();
+    let notice = notices.first().expect("admins should be notified");
+    let unwrapped = client.unwrap_gift_wrap(notice).await.unwrap();
+    assert_eq!(unwrapped.sender, keys.public_key());
+    let message = unwrapped.rumor.content;
+    assert!(message.contains("item 5: jammed"));
+    client.disconnect().await;
+
+    vm.admin().await.unwrap();
+    vm.clear_fault(5).await.unwrap();
+    vm.cancel().await.unwrap();
+    assert!(!vm.is_out_of_service(5));
+    assert_eq!(vm.available(5), 2);
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use helper::LOCAL_RELAY_URL;
+use nostr_sdk::Keys;
+use tokio::sync::mpsc;
+use vending_machines_nostr::hardware::{Simulator, SimulatorConfig};
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::storage::MemoryStore;
+use vending_machines_nostr::vending_machine::{Item, VendingMachine};
+mod helper;
+
+async fn setup(simulator: &Simulator) -> VendingMachine {
+    setup_with_relays(simulator, &[LOCAL_RELAY_URL]).await
+}
+
+async fn setup_with_relays(simulator: &Simulator, relays: &[&str]) -> VendingMachine {
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        Keys::generate(),
+        relays,
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![
+            Item::new(5, "Coffee".to_string(), 21, 3),
+            Item::new(6, "Tea".to_string(), 10, 3),
+        ])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    vm.set_hardware(simulator.hardware());
+    vm
+}
+
+#[tokio::test]
+async fn test_purchase_on_simulator() {
+    let simulator = Simulator::new(SimulatorConfig::default());
+    let mut vm = setup(&simulator).await;
+
+    vm.admin().await.unwrap();
+    vm.load_float(2, 5).await.unwrap();
+    vm.cancel().await.unwrap();
+
+    vm.request_item(5).await.unwrap();
+    vm.insert_money(20).await.unwrap();
+    vm.insert_money(5).await.unwrap();
+    vm.dispense_item().await.unwrap();
+
+    assert_eq!(simulator.accepted(), vec![20, 5]);
+    assert_eq!(simulator.dispensed(), vec![5]);
+    assert_eq!(simulator.paid_out(), BTreeMap::from([(2, 2)]));
+    assert!(simulator
+        .messages()
+        .iter()
+        .any(|message| message.contains("returning change: 4 units")));
+}
+
+#[tokio::test]
+async fn test_simulated_empty_slot_faults() {
+    let simulator = Simulator::new(SimulatorConfig {
+        empty_slots: vec![6],
+        ..Default::default()
+    });
+    let mut vm = setup(&simulator).await;
+
+    vm.request_item(6).await.unwrap();
+    vm.insert_money(10).await.unwrap();
+    vm.dispense_item().await.unwrap();
+
+    assert_eq!(vm.state_name(), "FaultState");
+    assert!(vm.is_out_of_service(6));
+    assert!(simulator.dispensed().is_empty());
+    // The coin that was just accepted is paid back
+    assert_eq!(simulator.paid_out(), BTreeMap::from([(10, 1)]));
+
+    // Coins are handed back while nothing is requested
+    assert!(vm.insert_money(10).await.is_err());
+    assert_eq!(simulator.rejected(), vec![10]);
+
+    // So are coins the machine does not take
+    vm.request_item(5).await.unwrap();
+    assert!(vm.insert_money(7).await.is_err());
+    assert_eq!(simulator.rejected(), vec![10, 7]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_simulated_latency_does_not_stall_other_tasks() {
+    let simulator = Simulator::new(SimulatorConfig {
+        dispense_latency_ms: 1000,
+        ..Default::default()
+    });
+    let mut vm = setup(&simulator).await;
+
+    let ticks = Arc::new(AtomicU64::new(0));
+    let ticker = tokio::spawn({
+        let ticks = ticks.clone();
+        async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                ticks.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+
+    // Runs on the only worker thread, next to the ticker
+    let ticks_while_dispensing = tokio::spawn({
+        let ticks = ticks.clone();
+        async move {
+            vm.request_item(6).await.unwrap();
+            vm.insert_money(10).await.unwrap();
+            let before = ticks.load(Ordering::Relaxed);
+            vm.dispense_item().await.unwrap();
+            ticks.load(Ordering::Relaxed) - before
+        }
+    })
+    .await
+    .unwrap();
+    ticker.abort();
+
+    assert_eq!(simulator.dispensed(), vec![6]);
+    assert!(ticks_while_dispensing >= 5, "{}", ticks_while_dispensing);
+}
+
+#[tokio::test]
+async fn test_coin_is_kept_when_publishing_fails() {
+    let simulator = Simulator::new(SimulatorConfig::default());
+    // Without relays publishing the state fails
+    let mut vm = setup_with_relays(&simulator, &[]).await;
+
+    // The item is requested, only publishing the new state fails
+    assert!(vm.request_item(5).await.is_err());
+    assert_eq!(vm.amount_due(), Some(21));
+    vm.insert_money(20).await.unwrap();
+
+    assert_eq!(vm.amount_due(), Some(1));
+    assert_eq!(simulator.accepted(), vec![20]);
+    assert!(simulator.rejected().is_empty());
+    assert_eq!(vm.cash_box().total(), 20);
+}
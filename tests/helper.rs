@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use std::time::Duration;
 use tokio::time::sleep;
 
@@ -14,3 +16,337 @@ pub async fn setup_local_relay_client(keys: nostr_sdk::Keys) -> nostr_sdk::Clien
 
     client
 }
+
+/// Stand-in Lightning wallet answering NIP-47 requests over the local relay.
+///
+/// Invoices are created unpaid; call `pay` to settle one.
+pub struct TestWallet {
+    pub uri: nostr_sdk::nips::nip47::NostrWalletConnectURI,
+    invoices: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, bool>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl TestWallet {
+    pub async fn spawn() -> Self {
+        use nostr_sdk::nips::nip04;
+        use nostr_sdk::nips::nip47::{
+            LookupInvoiceResponse, MakeInvoiceResponse, Method, NostrWalletConnectURI, Request,
+            RequestParams, Response, ResponseResult,
+        };
+        use nostr_sdk::{EventBuilder, Filter, JsonUtil, Keys, Kind, RelayUrl, Tag, Timestamp};
+
+        let wallet_keys = Keys::generate();
+        let app_secret = Keys::generate().secret_key().clone();
+        let uri = NostrWalletConnectURI::new(
+            wallet_keys.public_key(),
+            vec![RelayUrl::parse(LOCAL_RELAY_URL).unwrap()],
+            app_secret,
+            None,
+        );
+
+        let client = setup_local_relay_client(wallet_keys.clone()).await;
+        client
+            .subscribe(
+                Filter::new()
+                    .kind(Kind::WalletConnectRequest)
+                    .pubkey(wallet_keys.public_key()),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let invoices: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, bool>>> =
+            Default::default();
+        let state = invoices.clone();
+        let task = tokio::spawn(async move {
+            let _ = client
+                .handle_notifications(|notification| {
+                    let client = client.clone();
+                    let state = state.clone();
+                    let wallet_keys = wallet_keys.clone();
+                    async move {
+                        let nostr_sdk::RelayPoolNotification::Event { event, .. } = notification
+                        else {
+                            return Ok(false);
+                        };
+                        if event.kind != Kind::WalletConnectRequest {
+                            return Ok(false);
+                        }
+                        let plain = nip04::decrypt(
+                            wallet_keys.secret_key(),
+                            &event.pubkey,
+                            &event.content,
+                        )?;
+                        let request = Request::from_json(plain)?;
+
+                        let result = match request.params {
+                            RequestParams::MakeInvoice(params) => {
+                                let payment_hash = Keys::generate().public_key().to_hex();
+                                state.lock().unwrap().insert(payment_hash.clone(), false);
+                                ResponseResult::MakeInvoice(MakeInvoiceResponse {
+                                    invoice: format!(
+                                        "lnbcrt{}n1test{}",
+                                        params.amount / 1000,
+                                        payment_hash
+                                    ),
+                                    payment_hash,
+                                })
+                            }
+                            RequestParams::LookupInvoice(params) => {
+                                let payment_hash = params.payment_hash.unwrap_or_default();
+                                let paid = state
+                                    .lock()
+                                    .unwrap()
+                                    .get(&payment_hash)
+                                    .copied()
+                                    .unwrap_or(false);
+                                ResponseResult::LookupInvoice(LookupInvoiceResponse {
+                                    transaction_type: None,
+                                    invoice: None,
+                                    description: None,
+                                    description_hash: None,
+                                    preimage: None,
+                                    payment_hash,
+                                    amount: 0,
+                                    fees_paid: 0,
+                                    created_at: Timestamp::now(),
+                                    expires_at: None,
+                                    settled_at: paid.then(Timestamp::now),
+                                    metadata: None,
+                                })
+                            }
+                            _ => return Ok(false),
+                        };
+
+                        let response = Response {
+                            result_type: match result {
+                                ResponseResult::MakeInvoice(_) => Method::MakeInvoice,
+                                _ => Method::LookupInvoice,
+                            },
+                            error: None,
+                            result: Some(result),
+                        };
+                        let content = nip04::encrypt(
+                            wallet_keys.secret_key(),
+                            &event.pubkey,
+                            response.as_json(),
+                        )?;
+                        let reply = EventBuilder::new(Kind::WalletConnectResponse, content)
+                            .tag(Tag::public_key(event.pubkey))
+                            .tag(Tag::event(event.id))
+                            .sign_with_keys(&wallet_keys)?;
+                        client.send_event(&reply).await?;
+                        Ok(false)
+                    }
+                })
+                .await;
+        });
+
+        Self {
+            uri,
+            invoices,
+            task,
+        }
+    }
+
+    /// Connection string the machine should use to reach this wallet.
+    pub fn connection_string(&self) -> String {
+        self.uri.to_string()
+    }
+
+    /// Marks the invoice with `payment_hash` as paid.
+    pub fn pay(&self, payment_hash: &str) {
+        self.invoices
+            .lock()
+            .unwrap()
+            .insert(payment_hash.to_string(), true);
+    }
+}
+
+impl Drop for TestWallet {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Stand-in Cashu mint serving `/v1/keys` and `/v1/swap` over HTTP on a random local port.
+pub struct TestMint {
+    pub url: String,
+    keyset_id: String,
+    keys: std::collections::HashMap<u64, nostr_sdk::secp256k1::SecretKey>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl TestMint {
+    pub async fn spawn() -> Self {
+        use nostr_sdk::secp256k1::{rand, SecretKey};
+        use std::collections::{HashMap, HashSet};
+        use std::sync::{Arc, Mutex};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use vending_machines_nostr::payment::cashu::{dhke, Proof};
+
+        let keyset_id = "009a1f293253e41e".to_string();
+        let keys: HashMap<u64, SecretKey> = (0..16)
+            .map(|bit| (1u64 << bit, SecretKey::new(&mut rand::thread_rng())))
+            .collect();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let keys_body = serde_json::json!({
+            "keysets": [{
+                "id": keyset_id,
+                "unit": "sat",
+                "keys": keys
+                    .iter()
+                    .map(|(amount, k)| {
+                        (amount.to_string(), k.public_key(nostr_sdk::SECP256K1).to_string())
+                    })
+                    .collect::<HashMap<_, _>>(),
+            }]
+        })
+        .to_string();
+
+        let spent: Arc<Mutex<HashSet<String>>> = Default::default();
+        let mint_keys = keys.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+
+                // Read headers, then the body announced by Content-Length
+                let mut raw = Vec::new();
+                let mut buf = [0u8; 4096];
+                let header_end = loop {
+                    let n = socket.read(&mut buf).await.unwrap_or(0);
+                    if n == 0 {
+                        break None;
+                    }
+                    raw.extend_from_slice(&buf[..n]);
+                    if let Some(pos) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break Some(pos + 4);
+                    }
+                };
+                let Some(header_end) = header_end else {
+                    continue;
+                };
+                let head = String::from_utf8_lossy(&raw[..header_end]).to_string();
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                while raw.len() < header_end + length {
+                    let n = socket.read(&mut buf).await.unwrap_or(0);
+                    if n == 0 {
+                        break;
+                    }
+                    raw.extend_from_slice(&buf[..n]);
+                }
+                let body = &raw[header_end..];
+
+                let (status, response) = if head.starts_with("GET /v1/keys") {
+                    ("200 OK", keys_body.clone())
+                } else if head.starts_with("POST /v1/swap") {
+                    let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+                    let inputs: Vec<Proof> =
+                        serde_json::from_value(request["inputs"].clone()).unwrap();
+                    let mut spent = spent.lock().unwrap();
+                    let valid = inputs.iter().all(|proof| {
+                        mint_keys.get(&proof.amount).is_some_and(|k| {
+                            dhke::verify(k, &proof.c.parse().unwrap(), proof.secret.as_bytes())
+                        })
+                    });
+                    if !valid {
+                        (
+                            "400 Bad Request",
+                            r#"{"detail":"Invalid proof","code":10003}"#.to_string(),
+                        )
+                    } else if inputs.iter().any(|proof| spent.contains(&proof.secret)) {
+                        (
+                            "400 Bad Request",
+                            r#"{"detail":"Token already spent","code":11001}"#.to_string(),
+                        )
+                    } else {
+                        spent.extend(inputs.iter().map(|proof| proof.secret.clone()));
+                        let signatures: Vec<serde_json::Value> = request["outputs"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|output| {
+                                let amount = output["amount"].as_u64().unwrap();
+                                let blinded = output["B_"].as_str().unwrap().parse().unwrap();
+                                let c = dhke::sign(&blinded, &mint_keys[&amount]).unwrap();
+                                serde_json::json!({
+                                    "amount": amount,
+                                    "id": output["id"],
+                                    "C_": c.to_string(),
+                                })
+                            })
+                            .collect();
+                        (
+                            "200 OK",
+                            serde_json::json!({ "signatures": signatures }).to_string(),
+                        )
+                    }
+                } else {
+                    (
+                        "404 Not Found",
+                        r#"{"detail":"not found","code":0}"#.to_string(),
+                    )
+                };
+
+                let reply = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    response.len(),
+                    response
+                );
+                let _ = socket.write_all(reply.as_bytes()).await;
+            }
+        });
+
+        Self {
+            url,
+            keyset_id,
+            keys,
+            task,
+        }
+    }
+
+    /// Issues a `cashuA` token worth `amount` sats signed by this mint.
+    pub fn issue_token(&self, amount: u64) -> String {
+        use vending_machines_nostr::payment::cashu::{dhke, split_amount, Proof, Token};
+
+        let proofs = split_amount(amount)
+            .into_iter()
+            .map(|value| {
+                let secret = nostr_sdk::Keys::generate().secret_key().to_secret_hex();
+                let y = dhke::hash_to_curve(secret.as_bytes()).unwrap();
+                Proof {
+                    amount: value,
+                    id: self.keyset_id.clone(),
+                    c: dhke::sign(&y, &self.keys[&value]).unwrap().to_string(),
+                    secret,
+                }
+            })
+            .collect();
+        Token {
+            mint: self.url.clone(),
+            unit: "sat".to_string(),
+            memo: None,
+            proofs,
+        }
+        .to_v3_string()
+    }
+}
+
+impl Drop for TestMint {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
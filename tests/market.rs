@@ -0,0 +1,284 @@
+use std::time::Duration;
+
+use helper::{setup_local_relay_client, TestWallet, LOCAL_RELAY_URL};
+use nostr_sdk::nips::nip15::{MerchantPaymentRequest, MerchantVerifyPayment, ProductData};
+use nostr_sdk::{Client, EventBuilder, Filter, JsonUtil, Keys, Kind, Tag};
+use tokio::sync::mpsc;
+use vending_machines_nostr::admin::AdminList;
+use vending_machines_nostr::customer::setup_nostr_customer_handler;
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::market::MarketConfig;
+use vending_machines_nostr::payment::nwc::{NwcConfig, NwcWallet};
+use vending_machines_nostr::storage::MemoryStore;
+use vending_machines_nostr::vending_machine::{Item, VendingMachine};
+mod helper;
+
+async fn setup(wallet: &TestWallet) -> (Keys, VendingMachine, mpsc::Sender<bool>) {
+    let keys = Keys::generate();
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![
+            Item::new(1, "Chocolate".to_string(), 13, 2),
+            Item::new(2, "Coffee".to_string(), 20, 1),
+        ])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    vm.set_lightning_wallet(
+        NwcWallet::connect(&NwcConfig {
+            uri: wallet.connection_string(),
+            invoice_expiry_secs: 60,
+            poll_interval_secs: 1,
+            request_timeout_secs: 10,
+        })
+        .await
+        .unwrap(),
+    );
+    vm.set_market(MarketConfig::default());
+    vm.publish_market().await.unwrap();
+
+    let handler = setup_nostr_customer_handler(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        AdminList::default(),
+        vm.remote_customer_sender(),
+    )
+    .await
+    .unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = handler.handle_events().await {
+            eprintln!("Nostr customer handler error: {}", e);
+        }
+    });
+
+    (keys, vm, shutdown_tx)
+}
+
+/// Sends `order` the way marketplace clients do, as a NIP-04 DM.
+async fn send_order(client: &Client, customer: &Keys, machine: &Keys, order: &str) {
+    let content =
+        nostr_sdk::nips::nip04::encrypt(customer.secret_key(), &machine.public_key(), order)
+            .unwrap();
+    let event = EventBuilder::new(Kind::EncryptedDirectMessage, content)
+        .tag(Tag::public_key(machine.public_key()))
+        .sign_with_keys(customer)
+        .unwrap();
+    client.send_event(&event).await.unwrap();
+}
+
+async fn replies(client: &Client, customer: &Keys, machine: &Keys) -> Vec<String> {
+    client
+        .fetch_events(
+            Filter::new()
+                .kind(Kind::EncryptedDirectMessage)
+                .author(machine.public_key())
+                .pubkey(customer.public_key()),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|event| {
+            nostr_sdk::nips::nip04::decrypt(
+                customer.secret_key(),
+                &machine.public_key(),
+                &event.content,
+            )
+            .ok()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_marketplace_order_is_paid_and_listing_updated() {
+    let wallet = TestWallet::spawn().await;
+    let keys = Keys::generate();
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![
+            Item::new(1, "Chocolate".to_string(), 13, 2),
+            Item::new(2, "Coffee".to_string(), 20, 1),
+        ])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    vm.set_lightning_wallet(
+        NwcWallet::connect(&NwcConfig {
+            uri: wallet.connection_string(),
+            invoice_expiry_secs: 60,
+            poll_interval_secs: 1,
+            request_timeout_secs: 10,
+        })
+        .await
+        .unwrap(),
+    );
+    vm.set_market(MarketConfig::default());
+    vm.publish_market().await.unwrap();
+
+    let handler = setup_nostr_customer_handler(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        AdminList::default(),
+        vm.remote_customer_sender(),
+    )
+    .await
+    .unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = handler.handle_events().await {
+            eprintln!("Nostr customer handler error: {}", e);
+        }
+    });
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert_eq!(vm.get_item(1).unwrap().count, 1);
+    });
+
+    let customer = Keys::generate();
+    let client = setup_local_relay_client(customer.clone()).await;
+    let stalls = client
+        .fetch_events(
+            Filter::new().kind(Kind::SetStall).author(keys.public_key()),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+    assert_eq!(stalls.len(), 1);
+    let products = Filter::new()
+        .kind(Kind::SetProduct)
+        .author(keys.public_key());
+    assert_eq!(
+        client
+            .fetch_events(products.clone(), Duration::from_secs(2))
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+
+    // Replaceable events are ordered by their timestamp in seconds
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    // Marketplace clients send orders as NIP-04 DMs
+    let order = r#"{"id":"order-1","type":0,"name":null,"address":null,"message":null,
+        "contact":{"nostr":null,"phone":null,"email":null},
+        "items":[{"id":"1","quantity":1}],"shipping_id":"none"}"#;
+    let content =
+        nostr_sdk::nips::nip04::encrypt(customer.secret_key(), &keys.public_key(), order).unwrap();
+    let event = EventBuilder::new(Kind::EncryptedDirectMessage, content)
+        .tag(Tag::public_key(keys.public_key()))
+        .sign_with_keys(&customer)
+        .unwrap();
+    client.send_event(&event).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    // The unit in the cart is no longer listed as available
+    let listing = client
+        .fetch_events(products.clone().identifier("1"), Duration::from_secs(2))
+        .await
+        .unwrap();
+    let product = ProductData::from_json(&listing.first().unwrap().content).unwrap();
+    assert_eq!(product.quantity, 1);
+
+    let replies = || async {
+        client
+            .fetch_events(
+                Filter::new()
+                    .kind(Kind::EncryptedDirectMessage)
+                    .author(keys.public_key())
+                    .pubkey(customer.public_key()),
+                Duration::from_secs(2),
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|event| {
+                nostr_sdk::nips::nip04::decrypt(
+                    customer.secret_key(),
+                    &keys.public_key(),
+                    &event.content,
+                )
+                .ok()
+            })
+            .collect::<Vec<String>>()
+    };
+    let request = replies()
+        .await
+        .iter()
+        .find_map(|reply| MerchantPaymentRequest::from_json(reply).ok())
+        .expect("a payment request should be sent for the order");
+    assert_eq!(request.id, "order-1");
+    let bolt11 = &request.payment_options[0].link;
+    // The test wallet embeds the payment hash in the invoice
+    wallet.pay(bolt11.rsplit("test").next().unwrap());
+    tokio::time::sleep(Duration::from_secs(4)).await;
+
+    let status = replies()
+        .await
+        .iter()
+        .find_map(|reply| MerchantVerifyPayment::from_json(reply).ok())
+        .expect("the customer should be told the order was paid");
+    assert!(status.paid && status.shipped);
+
+    let listing = client
+        .fetch_events(products.identifier("1"), Duration::from_secs(2))
+        .await
+        .unwrap();
+    let product = ProductData::from_json(&listing.first().unwrap().content).unwrap();
+    assert_eq!(product.quantity, 1);
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_order_with_unavailable_line_is_rejected_whole() {
+    let wallet = TestWallet::spawn().await;
+    let (keys, mut vm, shutdown_tx) = setup(&wallet).await;
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        // Nothing of the order was put in a cart
+        assert_eq!(vm.state_name(), "ListeningState");
+        assert_eq!(vm.available(1), 2);
+        assert!(vm.pending_invoice().is_none());
+    });
+
+    let customer = Keys::generate();
+    let client = setup_local_relay_client(customer.clone()).await;
+    // Only one Coffee is stocked
+    let order = r#"{"id":"order-2","type":0,"name":null,"address":null,"message":null,
+        "contact":{"nostr":null,"phone":null,"email":null},
+        "items":[{"id":"1","quantity":1},{"id":"2","quantity":2}],"shipping_id":"none"}"#;
+    send_order(&client, &customer, &keys, order).await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let replies = replies(&client, &customer, &keys).await;
+    assert!(replies
+        .iter()
+        .any(|reply| reply.contains("item 2: only 1 available")));
+    assert!(!replies
+        .iter()
+        .any(|reply| MerchantPaymentRequest::from_json(reply).is_ok()));
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
@@ -0,0 +1,252 @@
+use std::time::Duration;
+
+use helper::{setup_local_relay_client, TestMint, LOCAL_RELAY_URL};
+use nostr_sdk::{Client, EventBuilder, Filter, Keys, Kind, Tag};
+use tokio::sync::mpsc;
+use vending_machines_nostr::admin::{roles::Role, AdminList};
+use vending_machines_nostr::customer::setup_nostr_customer_handler;
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::payment::cashu::{CashuConfig, CashuMint};
+use vending_machines_nostr::storage::MemoryStore;
+use vending_machines_nostr::vending_machine::{Item, VendingMachine};
+mod helper;
+
+async fn setup(mint: &TestMint, admins: AdminList) -> (Keys, VendingMachine, mpsc::Sender<bool>) {
+    let keys = Keys::generate();
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            1,
+            "Chocolate".to_string(),
+            13,
+            2,
+        )])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    vm.set_cashu_mint(
+        CashuMint::new(&CashuConfig {
+            mint_url: mint.url.clone(),
+            proofs_path: std::env::temp_dir()
+                .join(format!(
+                    "vm-nostr-customer-{}.json",
+                    keys.public_key().to_hex()
+                ))
+                .to_string_lossy()
+                .to_string(),
+            mint_timeout_secs: 5,
+        })
+        .unwrap(),
+    );
+
+    let handler = setup_nostr_customer_handler(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        admins,
+        vm.remote_customer_sender(),
+    )
+    .await
+    .unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = handler.handle_events().await {
+            eprintln!("Nostr customer handler error: {}", e);
+        }
+    });
+
+    (keys, vm, shutdown_tx)
+}
+
+async fn send_dm(client: &Client, customer: &Keys, machine: &Keys, message: &str) {
+    let encrypted = nostr_sdk::nips::nip44::encrypt(
+        customer.secret_key(),
+        &machine.public_key(),
+        message,
+        nostr_sdk::nips::nip44::Version::V2,
+    )
+    .unwrap();
+    let event = EventBuilder::new(Kind::EncryptedDirectMessage, encrypted)
+        .tag(Tag::public_key(machine.public_key()))
+        .sign_with_keys(customer)
+        .unwrap();
+    client.send_event(&event).await.unwrap();
+}
+
+async fn replies(client: &Client, customer: &Keys, machine: &Keys) -> Vec<String> {
+    let filter = Filter::new()
+        .kind(Kind::EncryptedDirectMessage)
+        .author(machine.public_key())
+        .pubkey(customer.public_key());
+    client
+        .fetch_events(filter, Duration::from_secs(2))
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|event| {
+            nostr_sdk::nips::nip44::decrypt(
+                customer.secret_key(),
+                &machine.public_key(),
+                &event.content,
+            )
+            .ok()
+        })
+        .collect()
+}
+
+async fn private_replies(client: &Client, customer: &Keys) -> Vec<String> {
+    let filter = Filter::new()
+        .kind(Kind::GiftWrap)
+        .pubkey(customer.public_key());
+    let mut replies = Vec::new();
+    for event in client
+        .fetch_events(filter, Duration::from_secs(2))
+        .await
+        .unwrap()
+    {
+        if let Ok(unwrapped) = client.unwrap_gift_wrap(&event).await {
+            replies.push(unwrapped.rumor.content);
+        }
+    }
+    replies
+}
+
+#[tokio::test]
+async fn test_private_message_order_gets_private_reply() {
+    let mint = TestMint::spawn().await;
+    let (keys, mut vm, shutdown_tx) = setup(&mint, AdminList::default()).await;
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert_eq!(vm.state_name(), "ItemRequestedState");
+        assert_eq!(vm.amount_due(), Some(26));
+    });
+
+    let customer = Keys::generate();
+    let client = setup_local_relay_client(customer.clone()).await;
+    client
+        .send_private_msg(keys.public_key(), "request 1 2", [])
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let replies = private_replies(&client, &customer).await;
+    assert!(replies
+        .iter()
+        .any(|reply| reply.contains("total: 26 units")));
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_second_customer_cannot_take_over_session() {
+    let mint = TestMint::spawn().await;
+    let (keys, mut vm, shutdown_tx) = setup(&mint, AdminList::default()).await;
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        // The first customer's cart survives the second customer's cancel
+        assert_eq!(vm.state_name(), "ItemRequestedState");
+        assert_eq!(vm.amount_due(), Some(13));
+    });
+
+    let first = Keys::generate();
+    let first_client = setup_local_relay_client(first.clone()).await;
+    let second = Keys::generate();
+    let second_client = setup_local_relay_client(second.clone()).await;
+
+    send_dm(&first_client, &first, &keys, "request 1").await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    send_dm(&second_client, &second, &keys, "cancel").await;
+    send_dm(&second_client, &second, &keys, "what is this?").await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let replies = replies(&second_client, &second, &keys).await;
+    assert!(replies
+        .iter()
+        .any(|reply| reply.contains("serving another customer")));
+    assert!(replies.iter().any(|reply| reply.contains("menu")));
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    first_client.disconnect().await;
+    second_client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_admin_list_changes_apply_to_customer_messages() {
+    let mint = TestMint::spawn().await;
+    let admins = AdminList::default();
+    let (keys, mut vm, shutdown_tx) = setup(&mint, admins.clone()).await;
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+    });
+
+    let admin = Keys::generate();
+    let client = setup_local_relay_client(admin.clone()).await;
+
+    // Added after the handler started: not answered as a customer
+    admins
+        .lock()
+        .unwrap()
+        .insert(admin.public_key(), Role::Operator);
+    send_dm(&client, &admin, &keys, "menu").await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(replies(&client, &admin, &keys).await.is_empty());
+
+    // Removed: a customer again
+    admins.lock().unwrap().remove(&admin.public_key());
+    send_dm(&client, &admin, &keys, "menu").await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(replies(&client, &admin, &keys)
+        .await
+        .iter()
+        .any(|reply| reply.contains("Chocolate")));
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_help_replies_are_rate_limited() {
+    let mint = TestMint::spawn().await;
+    let (keys, mut vm, shutdown_tx) = setup(&mint, AdminList::default()).await;
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+    });
+
+    let stranger = Keys::generate();
+    let client = setup_local_relay_client(stranger.clone()).await;
+    for message in ["hello", "hi", "anyone?", "hello?"] {
+        send_dm(&client, &stranger, &keys, message).await;
+    }
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let replies = replies(&client, &stranger, &keys).await;
+    assert_eq!(
+        replies
+            .iter()
+            .filter(|reply| reply.contains("to see the items"))
+            .count(),
+        2
+    );
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+    client.disconnect().await;
+}
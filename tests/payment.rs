@@ -0,0 +1,333 @@
+use std::time::Duration;
+
+use helper::{TestWallet, LOCAL_RELAY_URL};
+use nostr_sdk::Keys;
+use tokio::sync::mpsc;
+use vending_machines_nostr::journal::{FileJournal, Journal, JournalEvent, MemoryJournal};
+use vending_machines_nostr::payment::nwc::{NwcConfig, NwcWallet};
+use vending_machines_nostr::storage::MemoryStore;
+use vending_machines_nostr::vending_machine::{Item, VendingMachine};
+mod helper;
+
+#[tokio::test]
+async fn test_lightning_invoice_paid_moves_to_has_money() {
+    let wallet = TestWallet::spawn().await;
+
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        Keys::generate(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            5,
+            "Coffee".to_string(),
+            21,
+            3,
+        )])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+
+    let nwc = NwcWallet::connect(&NwcConfig {
+        uri: wallet.connection_string(),
+        invoice_expiry_secs: 60,
+        poll_interval_secs: 1,
+        request_timeout_secs: 10,
+    })
+    .await
+    .unwrap();
+    vm.set_lightning_wallet(nwc);
+
+    vm.request_item(5).await.unwrap();
+    let invoice = vm
+        .pending_invoice()
+        .cloned()
+        .expect("an invoice should be created for the requested item");
+    assert_eq!(invoice.amount, 21);
+    assert_eq!(vm.state_name(), "ItemRequestedState");
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert_eq!(vm.state_name(), "HasMoneyState");
+        assert!(vm.pending_invoice().is_none());
+    });
+
+    wallet.pay(&invoice.payment_hash);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_partial_payments_accumulate_and_return_change() {
+    let path = std::env::temp_dir().join(format!(
+        "vm-change-{}.jsonl",
+        Keys::generate().public_key().to_hex()
+    ));
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        Keys::generate(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            5,
+            "Coffee".to_string(),
+            21,
+            3,
+        )])),
+        Box::new(FileJournal::open(&path).unwrap()),
+    )
+    .await
+    .unwrap();
+
+    vm.admin().await.unwrap();
+    vm.load_float(2, 2).await.unwrap();
+    vm.cancel().await.unwrap();
+
+    vm.request_item(5).await.unwrap();
+    vm.insert_money(10).await.unwrap();
+    assert_eq!(vm.state_name(), "ItemRequestedState");
+    vm.insert_money(10).await.unwrap();
+    assert_eq!(vm.state_name(), "ItemRequestedState");
+    vm.insert_money(5).await.unwrap();
+    assert_eq!(vm.state_name(), "HasMoneyState");
+    vm.dispense_item().await.unwrap();
+    assert_eq!(vm.get_item(5).unwrap().count, 2);
+
+    let events: Vec<_> = FileJournal::open(&path)
+        .unwrap()
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.event)
+        .collect();
+    assert!(events.contains(&JournalEvent::ChangeReturned { amount: 4 }));
+    assert!(vm.cash_box().coins().get(&2).is_none());
+    assert_eq!(vm.cash_box().total(), 25);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_coin_refused_when_change_cannot_be_made() {
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        Keys::generate(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            5,
+            "Coffee".to_string(),
+            21,
+            3,
+        )])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    assert!(vm.exact_change_only());
+
+    vm.request_item(5).await.unwrap();
+    assert!(vm.insert_money(50).await.is_err());
+    assert_eq!(vm.state_name(), "ItemRequestedState");
+    assert_eq!(vm.cash_box().total(), 0);
+
+    vm.insert_money(20).await.unwrap();
+    vm.insert_money(1).await.unwrap();
+    assert_eq!(vm.state_name(), "HasMoneyState");
+}
+
+#[tokio::test]
+async fn test_cart_is_paid_once_and_dispensed_unit_by_unit() {
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        Keys::generate(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![
+            Item::new(5, "Coffee".to_string(), 20, 3),
+            Item::new(6, "Cookie".to_string(), 5, 1),
+        ])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+
+    vm.request_items(5, 2).await.unwrap();
+    vm.request_item(6).await.unwrap();
+    assert_eq!(vm.amount_due(), Some(45));
+
+    // More cookies than in stock are not added
+    assert!(vm.request_item(6).await.is_err());
+    assert_eq!(vm.amount_due(), Some(45));
+
+    vm.insert_money(20).await.unwrap();
+    assert_eq!(vm.amount_due(), Some(25));
+    vm.insert_money(20).await.unwrap();
+    vm.insert_money(5).await.unwrap();
+    assert_eq!(vm.state_name(), "HasMoneyState");
+
+    vm.dispense_item().await.unwrap();
+    assert_eq!(vm.state_name(), "ListeningState");
+    assert_eq!(vm.get_item(5).unwrap().count, 1);
+    assert_eq!(vm.get_item(6).unwrap().count, 0);
+}
+
+async fn lightning_machine(
+    wallet: &TestWallet,
+    journal: Box<dyn Journal>,
+) -> (VendingMachine, mpsc::Sender<bool>) {
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        Keys::generate(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            5,
+            "Coffee".to_string(),
+            21,
+            3,
+        )])),
+        journal,
+    )
+    .await
+    .unwrap();
+    let nwc = NwcWallet::connect(&NwcConfig {
+        uri: wallet.connection_string(),
+        invoice_expiry_secs: 60,
+        poll_interval_secs: 1,
+        request_timeout_secs: 10,
+    })
+    .await
+    .unwrap();
+    vm.set_lightning_wallet(nwc);
+    (vm, shutdown_tx)
+}
+
+#[tokio::test]
+async fn test_superseded_invoice_paid_late_is_credited() {
+    let wallet = TestWallet::spawn().await;
+    let (mut vm, shutdown_tx) =
+        lightning_machine(&wallet, Box::new(MemoryJournal::default())).await;
+
+    vm.request_item(5).await.unwrap();
+    let first = vm.pending_invoice().cloned().unwrap();
+    vm.insert_money(1).await.unwrap();
+    assert_eq!(vm.pending_invoice().unwrap().amount, 20);
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        // The overpaid sat is returned as change on dispensing
+        assert_eq!(vm.state_name(), "HasMoneyState");
+        assert!(vm.pending_invoice().is_none());
+    });
+
+    wallet.pay(&first.payment_hash);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_invoice_paid_after_cancel_is_refunded() {
+    let wallet = TestWallet::spawn().await;
+    let path = std::env::temp_dir().join(format!(
+        "vm-late-{}.jsonl",
+        Keys::generate().public_key().to_hex()
+    ));
+    let (mut vm, shutdown_tx) =
+        lightning_machine(&wallet, Box::new(FileJournal::open(&path).unwrap())).await;
+
+    vm.request_item(5).await.unwrap();
+    let invoice = vm.pending_invoice().cloned().unwrap();
+    vm.cancel().await.unwrap();
+    assert!(vm.pending_invoice().is_none());
+
+    let machine = tokio::spawn(async move {
+        if let Err(e) = vm.run_machine().await {
+            eprintln!("Vending machine error: {}", e);
+        }
+        assert_eq!(vm.state_name(), "ListeningState");
+    });
+
+    wallet.pay(&invoice.payment_hash);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    shutdown_tx.send(true).await.unwrap();
+    machine.await.unwrap();
+
+    // The cash box is empty, so the payment is owed to the customer
+    let events: Vec<_> = FileJournal::open(&path)
+        .unwrap()
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.event)
+        .collect();
+    assert!(events.contains(&JournalEvent::RefundOwed { amount: 21 }));
+    assert!(!events.contains(&JournalEvent::MoneyInserted { amount: 21 }));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_silent_wallet_does_not_hold_up_the_machine() {
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        Keys::generate(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            5,
+            "Coffee".to_string(),
+            21,
+            3,
+        )])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    // No wallet service behind this connection string ever answers
+    let uri = format!(
+        "nostr+walletconnect://{}?relay={}&secret={}",
+        Keys::generate().public_key().to_hex(),
+        LOCAL_RELAY_URL,
+        Keys::generate().secret_key().to_secret_hex()
+    );
+    let nwc = NwcWallet::connect(&NwcConfig {
+        uri,
+        invoice_expiry_secs: 60,
+        poll_interval_secs: 1,
+        request_timeout_secs: 60,
+    })
+    .await
+    .unwrap();
+    vm.set_lightning_wallet(nwc);
+
+    let started = std::time::Instant::now();
+    vm.request_item(5).await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(30));
+    assert!(vm.pending_invoice().is_none());
+
+    // The customer can still pay in coins
+    vm.insert_money(20).await.unwrap();
+    assert_eq!(vm.amount_due(), Some(1));
+}
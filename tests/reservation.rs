@@ -0,0 +1,50 @@
+use helper::LOCAL_RELAY_URL;
+use nostr_sdk::Keys;
+use tokio::sync::mpsc;
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::storage::MemoryStore;
+use vending_machines_nostr::vending_machine::{Item, VendingMachine};
+mod helper;
+
+#[tokio::test]
+async fn test_requested_units_are_reserved_until_released() {
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        Keys::generate(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            5,
+            "Coffee".to_string(),
+            20,
+            2,
+        )])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+
+    vm.request_items(5, 2).await.unwrap();
+    assert_eq!(vm.available(5), 0);
+    assert_eq!(vm.get_item(5).unwrap().count, 2);
+
+    // Nothing left to add to the cart
+    assert!(vm.request_item(5).await.is_err());
+    assert_eq!(vm.amount_due(), Some(40));
+
+    // A refused command keeps the cart, whose units are given back on cancel
+    assert!(vm.dispense_item().await.is_err());
+    assert_eq!(vm.amount_due(), Some(40));
+    assert_eq!(vm.available(5), 0);
+
+    vm.cancel().await.unwrap();
+    assert_eq!(vm.available(5), 2);
+
+    vm.request_item(5).await.unwrap();
+    vm.insert_money(20).await.unwrap();
+    vm.dispense_item().await.unwrap();
+    assert_eq!(vm.available(5), 1);
+    assert_eq!(vm.get_item(5).unwrap().count, 1);
+}
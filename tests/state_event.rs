@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use helper::{setup_local_relay_client, LOCAL_RELAY_URL};
+use nostr_sdk::Keys;
+use tokio::sync::mpsc;
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::storage::MemoryStore;
+use vending_machines_nostr::vending_machine::{
+    state_filter, Item, VendingMachine, VendingMachineUpdate, STATE_SCHEMA_VERSION,
+};
+mod helper;
+
+#[tokio::test]
+async fn test_relay_keeps_only_latest_state() {
+    let keys = Keys::generate();
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let mut vm = VendingMachine::new(
+        keys.clone(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(MemoryStore::with_items(vec![Item::new(
+            1,
+            "Chocolate".to_string(),
+            13,
+            2,
+        )])),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    vm.send_update().await.unwrap();
+    // Replaceable events are ordered by their timestamp in seconds
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    vm.request_item(1).await.unwrap();
+
+    let client = setup_local_relay_client(Keys::generate()).await;
+    let events = client
+        .fetch_events(state_filter(keys.public_key()), Duration::from_secs(2))
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+
+    let update = VendingMachineUpdate::parse(&events.first().unwrap().content).unwrap();
+    assert_eq!(update.version, STATE_SCHEMA_VERSION);
+    assert_eq!(update.state, "ItemRequestedState");
+    assert_eq!(update.items.len(), 1);
+
+    assert!(
+        VendingMachineUpdate::parse(&events.first().unwrap().content.replace(
+            &format!("\"version\":{}", STATE_SCHEMA_VERSION),
+            "\"version\":99"
+        ))
+        .is_err()
+    );
+
+    client.disconnect().await;
+}
@@ -0,0 +1,41 @@
+use nostr_sdk::Keys;
+use tokio::sync::mpsc;
+use vending_machines_nostr::journal::MemoryJournal;
+use vending_machines_nostr::storage::{InventoryStore, JsonFileStore};
+use vending_machines_nostr::vending_machine::{Item, VendingMachine};
+
+use helper::LOCAL_RELAY_URL;
+mod helper;
+
+#[tokio::test]
+async fn test_inventory_restored_on_new() {
+    let path = std::env::temp_dir().join(format!(
+        "vm-restore-{}.json",
+        Keys::generate().public_key().to_hex()
+    ));
+    JsonFileStore::new(&path)
+        .save(&[Item::new(7, "Soda".to_string(), 120, 9)])
+        .unwrap();
+
+    let (_admin_tx, admin_rx) = mpsc::channel(10);
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    let vm = VendingMachine::new(
+        Keys::generate(),
+        &[LOCAL_RELAY_URL],
+        admin_rx,
+        shutdown_rx,
+        Box::new(JsonFileStore::new(&path)),
+        Box::new(MemoryJournal::default()),
+    )
+    .await
+    .unwrap();
+
+    let item = vm
+        .get_item(7)
+        .expect("item should be restored from storage");
+    assert_eq!(item.name, "Soda");
+    assert_eq!(item.price, 120);
+    assert_eq!(item.count, 9);
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -17,4 +17,9 @@ export const COMMAND_TYPES = {
   END_ADMIN: "End"
 };
 
+// Addressable event (NIP-78) holding the latest machine state
+export const STATE_EVENT_KIND = 30078;
+export const STATE_EVENT_IDENTIFIER = "vending_machine_state";
+export const STATE_SCHEMA_VERSION = 1;
+
 export const SIMULATION_MODE = false; // Set to true to enable simulation mode
\ No newline at end of file
@@ -1,6 +1,7 @@
 // src/services/nostrService.js
 import { SimplePool, nip19, getPublicKey, finalizeEvent } from 'nostr-tools';
-import { encrypt, getConversationKey} from 'nostr-tools/nip44';
+import { encrypt, decrypt, getConversationKey} from 'nostr-tools/nip44';
+import { STATE_EVENT_KIND, STATE_EVENT_IDENTIFIER, STATE_SCHEMA_VERSION } from '../config';
 
 // Set up relays
 const RELAYS = [
@@ -10,6 +11,42 @@ const RELAYS = [
 // Initialize a single relay pool for sending messages
 const pool = new SimplePool();
 
+// How long to wait for the machine to acknowledge a command
+const RESPONSE_TIMEOUT_MS = 15000;
+
+/**
+ * Waits for the machine's reply DM to the command with `id`
+ *
+ * @returns {Promise<object>} `{ id, status: "ok" | "error" | "pending", error? }`
+ */
+function waitForResponse(conversationKey, adminPubKey, machinePubKey, id) {
+  return new Promise((resolve, reject) => {
+    const filter = {
+      kinds: [4],
+      authors: [machinePubKey],
+      '#p': [adminPubKey],
+      since: Math.floor(Date.now() / 1000) - 5,
+    };
+    const timer = setTimeout(() => {
+      sub.close();
+      reject(new Error('the machine did not answer'));
+    }, RESPONSE_TIMEOUT_MS);
+    const sub = pool.subscribeMany(RELAYS, [filter], {
+      onevent(event) {
+        try {
+          const response = JSON.parse(decrypt(event.content, conversationKey));
+          if (response.id !== id) return;
+          clearTimeout(timer);
+          sub.close();
+          resolve(response);
+        } catch (error) {
+          // Not a reply to a command
+        }
+      }
+    });
+  });
+}
+
 const nostrService = {
   /**
    * Sends an administrative command to a vending machine over Nostr
@@ -57,14 +94,16 @@ const nostrService = {
         return { success: false, message: `Invalid private key: ${err.message}` };
       }
       
-      // Stringify the command
-      const commandStr = JSON.stringify(command);
+      // The machine echoes the id in its reply
+      const id = crypto.randomUUID();
+      const commandStr = JSON.stringify({ id, ...command });
       console.log("Command JSON:", commandStr);
       
       // Encrypt using NIP-44 with explicit key preparation
       let encryptedContent;
+      let conversationKey;
       try {
-          const conversationKey = getConversationKey(
+          conversationKey = getConversationKey(
             secretKey,
             pubKey
           );
@@ -87,15 +126,36 @@ const nostrService = {
       // In nostr-tools 2.x, finalizeEvent is the recommended way to create a signed event
       const signedEvent = finalizeEvent(event, secretKey);
       
-      // Publish to relays
-      const pubs = pool.publish(RELAYS, signedEvent);
-      
-      // Since pubs is already resolved and the command works, 
-      // we don't need to wait for explicit 'ok' responses
+      // Listen for the reply before publishing so it cannot be missed
+      const response = waitForResponse(conversationKey, adminPubKey, pubKey, id);
+      await Promise.any(pool.publish(RELAYS, signedEvent));
       console.log("Event published to relays:", signedEvent.id);
+
+      let reply;
+      try {
+        reply = await response;
+      } catch (err) {
+        return { success: false, message: `Command ${command.type}: ${err.message}`, event: signedEvent };
+      }
+      if (reply.status === 'pending') {
+        // Staged until other admins send `{ type: "Approve", data: result.data.command_id }`
+        const pending = reply.result.data;
+        return {
+          success: true,
+          pending: true,
+          message: `Command ${command.type} waits for approval (${pending.approvals}/${pending.required})`,
+          result: reply.result,
+          event: signedEvent
+        };
+      }
+      if (reply.status !== 'ok') {
+        return { success: false, message: `Command ${command.type} failed: ${reply.error}`, event: signedEvent };
+      }
       return { 
         success: true, 
-        message: `Command ${command.type} sent successfully`,
+        message: `Command ${command.type} succeeded`,
+        // Typed result, e.g. `{ type: "Status", data: { state, items, uptime_secs, ... } }`
+        result: reply.result,
         event: signedEvent
       };
 
@@ -145,11 +205,12 @@ const nostrService = {
     }
     
     console.log(`Subscribing to updates from ${pubKey}`);
-    // Create a subscription to listen for events from the target machine
+    // Relays keep only the latest state event, so this returns the current state and then
+    // every update
     const filter = {
-      kinds: [1], // Public note kind
+      kinds: [STATE_EVENT_KIND],
       authors: [pubKey],
-      // Remove time restriction to catch all updates
+      '#d': [STATE_EVENT_IDENTIFIER],
     };
 
     console.log('Setting up subscription with filter:', filter);
@@ -158,6 +219,11 @@ const nostrService = {
       onevent(event) {
         try {
           console.log('Received event:', event);
+          const { version } = JSON.parse(event.content);
+          if (version !== STATE_SCHEMA_VERSION) {
+            console.warn(`Ignoring state with unsupported schema version ${version}`);
+            return;
+          }
           // Parse the content directly since it's not encrypted
           callback(event);
         } catch (error) {